@@ -0,0 +1,34 @@
+.set noreorder
+
+.text
+.global __start
+__start:
+    # clear Status.ERL, keep the boot exception vectors
+    lui   $t0, 0x0040
+    mtc0  $t0, $12
+    li    $t1, 0
+    break
+    addiu $t1, $t1, 1
+    # integer overflow leaves the destination register unmodified
+    lui   $t2, 0x7fff
+    ori   $t2, $t2, 0xffff
+    li    $t3, 563
+    addi  $t3, $t2, 1
+    # misaligned halfword load raises an address error
+    li    $t4, 0x80000001
+    lh    $t5, 0($t4)
+    # conditional trap
+    teqi  $t1, 1
+    # system call
+    syscall
+    jr    $ra
+    nop
+
+# general exception vector (Status.BEV = 1)
+.org 0x380
+handler:
+    # skip the faulting instruction
+    mfc0  $k0, $14
+    addiu $k0, $k0, 4
+    mtc0  $k0, $14
+    eret
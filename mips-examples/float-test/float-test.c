@@ -0,0 +1,106 @@
+#define assert(x) \
+    do { \
+        if (!(x)) return 1; \
+    } while (0)
+
+static inline void set_rounding_mode(int rm) {
+    asm volatile("ctc1 %0,$31" :: "r"(rm));
+}
+
+static inline int get_fcsr(void) {
+    int fcsr;
+    asm volatile("cfc1 %0,$31" : "=r"(fcsr));
+    return fcsr;
+}
+
+int main() {
+    // add.s, sub.s, mul.s, div.s
+    {
+        volatile float x = 1.5f, y = 2.25f;
+        assert(x + y == 3.75f);
+        assert(x - y == -0.75f);
+        assert(x * y == 3.375f);
+        assert(y / x == 1.5f);
+    }
+
+    // add.d, sub.d, mul.d, div.d
+    {
+        volatile double x = 1.5, y = 2.25;
+        assert(x + y == 3.75);
+        assert(x - y == -0.75);
+        assert(x * y == 3.375);
+        assert(y / x == 1.5);
+    }
+
+    // sqrt.s, sqrt.d
+    {
+        volatile float x = 2.25f, y;
+        volatile double z = 2.25, w;
+        asm volatile("sqrt.s %0,%1" : "=f"(y) : "f"(x));
+        asm volatile("sqrt.d %0,%1" : "=f"(w) : "f"(z));
+        assert(y == 1.5f);
+        assert(w == 1.5);
+    }
+
+    // abs.s, neg.d
+    {
+        volatile float x = -1.5f, y;
+        volatile double z = 1.5, w;
+        asm volatile("abs.s %0,%1" : "=f"(y) : "f"(x));
+        asm volatile("neg.d %0,%1" : "=f"(w) : "f"(z));
+        assert(y == 1.5f);
+        assert(w == -1.5);
+    }
+
+    // cvt.s.d, cvt.d.s, cvt.s.w, cvt.d.w, trunc.w.s, trunc.w.d
+    {
+        volatile int i = -7;
+        volatile float x = 2.75f;
+        volatile double y = -2.75;
+        assert((float)y == -2.75f);
+        assert((double)x == 2.75);
+        assert((float)i == -7.0f);
+        assert((double)i == -7.0);
+        assert((int)x == 2);
+        assert((int)y == -2);
+    }
+
+    // c.eq, c.lt, c.le, bc1t, bc1f
+    {
+        volatile float x = 1.0f, y = 2.0f;
+        volatile double z = 1.0, w = 2.0;
+        assert(x < y);
+        assert(!(y < x));
+        assert(x <= x);
+        assert(x != y);
+        assert(z < w);
+        assert(!(w <= z));
+    }
+
+    // rounding modes
+    {
+        volatile float x = 1.0f, y = 3.0f, z;
+        set_rounding_mode(1);
+        z = x / y;
+        set_rounding_mode(0);
+        assert(*(volatile int *)&z == 0x3eaaaaaa);
+        z = x / y;
+        assert(*(volatile int *)&z == 0x3eaaaaab);
+        set_rounding_mode(2);
+        z = -x / y;
+        set_rounding_mode(0);
+        assert(*(volatile int *)&z == 0xbeaaaaaa);
+    }
+
+    // flags
+    {
+        volatile double x = 1.0, y = 0.0, z;
+        set_rounding_mode(0);
+        z = x / y;
+        // divide by zero in Cause and Flags
+        assert(get_fcsr() & (1 << 15));
+        assert(get_fcsr() & (1 << 5));
+    }
+
+    return 0;
+}
@@ -0,0 +1,14 @@
+.extern main
+
+.equ STACKTOP, 0x80000000 + 0x8000000
+
+.text
+.global __start
+__start:
+    # enable coprocessor 1
+    mfc0 $t0, $12
+    lui  $t1, 0x2000
+    or   $t0, $t0, $t1
+    mtc0 $t0, $12
+    li  $sp, STACKTOP
+    j   main
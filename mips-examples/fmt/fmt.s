@@ -0,0 +1,20 @@
+.set noreorder
+
+.text
+.global __start
+__start:
+    # Status.ERL is set at reset, kuseg is unmapped: physical 0x1000
+    li    $t0, 0x1000
+    li    $t1, 0x11
+    sw    $t1, 0($t0)
+    # clear Status.ERL, kuseg is mapped to physical 0x40001000
+    lui   $t2, 0x0040
+    mtc0  $t2, $12
+    li    $t3, 0x22
+    sw    $t3, 0($t0)
+    lw    $t4, 0($t0)
+    # the word at physical 0x1000 through kseg0 is unchanged
+    lui   $t5, 0x8000
+    lw    $t6, 0x1000($t5)
+    jr    $ra
+    nop
@@ -12,6 +12,20 @@ int main() {
         assert((x + y == z));
     }
 
+    // add
+    {
+        int x = 47, y = -5, z;
+        asm volatile("add %0,%1,%2" : "=r"(z) : "r"(x), "r"(y):);
+        assert(z == 42);
+    }
+
+    // addi
+    {
+        int x = 560, y;
+        asm volatile("addi %0,%1,-3" : "=r"(y) : "r"(x):);
+        assert(y == 557);
+    }
+
     // addiu
     {
         int x = 560, y = 563;
@@ -71,6 +85,13 @@ int main() {
         assert((x - y == z));
     }
 
+    // sub
+    {
+        int x = -47, y = 5, z;
+        asm volatile("sub %0,%1,%2" : "=r"(z) : "r"(x), "r"(y):);
+        assert(z == -52);
+    }
+
     // sll
     {
         int x = 1 << 10, y = 1 << 12;
@@ -209,6 +230,148 @@ int main() {
         assert(y == 0);
     }
 
+    // lb
+    {
+        int x = 0x000000fb, y;
+        asm volatile("lb %0,0(%1)" : "=r"(y) : "r"(&x):);
+        assert(y == -5);
+    }
+
+    // lbu
+    {
+        int x = 0x000000fb, y;
+        asm volatile("lbu %0,0(%1)" : "=r"(y) : "r"(&x):);
+        assert(y == 0xfb);
+    }
+
+    // lh
+    {
+        int x = 0xfffb0000, y;
+        asm volatile("lh %0,2(%1)" : "=r"(y) : "r"(&x):);
+        assert(y == -5);
+    }
+
+    // lhu
+    {
+        int x = 0xfffb0000, y;
+        asm volatile("lhu %0,2(%1)" : "=r"(y) : "r"(&x):);
+        assert(y == 0xfffb);
+    }
+
+    // sb
+    {
+        int x = 0x11111111, y = 0x11221111;
+        asm volatile("sb %1,2(%0)" :: "r"(&x), "r"(0x22) : "memory");
+        assert(x == y);
+    }
+
+    // sh
+    {
+        int x = 0x11111111, y = 0x11112233;
+        asm volatile("sh %1,0(%0)" :: "r"(&x), "r"(0x2233) : "memory");
+        assert(x == y);
+    }
+
+    // lwl, lwr
+    {
+        unsigned char buf[8] = {0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88};
+        int x;
+        asm volatile("lwl %0,4(%1)\n\tlwr %0,1(%1)" : "=&r"(x) : "r"(buf) : "memory");
+        assert(x == 0x55443322);
+    }
+
+    // swl, swr
+    {
+        unsigned char buf[8] = {0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88};
+        int x = 0xaabbccdd;
+        asm volatile("swl %1,5(%0)\n\tswr %1,2(%0)" :: "r"(buf), "r"(x) : "memory");
+        assert(buf[1] == 0x22 && buf[2] == 0xdd && buf[3] == 0xcc);
+        assert(buf[4] == 0xbb && buf[5] == 0xaa && buf[6] == 0x77);
+    }
+
+    // ll, sc
+    {
+        int x = 41, y, z;
+        asm volatile("ll %0,0(%2)\n\taddiu %1,%0,1\n\tsc %1,0(%2)" : "=&r"(y), "=&r"(z) : "r"(&x) : "memory");
+        assert(z == 1 && x == 42);
+
+        // a store to the linked word makes sc fail
+        asm volatile("ll %0,0(%2)\n\tsw $0,0(%2)\n\tsc %1,0(%2)" : "=&r"(y), "+r"(z) : "r"(&x) : "memory");
+        assert(z == 0 && x == 0);
+    }
+
+    // movn
+    {
+        int x = 1, y = 563, z = 0;
+        asm volatile("movn %0,%1,%2" : "+r"(z) : "r"(y), "r"(x):);
+        assert(z == y);
+        asm volatile("movn %0,$0,$0" : "+r"(z) ::);
+        assert(z == y);
+    }
+
+    // movz
+    {
+        int x = 0, y = 563, z = 0;
+        asm volatile("movz %0,%1,%2" : "+r"(z) : "r"(y), "r"(x):);
+        assert(z == y);
+        asm volatile("movz %0,$0,%1" : "+r"(z) : "r"(y):);
+        assert(z == y);
+    }
+
+    // teq, tne, tge, tgeu, tlt, tltu (not taken)
+    {
+        int x = -1, y = 1;
+        asm volatile("teq %0,%1" :: "r"(x), "r"(y):);
+        asm volatile("tne %0,%0" :: "r"(x):);
+        asm volatile("tge %0,%1" :: "r"(x), "r"(y):);
+        asm volatile("tgeu %1,%0" :: "r"(x), "r"(y):);
+        asm volatile("tlt %1,%0" :: "r"(x), "r"(y):);
+        asm volatile("tltu %0,%1" :: "r"(x), "r"(y):);
+    }
+
+    // teqi, tnei, tgei, tgeiu, tlti, tltiu (not taken)
+    {
+        int x = -1;
+        asm volatile("teqi %0,1" :: "r"(x):);
+        asm volatile("tnei %0,-1" :: "r"(x):);
+        asm volatile("tgei %0,0" :: "r"(x):);
+        asm volatile("tgeiu $0,1" :::);
+        asm volatile("tlti %0,-1" :: "r"(x):);
+        asm volatile("tltiu %0,-1" :: "r"(x):);
+    }
+
+    // beql, bnel
+    {
+        int x = 1, y = 0;
+        // not taken, the delay slot is nullified
+        asm volatile(".set noreorder\n\tbeql %1,$0,1f\n\taddiu %0,%0,1\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        assert(y == 0);
+        // taken, the delay slot is executed
+        asm volatile(".set noreorder\n\tbnel %1,$0,1f\n\taddiu %0,%0,1\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        assert(y == 1);
+    }
+
+    // blezl, bgtzl, bltzl, bgezl
+    {
+        int x = -1, y = 0;
+        asm volatile(".set noreorder\n\tblezl %1,1f\n\taddiu %0,%0,1\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        asm volatile(".set noreorder\n\tbgtzl %1,1f\n\taddiu %0,%0,2\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        asm volatile(".set noreorder\n\tbltzl %1,1f\n\taddiu %0,%0,4\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        asm volatile(".set noreorder\n\tbgezl %1,1f\n\taddiu %0,%0,8\n1:\n\t.set reorder" : "+r"(y) : "r"(x):);
+        assert(y == 5);
+    }
+
+    // bltzall, bgezall
+    {
+        int x = 1, y = 0;
+        void *ra;
+        // the return address is written even when not taken
+        asm volatile(".set noreorder\n\tbltzall %2,1f\n\taddiu %0,%0,1\n1:\n\tmove %1,$ra\n\t.set reorder" : "+r"(y), "=r"(ra) : "r"(x) : "ra");
+        assert(y == 0 && ra != 0);
+        asm volatile(".set noreorder\n\tbgezall %1,1f\n\taddiu %0,%0,1\n1:\n\t.set reorder" : "+r"(y) : "r"(x) : "ra");
+        assert(y == 1);
+    }
+
     // div
     {
         int x = -65, y = 8, z, w;
@@ -0,0 +1,29 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <sys/utsname.h>
+
+int main(int argc, char *argv[]) {
+    struct utsname name;
+    struct timespec ts;
+
+    printf("hello, world\n");
+    for (int i = 0; i < argc; i++) {
+        printf("argv[%d] = %s\n", i, argv[i]);
+    }
+
+    if (uname(&name) == 0) {
+        printf("%s %s %s\n", name.sysname, name.release, name.machine);
+    }
+    if (clock_gettime(CLOCK_MONOTONIC, &ts) == 0) {
+        printf("monotonic clock: %ld.%09ld\n", (long)ts.tv_sec, ts.tv_nsec);
+    }
+
+    char *buf = malloc(64);
+    strcpy(buf, "heap works");
+    printf("%s, %.3f\n", buf, 1.0 / 3.0);
+    free(buf);
+
+    return argc - 1;
+}
@@ -0,0 +1,74 @@
+#define assert(x) \
+    do { \
+        if (!(x)) return 1; \
+    } while (0)
+
+int main() {
+    // ext
+    {
+        int x = 0x123456f8, y;
+        asm volatile("ext %0,%1,4,8" : "=r"(y) : "r"(x):);
+        assert(y == 0x6f);
+    }
+
+    // ins
+    {
+        int x = 0x123456f8, y = 0xffff0000;
+        asm volatile("ins %0,%1,8,8" : "+r"(y) : "r"(x):);
+        assert(y == 0xfffff800);
+    }
+
+    // seb
+    {
+        int x = 0x123456f8, y;
+        asm volatile("seb %0,%1" : "=r"(y) : "r"(x):);
+        assert(y == -8);
+    }
+
+    // seh
+    {
+        int x = 0x1234f6f8, y;
+        asm volatile("seh %0,%1" : "=r"(y) : "r"(x):);
+        assert(y == (short)0xf6f8);
+    }
+
+    // wsbh
+    {
+        int x = 0x123456f8, y;
+        asm volatile("wsbh %0,%1" : "=r"(y) : "r"(x):);
+        assert(y == 0x3412f856);
+    }
+
+    // rotr
+    {
+        unsigned int x = 0x123456f8, y;
+        asm volatile("rotr %0,%1,8" : "=r"(y) : "r"(x):);
+        assert(y == 0xf8123456);
+    }
+
+    // rotrv
+    {
+        unsigned int x = 0x123456f8, y = 24, z;
+        asm volatile("rotrv %0,%1,%2" : "=r"(z) : "r"(x), "r"(y):);
+        assert(z == 0x3456f812);
+    }
+
+    // di, ei
+    {
+        int x, y;
+        asm volatile("ei %0" : "=r"(x) ::);
+        asm volatile("di %0" : "=r"(y) ::);
+        assert((x & 1) == 0);
+        assert((y & 1) == 1);
+    }
+
+    // rdhwr
+    {
+        int x, y;
+        asm volatile("rdhwr %0,$2" : "=r"(x) ::);
+        asm volatile("rdhwr %0,$2" : "=r"(y) ::);
+        assert(y - x > 0);
+    }
+
+    return 0;
+}
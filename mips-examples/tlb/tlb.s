@@ -0,0 +1,76 @@
+.set noreorder
+.set noat
+
+.text
+.global __start
+__start:
+    # clear Status.ERL so that kuseg is mapped, keep the boot exception vectors
+    lui   $t0, 0x0040
+    mtc0  $t0, $12
+    # the refill handler maps kuseg to physical 0x00100000 + virtual / 2,
+    # clean and valid: the first store to a page raises TLB Modified
+    lui   $t1, 0x0040
+    li    $t2, 0x1234
+    sw    $t2, 0($t1)
+    lw    $t3, 0($t1)
+    # the same word through kseg0: 0x80000000 + 0x00100000 + 0x00400000 / 2
+    lui   $t4, 0x8030
+    lw    $t5, 0($t4)
+    # kseg2 pages are mapped invalid, the load is skipped
+    lui   $t6, 0xc000
+    lw    $t7, 0($t6)
+    # tlbp finds the entry of the kuseg page
+    mtc0  $t1, $10
+    tlbp
+    mfc0  $s2, $0
+    jr    $ra
+    nop
+
+# TLB refill vector (Status.BEV = 1)
+.org 0x200
+refill:
+    addiu $s0, $s0, 1
+    # PFN of the even page: 0x100 + VPN2, from Context.BadVPN2
+    mfc0  $k0, $4
+    srl   $k0, $k0, 4
+    addiu $k0, $k0, 0x100
+    sll   $k0, $k0, 6
+    # kseg2 and kseg3: invalid
+    mfc0  $k1, $10
+    bltz  $k1, 1f
+    nop
+    ori   $k0, $k0, 0x2
+1:
+    mtc0  $k0, $2
+    addiu $k0, $k0, 0x40
+    mtc0  $k0, $3
+    mtc0  $zero, $5
+    tlbwr
+    eret
+
+# general exception vector (Status.BEV = 1)
+.org 0x380
+handler:
+    addiu $s1, $s1, 1
+    mfc0  $k0, $13
+    andi  $k0, $k0, 0x7c
+    li    $k1, 0x4
+    bne   $k0, $k1, skip
+    nop
+    # TLB Modified: mark both pages dirty and retry the store
+    tlbp
+    tlbr
+    mfc0  $k0, $2
+    ori   $k0, $k0, 0x4
+    mtc0  $k0, $2
+    mfc0  $k0, $3
+    ori   $k0, $k0, 0x4
+    mtc0  $k0, $3
+    tlbwi
+    eret
+skip:
+    # skip the faulting instruction
+    mfc0  $k0, $14
+    addiu $k0, $k0, 4
+    mtc0  $k0, $14
+    eret
@@ -0,0 +1,52 @@
+.set noreorder
+
+.text
+.global __start
+__start:
+    # Status: boot exception vectors, user mode once EXL is cleared, ERL clear
+    lui   $t0, 0x0040
+    ori   $t0, $t0, 0x0012
+    mtc0  $t0, $12
+    # eret to user code, the boot ROM seen through kuseg without an MMU
+    lui   $t1, 0x1fc0
+    ori   $t1, $t1, 0x0100
+    mtc0  $t1, $14
+    eret
+    nop
+kernel:
+    # back in kernel mode after the system call
+    jr    $ra
+    nop
+
+.org 0x100
+user:
+    # kseg0 is a kernel segment: address error on load and store
+    lui   $t2, 0x8000
+    lw    $t3, 0($t2)
+    sw    $t3, 0($t2)
+    # CP0 needs kernel mode or Status.CU0: coprocessor unusable
+    mfc0  $t4, $12
+    syscall
+
+# general exception vector (Status.BEV = 1)
+.org 0x380
+handler:
+    addiu $s0, $s0, 1
+    # ExcCode of the exception taken last
+    mfc0  $s1, $13
+    andi  $s1, $s1, 0x7c
+    srl   $s1, $s1, 2
+    li    $k0, 8
+    beq   $s1, $k0, syscall
+    nop
+    # skip the faulting instruction
+    mfc0  $k0, $14
+    addiu $k0, $k0, 4
+    mtc0  $k0, $14
+    eret
+syscall:
+    # stay in kernel mode with EXL set
+    lui   $k0, 0xbfc0
+    ori   $k0, $k0, %lo(kernel)
+    jr    $k0
+    nop
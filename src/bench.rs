@@ -0,0 +1,96 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::cpu::*;
+use crate::elf::*;
+
+fn load(binary: &[u8]) -> io::Result<Cpu> {
+    let mut cpu = if Elf::is_elf(binary) {
+        let elf = Elf::parse(binary.to_vec())?;
+        let mut cpu = Cpu::new(vec![], IsaRevision::Release2);
+        elf.load(&mut cpu)?;
+        cpu
+    } else {
+        Cpu::new(binary.to_vec(), IsaRevision::Release2)
+    };
+    cpu.trace = false;
+    Ok(cpu)
+}
+
+// The fetch/execute loop without the decoded-instruction cache: every step
+// loads the word from the bus and decodes it again
+fn run_uncached(cpu: &mut Cpu) {
+    while let Ok(inst) = cpu.fetch() {
+        cpu.pc += 4;
+        if cpu.execute(inst).is_err() || cpu.pc == 0 {
+            break;
+        }
+    }
+}
+
+// The fetch/execute loop of main
+fn run_cached(cpu: &mut Cpu) {
+    while let Ok(decoded) = cpu.fetch_decoded() {
+        cpu.pc += 4;
+        if cpu.execute_decoded(decoded).is_err() || cpu.pc == 0 {
+            break;
+        }
+    }
+}
+
+// The fetch/execute loop of main with --blocks
+fn run_blocks(cpu: &mut Cpu) {
+    while let Ok(block) = cpu.fetch_block() {
+        if cpu.execute_block(&block).is_err() || cpu.pc == 0 {
+            break;
+        }
+    }
+}
+
+// The loop of main with --jit
+#[cfg(feature = "jit")]
+fn run_jit(cpu: &mut Cpu) {
+    let _ = crate::jit::run(cpu).unwrap();
+}
+
+fn measure(binary: &[u8], run: fn(&mut Cpu)) -> io::Result<(Duration, Cpu)> {
+    let mut cpu = load(binary)?;
+    let start = Instant::now();
+    run(&mut cpu);
+    Ok((start.elapsed(), cpu))
+}
+
+// Run a bare-metal program without tracing with the plain loop, through the
+// decoded-instruction cache and block by block, and compare them
+pub fn run(binary: Vec<u8>) -> io::Result<()> {
+    let (uncached, uncached_cpu) = measure(&binary, run_uncached)?;
+    // Count increments every instruction unless the program writes it
+    let count = uncached_cpu.cp0.count as f64;
+    println!("{} instructions", uncached_cpu.cp0.count);
+    println!(
+        "{:<10} {:>10.3}s {:>10.2} MIPS",
+        "uncached",
+        uncached.as_secs_f64(),
+        count / uncached.as_secs_f64() / 1e6
+    );
+    let runs = [
+        ("cached", run_cached as fn(&mut Cpu)),
+        ("blocks", run_blocks),
+        #[cfg(feature = "jit")]
+        ("jit", run_jit),
+    ];
+    for (name, run) in runs.iter() {
+        let (elapsed, cpu) = measure(&binary, *run)?;
+        if cpu.regs != uncached_cpu.regs || cpu.pc != uncached_cpu.pc || cpu.cp0.count != uncached_cpu.cp0.count {
+            return Err(io::Error::other(format!("the {} run ended in a different state", name)));
+        }
+        println!(
+            "{:<10} {:>10.3}s {:>10.2} MIPS {:>6.2}x",
+            name,
+            elapsed.as_secs_f64(),
+            count / elapsed.as_secs_f64() / 1e6,
+            uncached.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+    Ok(())
+}
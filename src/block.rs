@@ -0,0 +1,159 @@
+use crate::cpu::*;
+use crate::decode::*;
+use crate::decode_cache::*;
+use crate::error::*;
+
+// An instruction of a block with its operands bound, run by execute_block
+pub type Handler = Box<dyn Fn(&mut Cpu) -> Result<(), CpuError>>;
+
+// Instructions from a block start up to the first branch and its delay
+// slot, a CP0 write that may remap pc, an undecodable word, the end of the
+// page or MAX_BLOCK_LEN, translated once into a handler each
+pub struct Block {
+    // for the jit, which compiles blocks from their decoded instructions
+    #[cfg(feature = "jit")]
+    pub decoded: Box<[Decoded]>,
+    pub handlers: Box<[Handler]>,
+}
+
+impl Block {
+    pub fn translate(decoded: &[Decoded]) -> Self {
+        Self {
+            #[cfg(feature = "jit")]
+            decoded: decoded.into(),
+            handlers: decoded.iter().map(|decoded| handler(*decoded)).collect(),
+        }
+    }
+}
+
+// Register operations and conditional branches get a handler of their own,
+// every other instruction, and any that can raise an exception, goes through
+// execute_decoded
+fn handler(decoded: Decoded) -> Handler {
+    let instruction = match decoded {
+        Ok(instruction) => instruction,
+        Err(_) => return Box::new(move |cpu| cpu.execute_decoded(decoded)),
+    };
+    match instruction {
+        Instruction::Sll { rd, rt, sa } => register(instruction, move |regs| regs[rd] = regs[rt].wrapping_shl(sa)),
+        Instruction::Srl { rd, rt, sa } => register(instruction, move |regs| regs[rd] = regs[rt].wrapping_shr(sa)),
+        Instruction::Sra { rd, rt, sa } => {
+            register(instruction, move |regs| regs[rd] = (regs[rt] as i32).wrapping_shr(sa) as u32)
+        }
+        Instruction::Sllv { rd, rt, rs } => {
+            register(instruction, move |regs| regs[rd] = regs[rt].wrapping_shl(regs[rs]))
+        }
+        Instruction::Srlv { rd, rt, rs } => {
+            register(instruction, move |regs| regs[rd] = regs[rt].wrapping_shr(regs[rs]))
+        }
+        Instruction::Srav { rd, rt, rs } => {
+            register(instruction, move |regs| regs[rd] = (regs[rt] as i32).wrapping_shr(regs[rs]) as u32)
+        }
+        Instruction::Movz { rd, rs, rt } => register(instruction, move |regs| {
+            if regs[rt] == 0 {
+                regs[rd] = regs[rs];
+            }
+        }),
+        Instruction::Movn { rd, rs, rt } => register(instruction, move |regs| {
+            if regs[rt] != 0 {
+                regs[rd] = regs[rs];
+            }
+        }),
+        Instruction::Addu { rd, rs, rt } => {
+            register(instruction, move |regs| regs[rd] = regs[rs].wrapping_add(regs[rt]))
+        }
+        Instruction::Subu { rd, rs, rt } => {
+            register(instruction, move |regs| regs[rd] = regs[rs].wrapping_sub(regs[rt]))
+        }
+        Instruction::And { rd, rs, rt } => register(instruction, move |regs| regs[rd] = regs[rs] & regs[rt]),
+        Instruction::Or { rd, rs, rt } => register(instruction, move |regs| regs[rd] = regs[rs] | regs[rt]),
+        Instruction::Xor { rd, rs, rt } => register(instruction, move |regs| regs[rd] = regs[rs] ^ regs[rt]),
+        Instruction::Nor { rd, rs, rt } => register(instruction, move |regs| regs[rd] = !(regs[rs] | regs[rt])),
+        Instruction::Slt { rd, rs, rt } => {
+            register(instruction, move |regs| regs[rd] = ((regs[rs] as i32) < (regs[rt] as i32)) as u32)
+        }
+        Instruction::Sltu { rd, rs, rt } => register(instruction, move |regs| regs[rd] = (regs[rs] < regs[rt]) as u32),
+        Instruction::Addiu { rt, rs, imm } => register(instruction, move |regs| regs[rt] = regs[rs].wrapping_add(imm)),
+        Instruction::Slti { rt, rs, imm } => {
+            register(instruction, move |regs| regs[rt] = ((regs[rs] as i32) < (imm as i32)) as u32)
+        }
+        Instruction::Sltiu { rt, rs, imm } => register(instruction, move |regs| regs[rt] = (regs[rs] < imm) as u32),
+        Instruction::Andi { rt, rs, imm } => register(instruction, move |regs| regs[rt] = regs[rs] & imm),
+        Instruction::Ori { rt, rs, imm } => register(instruction, move |regs| regs[rt] = regs[rs] | imm),
+        Instruction::Xori { rt, rs, imm } => register(instruction, move |regs| regs[rt] = regs[rs] ^ imm),
+        Instruction::Lui { rt, imm } => register(instruction, move |regs| regs[rt] = imm << 16),
+        Instruction::Mul { rd, rs, rt } => register(instruction, move |regs| {
+            regs[rd] = ((regs[rs] as i64).wrapping_mul(regs[rt] as i64)) as u32
+        }),
+        Instruction::Clz { rd, rs } => register(instruction, move |regs| regs[rd] = regs[rs].leading_zeros()),
+        Instruction::Clo { rd, rs } => register(instruction, move |regs| regs[rd] = regs[rs].leading_ones()),
+        Instruction::Beq { rs, rt, offset } => branch(instruction, offset, move |regs| regs[rs] == regs[rt]),
+        Instruction::Bne { rs, rt, offset } => branch(instruction, offset, move |regs| regs[rs] != regs[rt]),
+        Instruction::Blez { rs, offset } => branch(instruction, offset, move |regs| (regs[rs] as i32) <= 0),
+        Instruction::Bgtz { rs, offset } => branch(instruction, offset, move |regs| (regs[rs] as i32) > 0),
+        Instruction::Bltz { rs, offset } => branch(instruction, offset, move |regs| (regs[rs] as i32) < 0),
+        Instruction::Bgez { rs, offset } => branch(instruction, offset, move |regs| (regs[rs] as i32) >= 0),
+        _ => Box::new(move |cpu| cpu.execute_decoded(decoded)),
+    }
+}
+
+fn register(instruction: Instruction, operation: impl Fn(&mut [u32; 32]) + 'static) -> Handler {
+    Box::new(move |cpu| cpu.execute_register(instruction, &operation))
+}
+
+fn branch(instruction: Instruction, offset: u32, condition: impl Fn(&[u32; 32]) -> bool + 'static) -> Handler {
+    Box::new(move |cpu| cpu.execute_branch(instruction, offset, &condition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r_type(rs: u32, rt: u32, rd: u32, sa: u32, funct: u32) -> u32 {
+        rs << 21 | rt << 16 | rd << 11 | sa << 6 | funct
+    }
+
+    fn i_type(opcode: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+        opcode << 26 | rs << 21 | rt << 16 | (imm & 0xffff)
+    }
+
+    #[test]
+    fn blocks_match_the_interpreter() {
+        // v0 = 10 + 9 + ... + 0 with the decrement in the delay slot, then a
+        // taken branch over an ori whose delay slot still runs
+        let code = [
+            i_type(0x09, 0, 8, 10),
+            i_type(0x09, 0, 2, 0),
+            r_type(2, 8, 2, 0, 0x21),
+            r_type(0, 2, 9, 1, 0x00),
+            r_type(9, 2, 10, 0, 0x2a),
+            i_type(0x05, 8, 0, 0xfffc),
+            i_type(0x09, 8, 8, 0xffff),
+            i_type(0x04, 0, 0, 2),
+            i_type(0x0f, 0, 3, 0x1234),
+            i_type(0x0d, 3, 3, 1),
+            r_type(2, 10, 4, 0, 0x0a),
+            r_type(0, 0, 0, 0, 0x08),
+            0,
+        ];
+        let binary: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+
+        let mut interpreted = Cpu::new(binary.clone(), IsaRevision::Release2);
+        interpreted.trace = false;
+        while interpreted.pc != 0 {
+            let inst = interpreted.fetch().unwrap();
+            interpreted.pc += 4;
+            interpreted.execute(inst).unwrap();
+        }
+        let mut translated = Cpu::new(binary, IsaRevision::Release2);
+        translated.trace = false;
+        while translated.pc != 0 {
+            let block = translated.fetch_block().unwrap();
+            translated.execute_block(&block).unwrap();
+        }
+
+        assert_eq!(interpreted.regs, translated.regs);
+        assert_eq!(interpreted.cp0.count, translated.cp0.count);
+        assert_eq!((translated.regs[2], translated.regs[3], translated.regs[4]), (55, 0x1234_0000, 55));
+    }
+}
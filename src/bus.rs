@@ -1,15 +1,27 @@
+use std::rc::Rc;
+
+use crate::block::*;
+use crate::decode::*;
+use crate::decode_cache::*;
+use crate::error::*;
 use crate::memory::*;
 
 pub const PHY_BOOT_ROM_BASE: u32 = 0x1fc0_0000;
+pub const PHY_MEMORY_BASE: u32 = 0x0000_0000;
+// where the fixed mapping MMU puts kuseg
+pub const PHY_USER_MEMORY_BASE: u32 = 0x4000_0000;
 
 pub trait Device {
-    fn load(&self, addr: u32, size: u32) -> Result<u32, ()>;
-    fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), ()>;
+    // addr is the offset in the device
+    fn load(&self, addr: u32, size: u32) -> Result<u32, BusError>;
+    fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), BusError>;
 }
 
 pub struct Bus {
     memory: Dram,
     boot_rom: Rom,
+    user_memory: Dram,
+    decode_cache: DecodeCache,
 }
 
 impl Bus {
@@ -17,22 +29,132 @@ impl Bus {
         Self {
             memory: Dram::new(vec![], MEMORY_SIZE),
             boot_rom: Rom::new(binary, BOOT_ROM_SIZE),
+            user_memory: Dram::new(vec![], USER_MEMORY_SIZE),
+            decode_cache: DecodeCache::new(),
         }
     }
 
-    pub fn load(&self, addr: u32, size: u32) -> Result<u32, ()> {
-        if PHY_BOOT_ROM_BASE <= addr && addr < PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE {
-            return self.boot_rom.load(addr - PHY_BOOT_ROM_BASE, size);
+    pub fn load(&self, addr: u32, size: u32) -> Result<u32, BusError> {
+        if (PHY_BOOT_ROM_BASE..PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE).contains(&addr) {
+            self.boot_rom.load(addr - PHY_BOOT_ROM_BASE, size).map_err(|error| error.offset(PHY_BOOT_ROM_BASE))
+        } else if (PHY_MEMORY_BASE..PHY_MEMORY_BASE + MEMORY_SIZE).contains(&addr) {
+            self.memory.load(addr - PHY_MEMORY_BASE, size).map_err(|error| error.offset(PHY_MEMORY_BASE))
+        } else if (PHY_USER_MEMORY_BASE..PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE).contains(&addr) {
+            self.user_memory
+                .load(addr - PHY_USER_MEMORY_BASE, size)
+                .map_err(|error| error.offset(PHY_USER_MEMORY_BASE))
         } else {
-            return self.memory.load(addr, size);
+            Err(BusError::Unmapped { addr, size })
+        }
+    }
+
+    // Whether a store to a physical address changes memory, the boot ROM
+    // ignores stores
+    pub fn writable(&self, addr: u32) -> bool {
+        (PHY_MEMORY_BASE..PHY_MEMORY_BASE + MEMORY_SIZE).contains(&addr)
+            || (PHY_USER_MEMORY_BASE..PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE).contains(&addr)
+    }
+
+    // Fetch and decode the instruction at a word aligned physical address.
+    // A miss decodes the whole page into the decoded-instruction cache.
+    pub fn fetch_decoded(&mut self, addr: u32) -> Result<Decoded, BusError> {
+        if let Some(decoded) = self.decode_cache.get(addr) {
+            return Ok(decoded);
+        }
+        if !DecodeCache::cacheable(addr) {
+            return self.load(addr, 32).map(decode);
         }
+        let base = addr - addr % PAGE_SIZE;
+        let page = (0..PAGE_SIZE)
+            .step_by(4)
+            .map(|offset| self.load(base + offset, 32).map(decode))
+            .collect::<Result<Box<[Decoded]>, BusError>>()?;
+        let decoded = page[((addr - base) / 4) as usize];
+        self.decode_cache.insert(base, page);
+        Ok(decoded)
+    }
+
+    // Fetch the block starting at a word aligned physical address. Outside
+    // the cached devices a block is the single instruction at the address.
+    pub fn fetch_block(&mut self, addr: u32) -> Result<Rc<Block>, BusError> {
+        let decoded = self.fetch_decoded(addr)?;
+        Ok(self.decode_cache.block(addr).unwrap_or_else(|| Rc::new(Block::translate(&[decoded]))))
+    }
+
+    // Changes whenever a store drops cached instructions
+    pub fn code_generation(&self) -> u64 {
+        self.decode_cache.generation()
+    }
+
+    // Changes whenever a store drops the cached instructions of the page at
+    // a physical address
+    #[cfg(feature = "jit")]
+    pub fn code_version(&self, addr: u32) -> u32 {
+        self.decode_cache.version(addr)
+    }
+
+    // Copy an image to a physical address, the boot ROM included. The image
+    // must fit in a single device, the error is at the first byte outside.
+    pub fn load_image(&mut self, addr: u32, image: &[u8]) -> Result<(), BusError> {
+        self.image_mut(addr, image.len() as u32)?.copy_from_slice(image);
+        self.decode_cache.invalidate(addr, image.len() as u32);
+        Ok(())
+    }
+
+    // Zero len bytes at a physical address, with the same rules as load_image
+    pub fn zero_image(&mut self, addr: u32, len: u32) -> Result<(), BusError> {
+        self.image_mut(addr, len)?.fill(0);
+        self.decode_cache.invalidate(addr, len);
+        Ok(())
+    }
+
+    fn image_mut(&mut self, addr: u32, len: u32) -> Result<&mut [u8], BusError> {
+        let end = addr.saturating_add(len);
+        let (memory, base) = if (PHY_BOOT_ROM_BASE..PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE).contains(&addr)
+            && end <= PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE
+        {
+            (&mut self.boot_rom.memory, PHY_BOOT_ROM_BASE)
+        } else if (PHY_MEMORY_BASE..PHY_MEMORY_BASE + MEMORY_SIZE).contains(&addr)
+            && end <= PHY_MEMORY_BASE + MEMORY_SIZE
+        {
+            (&mut self.memory.memory, PHY_MEMORY_BASE)
+        } else if (PHY_USER_MEMORY_BASE..PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE).contains(&addr)
+            && end <= PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE
+        {
+            (&mut self.user_memory.memory, PHY_USER_MEMORY_BASE)
+        } else {
+            // past the end of the device holding addr, or addr itself
+            let devices = [
+                (PHY_BOOT_ROM_BASE, BOOT_ROM_SIZE),
+                (PHY_MEMORY_BASE, MEMORY_SIZE),
+                (PHY_USER_MEMORY_BASE, USER_MEMORY_SIZE),
+            ];
+            let outside = devices
+                .iter()
+                .find(|(base, size)| (*base..*base + *size).contains(&addr))
+                .map_or(addr, |(base, size)| base + size);
+            return Err(BusError::Unmapped { addr: outside, size: 8 });
+        };
+        let start = (addr - base) as usize;
+        Ok(&mut memory[start..start + len as usize])
     }
 
-    pub fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), ()> {
-        if PHY_BOOT_ROM_BASE <= addr && addr < PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE {
-            return self.boot_rom.store(addr - PHY_BOOT_ROM_BASE, size, value);
+    pub fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), BusError> {
+        if (PHY_BOOT_ROM_BASE..PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE).contains(&addr) {
+            self.boot_rom
+                .store(addr - PHY_BOOT_ROM_BASE, size, value)
+                .map_err(|error| error.offset(PHY_BOOT_ROM_BASE))?;
+        } else if (PHY_MEMORY_BASE..PHY_MEMORY_BASE + MEMORY_SIZE).contains(&addr) {
+            self.memory.store(addr - PHY_MEMORY_BASE, size, value).map_err(|error| error.offset(PHY_MEMORY_BASE))?;
+        } else if (PHY_USER_MEMORY_BASE..PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE).contains(&addr) {
+            self.user_memory
+                .store(addr - PHY_USER_MEMORY_BASE, size, value)
+                .map_err(|error| error.offset(PHY_USER_MEMORY_BASE))?;
         } else {
-            return self.memory.store(addr, size, value);
+            return Err(BusError::Unmapped { addr, size });
         }
+        // the stored word may have been decoded already
+        self.decode_cache.invalidate(addr, size / 8);
+        Ok(())
     }
 }
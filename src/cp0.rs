@@ -0,0 +1,267 @@
+use crate::cpu::IsaRevision;
+use crate::tlb::*;
+
+pub const CP0_INDEX: usize = 0;
+pub const CP0_RANDOM: usize = 1;
+pub const CP0_ENTRYLO0: usize = 2;
+pub const CP0_ENTRYLO1: usize = 3;
+pub const CP0_CONTEXT: usize = 4;
+pub const CP0_USERLOCAL: usize = 4;
+pub const CP0_PAGEMASK: usize = 5;
+pub const CP0_WIRED: usize = 6;
+pub const CP0_BADVADDR: usize = 8;
+pub const CP0_COUNT: usize = 9;
+pub const CP0_ENTRYHI: usize = 10;
+pub const CP0_STATUS: usize = 12;
+pub const CP0_CAUSE: usize = 13;
+pub const CP0_EPC: usize = 14;
+pub const CP0_PRID: usize = 15;
+pub const CP0_CONFIG: usize = 16;
+pub const CP0_ERROREPC: usize = 30;
+
+pub const STATUS_IE: u32 = 1 << 0;
+pub const STATUS_EXL: u32 = 1 << 1;
+pub const STATUS_ERL: u32 = 1 << 2;
+pub const STATUS_SM: u32 = 1 << 3;
+pub const STATUS_UM: u32 = 1 << 4;
+pub const STATUS_KSU: u32 = STATUS_SM | STATUS_UM;
+pub const STATUS_BEV: u32 = 1 << 22;
+pub const STATUS_CU0: u32 = 1 << 28;
+pub const STATUS_CU1: u32 = 1 << 29;
+// CU3..0, FR, BEV, IM7..0, KSU, ERL, EXL, IE
+const STATUS_WRITABLE: u32 = 0xf440_ff1f;
+
+// Index.P: tlbp found no matching entry
+pub const INDEX_P: u32 = 1 << 31;
+
+pub const CONTEXT_PTE_BASE: u32 = 0xff80_0000;
+pub const CONTEXT_BAD_VPN2_SHIFT: u32 = 9;
+
+pub const CAUSE_EXC_CODE_SHIFT: u32 = 2;
+pub const CAUSE_EXC_CODE: u32 = 0x1f << CAUSE_EXC_CODE_SHIFT;
+pub const CAUSE_CE_SHIFT: u32 = 28;
+pub const CAUSE_CE: u32 = 0x3 << CAUSE_CE_SHIFT;
+pub const CAUSE_BD: u32 = 1 << 31;
+// IP1..0 (software interrupts)
+const CAUSE_WRITABLE: u32 = 0x0000_0300;
+
+// Company ID: MIPS Technologies, Processor ID: 4Kc
+pub const PRID_4KC: u32 = 0x0001_8000;
+// Company ID: MIPS Technologies, Processor ID: 4KEc
+pub const PRID_4KEC: u32 = 0x0001_9000;
+
+// Config1 present, little endian, MIPS32, kseg0 uncached
+pub const CONFIG_RESET: u32 = 0x8000_0002;
+pub const CONFIG_K0: u32 = 0x0000_0007;
+pub const CONFIG_AR_SHIFT: u32 = 10;
+// MMU type
+pub const CONFIG_MT: u32 = 0x0000_0380;
+pub const CONFIG_MT_NONE: u32 = 0 << 7;
+pub const CONFIG_MT_TLB: u32 = 1 << 7;
+pub const CONFIG_MT_FMT: u32 = 3 << 7;
+
+// Floating point unit implemented
+pub const CONFIG1_FP: u32 = 1 << 0;
+// number of TLB entries - 1
+pub const CONFIG1_MMU_SIZE_SHIFT: u32 = 25;
+pub const CONFIG1_MMU_SIZE: u32 = 0x3f << CONFIG1_MMU_SIZE_SHIFT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Kernel,
+    Supervisor,
+    User,
+}
+
+pub struct Cp0 {
+    pub index: u32,
+    pub entry_lo0: u32,
+    pub entry_lo1: u32,
+    pub context: u32,
+    pub user_local: u32,
+    pub page_mask: u32,
+    pub wired: u32,
+    // Count when Wired was last written, Random counts down from there
+    random_start: u32,
+    pub bad_vaddr: u32,
+    pub count: u32,
+    pub entry_hi: u32,
+    pub status: u32,
+    pub cause: u32,
+    pub epc: u32,
+    pub prid: u32,
+    pub config: u32,
+    pub config1: u32,
+    pub error_epc: u32,
+    pub tlb: Tlb,
+}
+
+impl Cp0 {
+    pub fn new(isa: IsaRevision) -> Self {
+        let (prid, ar) = match isa {
+            IsaRevision::Release1 => (PRID_4KC, 0),
+            IsaRevision::Release2 => (PRID_4KEC, 1),
+        };
+
+        Self {
+            index: 0u32,
+            entry_lo0: 0u32,
+            entry_lo1: 0u32,
+            context: 0u32,
+            user_local: 0u32,
+            page_mask: 0u32,
+            wired: 0u32,
+            random_start: 0u32,
+            bad_vaddr: 0u32,
+            count: 0u32,
+            entry_hi: 0u32,
+            // reset exception: boot exception vectors, error level
+            status: STATUS_BEV | STATUS_ERL,
+            cause: 0u32,
+            epc: 0u32,
+            prid,
+            config: CONFIG_RESET | CONFIG_MT_TLB | (ar << CONFIG_AR_SHIFT),
+            config1: CONFIG1_FP | ((TLB_ENTRIES as u32 - 1) << CONFIG1_MMU_SIZE_SHIFT),
+            error_epc: 0u32,
+            tlb: Tlb::new(TLB_ENTRIES),
+        }
+    }
+
+    // Execution mode: kernel while handling an exception or an error,
+    // otherwise from Status.KSU, where the reserved value is taken as user
+    pub fn mode(&self) -> Mode {
+        if self.status & (STATUS_EXL | STATUS_ERL) != 0 {
+            return Mode::Kernel;
+        }
+        match self.status & STATUS_KSU {
+            0 => Mode::Kernel,
+            STATUS_SM => Mode::Supervisor,
+            _ => Mode::User,
+        }
+    }
+
+    // Whether CP0 instructions may run, in kernel mode or with Status.CU0
+    pub fn usable(&self) -> bool {
+        self.mode() == Mode::Kernel || self.status & STATUS_CU0 != 0
+    }
+
+    // Select the MMU, one of CONFIG_MT_NONE, CONFIG_MT_TLB and CONFIG_MT_FMT.
+    // Config.MT is read only to software.
+    pub fn set_mmu_type(&mut self, mt: u32) {
+        self.config = (self.config & !CONFIG_MT) | (mt & CONFIG_MT);
+    }
+
+    // Replace the TLB with one of 1 to MAX_TLB_ENTRIES invalid entries
+    pub fn set_tlb_entries(&mut self, entries: usize) {
+        self.tlb = Tlb::new(entries);
+        self.config1 = (self.config1 & !CONFIG1_MMU_SIZE) | ((entries as u32 - 1) << CONFIG1_MMU_SIZE_SHIFT);
+        self.index = 0;
+        self.wired = 0;
+    }
+
+    // Counts down from the last entry to Wired once per instruction, for tlbwr
+    pub fn random(&self) -> u32 {
+        let entries = self.tlb.entries.len() as u32;
+        if self.wired >= entries {
+            return entries - 1;
+        }
+        entries - 1 - self.count.wrapping_sub(self.random_start) % (entries - self.wired)
+    }
+
+    // Record the VPN2 of a TLB exception at addr in EntryHi and Context
+    pub fn tlb_miss(&mut self, addr: u32) {
+        self.entry_hi = (addr & ENTRY_HI_VPN2) | (self.entry_hi & ENTRY_HI_ASID);
+        self.context = (self.context & CONTEXT_PTE_BASE) | ((addr & ENTRY_HI_VPN2) >> CONTEXT_BAD_VPN2_SHIFT);
+    }
+
+    // tlbr: the entry at Index into EntryHi, EntryLo0, EntryLo1 and PageMask
+    pub fn tlb_read(&mut self) {
+        let entry = self.tlb.entries[self.index as usize % self.tlb.entries.len()];
+        self.page_mask = entry.page_mask;
+        self.entry_hi = entry.entry_hi;
+        self.entry_lo0 = entry.entry_lo0;
+        self.entry_lo1 = entry.entry_lo1;
+    }
+
+    // tlbwi and tlbwr: EntryHi, EntryLo0, EntryLo1 and PageMask into an entry
+    pub fn tlb_write(&mut self, index: u32) {
+        let global = self.entry_lo0 & self.entry_lo1 & ENTRY_LO_G;
+        let entries = self.tlb.entries.len();
+        self.tlb.entries[index as usize % entries] = TlbEntry {
+            page_mask: self.page_mask,
+            entry_hi: self.entry_hi & !self.page_mask,
+            entry_lo0: (self.entry_lo0 & !ENTRY_LO_G) | global,
+            entry_lo1: (self.entry_lo1 & !ENTRY_LO_G) | global,
+        };
+    }
+
+    // tlbp: Index of the entry matching EntryHi, or Index.P
+    pub fn tlb_probe(&mut self) {
+        self.index = match self.tlb.probe(self.entry_hi) {
+            Some(index) => index as u32,
+            None => INDEX_P | self.index,
+        };
+    }
+
+    pub fn read(&self, reg: usize, sel: u32) -> u32 {
+        match (reg, sel) {
+            (CP0_INDEX, 0) => self.index,
+            (CP0_RANDOM, 0) => self.random(),
+            (CP0_ENTRYLO0, 0) => self.entry_lo0,
+            (CP0_ENTRYLO1, 0) => self.entry_lo1,
+            (CP0_CONTEXT, 0) => self.context,
+            (CP0_USERLOCAL, 2) => self.user_local,
+            (CP0_PAGEMASK, 0) => self.page_mask,
+            (CP0_WIRED, 0) => self.wired,
+            (CP0_BADVADDR, 0) => self.bad_vaddr,
+            (CP0_COUNT, 0) => self.count,
+            (CP0_ENTRYHI, 0) => self.entry_hi,
+            (CP0_STATUS, 0) => self.status,
+            (CP0_CAUSE, 0) => self.cause,
+            (CP0_EPC, 0) => self.epc,
+            (CP0_PRID, 0) => self.prid,
+            (CP0_CONFIG, 0) => self.config,
+            (CP0_CONFIG, 1) => self.config1,
+            (CP0_ERROREPC, 0) => self.error_epc,
+            // unimplemented registers read as zero
+            _ => 0u32,
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, sel: u32, value: u32) {
+        match (reg, sel) {
+            (CP0_RANDOM, 0) | (CP0_BADVADDR, 0) | (CP0_PRID, 0) | (CP0_CONFIG, 1) => {
+                // read only
+            }
+            (CP0_STATUS, 0) => {
+                self.status = (self.status & !STATUS_WRITABLE) | (value & STATUS_WRITABLE);
+            }
+            (CP0_CAUSE, 0) => {
+                self.cause = (self.cause & !CAUSE_WRITABLE) | (value & CAUSE_WRITABLE);
+            }
+            (CP0_INDEX, 0) => self.index = value % self.tlb.entries.len() as u32,
+            (CP0_ENTRYLO0, 0) => self.entry_lo0 = value & ENTRY_LO_WRITABLE,
+            (CP0_ENTRYLO1, 0) => self.entry_lo1 = value & ENTRY_LO_WRITABLE,
+            (CP0_CONTEXT, 0) => {
+                self.context = (self.context & !CONTEXT_PTE_BASE) | (value & CONTEXT_PTE_BASE);
+            }
+            (CP0_USERLOCAL, 2) => self.user_local = value,
+            (CP0_PAGEMASK, 0) => self.page_mask = value & PAGE_MASK_WRITABLE,
+            (CP0_WIRED, 0) => {
+                // Random restarts from the last entry
+                self.wired = value % self.tlb.entries.len() as u32;
+                self.random_start = self.count;
+            }
+            (CP0_COUNT, 0) => self.count = value,
+            (CP0_ENTRYHI, 0) => self.entry_hi = value & (ENTRY_HI_VPN2 | ENTRY_HI_ASID),
+            (CP0_EPC, 0) => self.epc = value,
+            (CP0_CONFIG, 0) => {
+                self.config = (self.config & !CONFIG_K0) | (value & CONFIG_K0);
+            }
+            (CP0_ERROREPC, 0) => self.error_epc = value,
+            _ => {
+                // writes to unimplemented registers are ignored
+            }
+        }
+    }
+}
@@ -0,0 +1,562 @@
+pub const CP1_FIR: usize = 0;
+pub const CP1_FCCR: usize = 25;
+pub const CP1_FEXR: usize = 26;
+pub const CP1_FENR: usize = 28;
+pub const CP1_FCSR: usize = 31;
+
+// Single and double precision, Processor ID: 4K
+pub const FIR_RESET: u32 = 0x0003_8000;
+
+pub const FCSR_RM: u32 = 0x0000_0003;
+pub const FCSR_FLAGS_SHIFT: u32 = 2;
+pub const FCSR_ENABLES_SHIFT: u32 = 7;
+pub const FCSR_CAUSE_SHIFT: u32 = 12;
+pub const FCSR_FLAGS: u32 = 0x1f << FCSR_FLAGS_SHIFT;
+pub const FCSR_ENABLES: u32 = 0x1f << FCSR_ENABLES_SHIFT;
+pub const FCSR_CAUSE: u32 = 0x3f << FCSR_CAUSE_SHIFT;
+pub const FCSR_FS: u32 = 1 << 24;
+// FCC7..1, FS, FCC0, Cause, Enables, Flags, RM
+const FCSR_WRITABLE: u32 = 0xff83_ffff;
+
+// exception conditions, in the bit order of the FCSR Flags, Enables and Cause fields
+pub const FLAG_INEXACT: u32 = 1 << 0;
+pub const FLAG_UNDERFLOW: u32 = 1 << 1;
+pub const FLAG_OVERFLOW: u32 = 1 << 2;
+pub const FLAG_DIVIDE_BY_ZERO: u32 = 1 << 3;
+pub const FLAG_INVALID: u32 = 1 << 4;
+// Cause only, can't be masked
+pub const FLAG_UNIMPLEMENTED: u32 = 1 << 5;
+
+// MIPS legacy NaN encoding: the most significant fraction bit is set for signaling NaNs
+pub const DEFAULT_NAN_SINGLE: u32 = 0x7fbf_ffff;
+pub const DEFAULT_NAN_DOUBLE: u64 = 0x7ff7_ffff_ffff_ffff;
+// result of an invalid conversion to word
+pub const DEFAULT_NAN_WORD: u32 = 0x7fff_ffff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Single,
+    Double,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    Nearest,
+    Zero,
+    PlusInfinity,
+    MinusInfinity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Sqrt,
+}
+
+pub struct Cp1 {
+    // FR = 0: doubles occupy even/odd register pairs, low word in the even register
+    pub fpr: [u32; 32],
+    pub fir: u32,
+    pub fcsr: u32,
+}
+
+impl Default for Cp1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cp1 {
+    pub fn new() -> Self {
+        Self {
+            fpr: [0; 32],
+            fir: FIR_RESET,
+            fcsr: 0u32,
+        }
+    }
+
+    pub fn read(&self, format: Format, reg: usize) -> u64 {
+        match format {
+            Format::Single | Format::Word => self.fpr[reg] as u64,
+            Format::Double => (self.fpr[reg & !1] as u64) | ((self.fpr[reg | 1] as u64) << 32),
+        }
+    }
+
+    pub fn write(&mut self, format: Format, reg: usize, value: u64) {
+        match format {
+            Format::Single | Format::Word => self.fpr[reg] = value as u32,
+            Format::Double => {
+                self.fpr[reg & !1] = value as u32;
+                self.fpr[reg | 1] = (value >> 32) as u32;
+            }
+        }
+    }
+
+    pub fn read_control(&self, reg: usize) -> u32 {
+        match reg {
+            CP1_FIR => self.fir,
+            CP1_FCCR => ((self.fcsr >> 24) & 0xfe) | ((self.fcsr >> 23) & 0x01),
+            CP1_FEXR => self.fcsr & (FCSR_CAUSE | FCSR_FLAGS),
+            // FS is at bit 2 in FENR
+            CP1_FENR => (self.fcsr & (FCSR_ENABLES | FCSR_RM)) | ((self.fcsr & FCSR_FS) >> 22),
+            CP1_FCSR => self.fcsr,
+            // unimplemented registers read as zero
+            _ => 0u32,
+        }
+    }
+
+    // Returns true when the written Cause bits request a Floating Point exception.
+    pub fn write_control(&mut self, reg: usize, value: u32) -> bool {
+        let fcsr = match reg {
+            CP1_FCCR => (self.fcsr & !0xfe80_0000) | ((value & 0xfe) << 24) | ((value & 0x01) << 23),
+            CP1_FEXR => (self.fcsr & !(FCSR_CAUSE | FCSR_FLAGS)) | (value & (FCSR_CAUSE | FCSR_FLAGS)),
+            CP1_FENR => {
+                let mask = FCSR_ENABLES | FCSR_FS | FCSR_RM;
+                // FS is at bit 2 in FENR
+                let value = (value & (FCSR_ENABLES | FCSR_RM)) | ((value & 0x4) << 22);
+                (self.fcsr & !mask) | value
+            }
+            CP1_FCSR => (self.fcsr & !FCSR_WRITABLE) | (value & FCSR_WRITABLE),
+            // writes to unimplemented registers are ignored
+            _ => return false,
+        };
+        self.fcsr = fcsr;
+        self.cause() & (self.enables() | FLAG_UNIMPLEMENTED) != 0
+    }
+
+    pub fn rounding_mode(&self) -> RoundingMode {
+        match self.fcsr & FCSR_RM {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::Zero,
+            2 => RoundingMode::PlusInfinity,
+            _ => RoundingMode::MinusInfinity,
+        }
+    }
+
+    fn cause(&self) -> u32 {
+        (self.fcsr & FCSR_CAUSE) >> FCSR_CAUSE_SHIFT
+    }
+
+    fn enables(&self) -> u32 {
+        (self.fcsr & FCSR_ENABLES) >> FCSR_ENABLES_SHIFT
+    }
+
+    // Record the exception conditions of an operation. Returns true when they
+    // are enabled, in which case the flags are left untouched and the result
+    // must not be written.
+    pub fn signal(&mut self, cause: u32) -> bool {
+        self.fcsr = (self.fcsr & !FCSR_CAUSE) | (cause << FCSR_CAUSE_SHIFT);
+        if cause & (self.enables() | FLAG_UNIMPLEMENTED) != 0 {
+            return true;
+        }
+        self.fcsr |= cause << FCSR_FLAGS_SHIFT;
+        false
+    }
+
+    pub fn condition(&self, cc: u32) -> bool {
+        let bit = if cc == 0 { 23 } else { 24 + cc };
+        self.fcsr & (1 << bit) != 0
+    }
+
+    pub fn set_condition(&mut self, cc: u32, value: bool) {
+        let bit = if cc == 0 { 23 } else { 24 + cc };
+        if value {
+            self.fcsr |= 1 << bit;
+        } else {
+            self.fcsr &= !(1 << bit);
+        }
+    }
+}
+
+fn is_nan(format: Format, value: u64) -> bool {
+    match format {
+        Format::Single => f32::from_bits(value as u32).is_nan(),
+        Format::Double => f64::from_bits(value).is_nan(),
+        Format::Word => false,
+    }
+}
+
+fn is_signaling_nan(format: Format, value: u64) -> bool {
+    match format {
+        Format::Single => is_nan(format, value) && value & 0x0040_0000 != 0,
+        Format::Double => is_nan(format, value) && value & 0x0008_0000_0000_0000 != 0,
+        Format::Word => false,
+    }
+}
+
+fn default_nan(format: Format) -> u64 {
+    match format {
+        Format::Single => DEFAULT_NAN_SINGLE as u64,
+        Format::Double => DEFAULT_NAN_DOUBLE,
+        Format::Word => DEFAULT_NAN_WORD as u64,
+    }
+}
+
+// Exact conversion of an operand to the host double format.
+fn to_f64(format: Format, value: u64) -> f64 {
+    match format {
+        Format::Single => f32::from_bits(value as u32) as f64,
+        Format::Double => f64::from_bits(value),
+        Format::Word => (value as u32 as i32) as f64,
+    }
+}
+
+// Conversion of a value exactly representable in the destination format.
+fn from_f64(format: Format, value: f64) -> u64 {
+    match format {
+        Format::Single => (value as f32).to_bits() as u64,
+        Format::Double => value.to_bits(),
+        Format::Word => value as i32 as u32 as u64,
+    }
+}
+
+// Round a result to the destination format. `hi` is the result rounded to
+// nearest in the host double format and `residual` carries the sign of the
+// exact result minus `hi`.
+fn round(format: Format, hi: f64, residual: f64, rm: RoundingMode) -> (u64, u32) {
+    match format {
+        Format::Single => {
+            let nearest = hi as f32;
+            if nearest.is_infinite() && hi.is_finite() {
+                return (overflow_single(hi, rm).to_bits() as u64, FLAG_OVERFLOW | FLAG_INEXACT);
+            }
+            let difference = hi - nearest as f64;
+            let residual = if difference != 0.0 { difference } else { residual };
+            if residual == 0.0 {
+                return (nearest.to_bits() as u64, 0);
+            }
+            let result = match rm {
+                RoundingMode::Nearest => nearest,
+                RoundingMode::Zero if nearest > 0.0 && residual < 0.0 => nearest.next_down(),
+                RoundingMode::Zero if nearest < 0.0 && residual > 0.0 => nearest.next_up(),
+                RoundingMode::PlusInfinity if residual > 0.0 => nearest.next_up(),
+                RoundingMode::MinusInfinity if residual < 0.0 => nearest.next_down(),
+                _ => nearest,
+            };
+            let mut flags = FLAG_INEXACT;
+            if result.is_infinite() {
+                flags |= FLAG_OVERFLOW;
+            } else if hi.abs() < f32::MIN_POSITIVE as f64 {
+                flags |= FLAG_UNDERFLOW;
+            }
+            (result.to_bits() as u64, flags)
+        }
+        Format::Double => {
+            if hi.is_infinite() {
+                return (overflow_double(hi, rm).to_bits(), FLAG_OVERFLOW | FLAG_INEXACT);
+            }
+            if residual == 0.0 {
+                return (hi.to_bits(), 0);
+            }
+            let result = match rm {
+                RoundingMode::Nearest => hi,
+                RoundingMode::Zero if hi > 0.0 && residual < 0.0 => hi.next_down(),
+                RoundingMode::Zero if hi < 0.0 && residual > 0.0 => hi.next_up(),
+                RoundingMode::PlusInfinity if residual > 0.0 => hi.next_up(),
+                RoundingMode::MinusInfinity if residual < 0.0 => hi.next_down(),
+                _ => hi,
+            };
+            let mut flags = FLAG_INEXACT;
+            if result.is_infinite() {
+                flags |= FLAG_OVERFLOW;
+            } else if hi.abs() < f64::MIN_POSITIVE {
+                flags |= FLAG_UNDERFLOW;
+            }
+            (result.to_bits(), flags)
+        }
+        Format::Word => {
+            let result = match rm {
+                RoundingMode::Nearest => hi.round_ties_even(),
+                RoundingMode::Zero => hi.trunc(),
+                RoundingMode::PlusInfinity => hi.ceil(),
+                RoundingMode::MinusInfinity => hi.floor(),
+            };
+            if hi.is_nan() || result < i32::MIN as f64 || result > i32::MAX as f64 {
+                return (DEFAULT_NAN_WORD as u64, FLAG_INVALID);
+            }
+            let flags = if result != hi || residual != 0.0 { FLAG_INEXACT } else { 0 };
+            (result as i32 as u32 as u64, flags)
+        }
+    }
+}
+
+fn overflow_single(hi: f64, rm: RoundingMode) -> f32 {
+    match rm {
+        RoundingMode::Nearest => f32::INFINITY.copysign(hi as f32),
+        RoundingMode::Zero => f32::MAX.copysign(hi as f32),
+        RoundingMode::PlusInfinity if hi > 0.0 => f32::INFINITY,
+        RoundingMode::MinusInfinity if hi < 0.0 => f32::NEG_INFINITY,
+        _ => f32::MAX.copysign(hi as f32),
+    }
+}
+
+fn overflow_double(hi: f64, rm: RoundingMode) -> f64 {
+    match rm {
+        RoundingMode::Nearest => hi,
+        RoundingMode::Zero => f64::MAX.copysign(hi),
+        RoundingMode::PlusInfinity if hi > 0.0 => f64::INFINITY,
+        RoundingMode::MinusInfinity if hi < 0.0 => f64::NEG_INFINITY,
+        _ => f64::MAX.copysign(hi),
+    }
+}
+
+// IEEE 754 add, sub, mul, div and sqrt. Returns the result and the raised
+// exception conditions. `b` is ignored by sqrt.
+pub fn arithmetic(operation: Operation, format: Format, a: u64, b: u64, rm: RoundingMode) -> (u64, u32) {
+    let b = if operation == Operation::Sqrt { 0 } else { b };
+    if is_nan(format, a) || is_nan(format, b) {
+        let flags = if is_signaling_nan(format, a) || is_signaling_nan(format, b) {
+            FLAG_INVALID
+        } else {
+            0
+        };
+        return (default_nan(format), flags);
+    }
+
+    let x = to_f64(format, a);
+    let y = to_f64(format, b);
+    let invalid = match operation {
+        Operation::Add => x.is_infinite() && y.is_infinite() && x.signum() != y.signum(),
+        Operation::Sub => x.is_infinite() && y.is_infinite() && x.signum() == y.signum(),
+        Operation::Mul => (x == 0.0 && y.is_infinite()) || (x.is_infinite() && y == 0.0),
+        Operation::Div => (x == 0.0 && y == 0.0) || (x.is_infinite() && y.is_infinite()),
+        Operation::Sqrt => x < 0.0,
+    };
+    if invalid {
+        return (default_nan(format), FLAG_INVALID);
+    }
+
+    let (hi, residual) = match operation {
+        Operation::Add | Operation::Sub => {
+            let y = if operation == Operation::Sub { -y } else { y };
+            let sum = x + y;
+            // TwoSum: sum + residual == x + y exactly
+            let bb = sum - x;
+            let residual = (x - (sum - bb)) + (y - bb);
+            // an exact zero sum of operands with opposite signs is -0 when
+            // rounding down, the host always rounds to nearest
+            if sum == 0.0 && x.is_sign_negative() != y.is_sign_negative() && rm == RoundingMode::MinusInfinity {
+                return (from_f64(format, -0.0), 0);
+            }
+            (sum, residual)
+        }
+        Operation::Mul => {
+            let product = x * y;
+            (product, x.mul_add(y, -product))
+        }
+        Operation::Div => {
+            if y == 0.0 {
+                if x.is_infinite() {
+                    return (from_f64(format, x / y), 0);
+                }
+                return (from_f64(format, x / y), FLAG_DIVIDE_BY_ZERO);
+            }
+            let quotient = x / y;
+            // the remainder has the sign of (exact - quotient) * y
+            let remainder = (-quotient).mul_add(y, x);
+            (quotient, remainder * y.signum())
+        }
+        Operation::Sqrt => {
+            let root = x.sqrt();
+            (root, (-root).mul_add(root, x))
+        }
+    };
+
+    if x.is_infinite() || y.is_infinite() {
+        // results from infinite operands are exact
+        return (from_f64(format, hi), 0);
+    }
+    round(format, hi, residual, rm)
+}
+
+// abs and neg only operate on the sign bit, except that signaling NaNs are invalid.
+pub fn sign(format: Format, value: u64, negate: bool) -> (u64, u32) {
+    if is_signaling_nan(format, value) {
+        return (default_nan(format), FLAG_INVALID);
+    }
+    let sign_bit = match format {
+        Format::Single => 1u64 << 31,
+        _ => 1u64 << 63,
+    };
+    if negate {
+        (value ^ sign_bit, 0)
+    } else {
+        (value & !sign_bit, 0)
+    }
+}
+
+pub fn convert(from: Format, to: Format, value: u64, rm: RoundingMode) -> (u64, u32) {
+    if is_nan(from, value) {
+        let flags = if is_signaling_nan(from, value) || to == Format::Word {
+            FLAG_INVALID
+        } else {
+            0
+        };
+        return (default_nan(to), flags);
+    }
+    let x = to_f64(from, value);
+    if x.is_infinite() {
+        if to == Format::Word {
+            return (DEFAULT_NAN_WORD as u64, FLAG_INVALID);
+        }
+        return (from_f64(to, x), 0);
+    }
+    round(to, x, 0.0, rm)
+}
+
+// c.cond.fmt: bit 0 of cond selects unordered, bit 1 equal, bit 2 less than and
+// bit 3 signals Invalid on unordered operands.
+pub fn compare(format: Format, a: u64, b: u64, cond: u32) -> (bool, u32) {
+    if is_nan(format, a) || is_nan(format, b) {
+        let flags = if cond & 0x8 != 0 || is_signaling_nan(format, a) || is_signaling_nan(format, b) {
+            FLAG_INVALID
+        } else {
+            0
+        };
+        return (cond & 0x1 != 0, flags);
+    }
+    let x = to_f64(format, a);
+    let y = to_f64(format, b);
+    let result = (cond & 0x2 != 0 && x == y) || (cond & 0x4 != 0 && x < y);
+    (result, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(value: f32) -> u64 {
+        value.to_bits() as u64
+    }
+
+    fn double(value: f64) -> u64 {
+        value.to_bits()
+    }
+
+    #[test]
+    fn inexact_results_round_in_every_mode() {
+        let tiny = 2f32.powi(-30);
+        let cases = [
+            (RoundingMode::Nearest, 1.0, -1.0),
+            (RoundingMode::Zero, 1.0, -1.0),
+            (RoundingMode::PlusInfinity, 1f32.next_up(), -1.0),
+            (RoundingMode::MinusInfinity, 1.0, -1f32.next_up()),
+        ];
+        for (rm, up, down) in cases {
+            let sum = arithmetic(Operation::Add, Format::Single, single(1.0), single(tiny), rm);
+            assert_eq!(sum, (single(up), FLAG_INEXACT), "1 + tiny under {:?}", rm);
+            let difference = arithmetic(Operation::Sub, Format::Single, single(-1.0), single(tiny), rm);
+            assert_eq!(difference, (single(down), FLAG_INEXACT), "-1 - tiny under {:?}", rm);
+        }
+
+        let third = |rm| arithmetic(Operation::Div, Format::Double, double(1.0), double(3.0), rm);
+        assert_eq!(third(RoundingMode::Nearest), (double(1.0 / 3.0), FLAG_INEXACT));
+        assert_eq!(third(RoundingMode::Zero), third(RoundingMode::MinusInfinity));
+        let (up, _) = third(RoundingMode::PlusInfinity);
+        let (down, _) = third(RoundingMode::MinusInfinity);
+        assert_eq!(f64::from_bits(down).next_up(), f64::from_bits(up));
+
+        let root = |rm| arithmetic(Operation::Sqrt, Format::Single, single(2.0), 0, rm);
+        let (up, _) = root(RoundingMode::PlusInfinity);
+        let (down, flags) = root(RoundingMode::Zero);
+        assert_eq!(flags, FLAG_INEXACT);
+        assert_eq!(f32::from_bits(down as u32).next_up(), f32::from_bits(up as u32));
+    }
+
+    #[test]
+    fn exact_results_raise_nothing() {
+        let rm = RoundingMode::Nearest;
+        assert_eq!(arithmetic(Operation::Mul, Format::Double, double(1.5), double(4.0), rm), (double(6.0), 0));
+        assert_eq!(arithmetic(Operation::Sqrt, Format::Single, single(9.0), 0, rm), (single(3.0), 0));
+    }
+
+    #[test]
+    fn exact_zero_sums_are_negative_only_when_rounding_down() {
+        for (rm, zero) in [
+            (RoundingMode::Nearest, 0.0),
+            (RoundingMode::Zero, 0.0),
+            (RoundingMode::PlusInfinity, 0.0),
+            (RoundingMode::MinusInfinity, -0.0),
+        ] {
+            let sum = arithmetic(Operation::Add, Format::Double, double(2.5), double(-2.5), rm);
+            assert_eq!(sum, (double(zero), 0), "x + -x under {:?}", rm);
+            let difference = arithmetic(Operation::Sub, Format::Single, single(2.5), single(2.5), rm);
+            assert_eq!(difference, (single(zero as f32), 0), "x - x under {:?}", rm);
+        }
+        // equal signs keep their sign in every mode
+        let sum = arithmetic(Operation::Add, Format::Double, double(-0.0), double(-0.0), RoundingMode::Nearest);
+        assert_eq!(sum, (double(-0.0), 0));
+    }
+
+    #[test]
+    fn overflow_depends_on_the_rounding_mode() {
+        let twice = |value: f32, rm| arithmetic(Operation::Mul, Format::Single, single(value), single(2.0), rm);
+        let flags = FLAG_OVERFLOW | FLAG_INEXACT;
+        assert_eq!(twice(f32::MAX, RoundingMode::Nearest), (single(f32::INFINITY), flags));
+        assert_eq!(twice(f32::MAX, RoundingMode::Zero), (single(f32::MAX), flags));
+        assert_eq!(twice(f32::MAX, RoundingMode::MinusInfinity), (single(f32::MAX), flags));
+        assert_eq!(twice(-f32::MAX, RoundingMode::MinusInfinity), (single(f32::NEG_INFINITY), flags));
+
+        let sum = arithmetic(Operation::Add, Format::Double, double(f64::MAX), double(f64::MAX), RoundingMode::Zero);
+        assert_eq!(sum, (double(f64::MAX), flags));
+    }
+
+    #[test]
+    fn tiny_inexact_results_underflow() {
+        let (result, flags) = arithmetic(
+            Operation::Div,
+            Format::Single,
+            single(f32::MIN_POSITIVE),
+            single(3.0),
+            RoundingMode::Nearest,
+        );
+        assert_eq!(flags, FLAG_UNDERFLOW | FLAG_INEXACT);
+        assert!(f32::from_bits(result as u32).is_subnormal());
+
+        let (_, flags) = arithmetic(
+            Operation::Mul,
+            Format::Double,
+            double(f64::MIN_POSITIVE),
+            double(0.5),
+            RoundingMode::Nearest,
+        );
+        // halving the smallest normal is exact
+        assert_eq!(flags, 0);
+    }
+
+    #[test]
+    fn invalid_operations_return_the_default_nan() {
+        let rm = RoundingMode::Nearest;
+        let infinity = single(f32::INFINITY);
+        let invalid = (DEFAULT_NAN_SINGLE as u64, FLAG_INVALID);
+        assert_eq!(arithmetic(Operation::Sub, Format::Single, infinity, infinity, rm), invalid);
+        assert_eq!(arithmetic(Operation::Mul, Format::Single, infinity, single(0.0), rm), invalid);
+        assert_eq!(arithmetic(Operation::Div, Format::Single, single(0.0), single(0.0), rm), invalid);
+        assert_eq!(arithmetic(Operation::Sqrt, Format::Single, single(-1.0), 0, rm), invalid);
+        assert_eq!(
+            arithmetic(Operation::Div, Format::Double, double(1.0), double(-0.0), rm),
+            (double(f64::NEG_INFINITY), FLAG_DIVIDE_BY_ZERO)
+        );
+    }
+
+    #[test]
+    fn nan_operands_give_the_default_nan() {
+        let rm = RoundingMode::Nearest;
+        // legacy encoding: quiet NaNs have the most significant fraction bit clear
+        let quiet = DEFAULT_NAN_DOUBLE;
+        let signaling = 0x7ff8_0000_0000_0000;
+        assert_eq!(arithmetic(Operation::Add, Format::Double, quiet, double(1.0), rm), (DEFAULT_NAN_DOUBLE, 0));
+        assert_eq!(
+            arithmetic(Operation::Mul, Format::Double, double(1.0), signaling, rm),
+            (DEFAULT_NAN_DOUBLE, FLAG_INVALID)
+        );
+        assert_eq!(
+            arithmetic(Operation::Sqrt, Format::Single, 0x7fc0_0000, 0, rm),
+            (DEFAULT_NAN_SINGLE as u64, FLAG_INVALID)
+        );
+    }
+}
@@ -1,4 +1,17 @@
+use std::rc::Rc;
+
+use crate::block::*;
 use crate::bus::*;
+use crate::cp0::*;
+use crate::cp1::*;
+use crate::debug_info::*;
+use crate::decode::*;
+use crate::decode_cache::*;
+use crate::disasm::*;
+use crate::error::*;
+use crate::exception::*;
+use crate::syscall::*;
+use crate::tlb::*;
 
 pub const BOOT_EXCEPTION_VECTOR: u32 = 0xbfc0_0000;
 pub const KUSEG_BASE: u32 = 0x0000_0000;
@@ -7,448 +20,1220 @@ pub const KSEG0_BASE: u32 = 0x8000_0000;
 pub const KSEG0_SIZE: u32 = 0x2000_0000;
 pub const KSEG1_BASE: u32 = 0xa000_0000;
 pub const KSEG1_SIZE: u32 = 0x2000_0000;
-pub const KSEG2_BASE: u32 = 0xc000_0000;
+// the supervisor segment, the lower half of kseg2
+pub const SSEG_BASE: u32 = 0xc000_0000;
+pub const SSEG_SIZE: u32 = 0x2000_0000;
+// kuseg base address of the fixed mapping MMU
+pub const FMT_KUSEG_BASE: u32 = 0x4000_0000;
+pub const BOOT_EXCEPTION_BASE: u32 = 0xbfc0_0200;
+pub const EXCEPTION_BASE: u32 = 0x8000_0000;
+pub const TLB_REFILL_OFFSET: u32 = 0x000;
+pub const GENERAL_EXCEPTION_OFFSET: u32 = 0x180;
+
+// o32 ABI names of the general purpose registers
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1",
+    "t2", "t3", "t4", "t5", "t6", "t7", "s0", "s1", "s2", "s3",
+    "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp",
+    "fp", "ra",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IsaRevision {
+    Release1,
+    Release2,
+}
+
+// Pending control transfer of a branch whose delay slot is executing next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BranchDelay {
+    Taken(u32),
+    NotTaken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
 
 pub struct Cpu {
     pub regs: [u32; 32],
     pub pc: u32,
     pub bus: Bus,
-    pc_branch_delay: Option<u32>,
+    branch_delay: Option<BranchDelay>,
     pub hi: u32,
     pub lo: u32,
+    pub cp0: Cp0,
+    pub cp1: Cp1,
+    pub isa: IsaRevision,
+    // set by ll, cleared by eret and by stores to the linked word
+    ll_bit: bool,
+    // physical address of the word linked by ll
+    ll_addr: u32,
+    syscall_handler: Box<dyn SyscallHandler>,
+    // print every executed instruction and exception
+    pub trace: bool,
+    // symbols and source lines of the loaded ELF image
+    pub debug_info: DebugInfo,
+    // data watchpoints of a debugger
+    pub watchpoints: Vec<Watchpoint>,
+    // set by a load or store that hits a watchpoint
+    pub watch_hit: Option<Watchpoint>,
 }
 
 impl Cpu {
-    pub fn new(binary: Vec<u8>) -> Self {
+    pub fn new(binary: Vec<u8>, isa: IsaRevision) -> Self {
         let regs = [0; 32];
 
         Self {
-            regs: regs,
+            regs,
             pc: BOOT_EXCEPTION_VECTOR,
             bus: Bus::new(binary),
-            pc_branch_delay: None,
+            branch_delay: None,
             hi: 0u32,
             lo: 0u32,
+            cp0: Cp0::new(isa),
+            cp1: Cp1::new(),
+            isa,
+            ll_bit: false,
+            ll_addr: 0u32,
+            syscall_handler: Box::new(ExceptionSyscall),
+            trace: true,
+            debug_info: DebugInfo::default(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
         }
     }
 
+    pub fn set_syscall_handler(&mut self, handler: Box<dyn SyscallHandler>) {
+        self.syscall_handler = handler;
+    }
+
     pub fn dump_registers(&self) {
         let mut output = String::from("");
-        let abi = [
-            "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1",
-            "t2", "t3", "t4", "t5", "t6", "t7", "s0", "s1", "s2", "s3",
-            "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp",
-            "fp", "ra",
-        ];
         for i in (0..32).step_by(4) {
             output = format!(
-                "{}\n{}",
+                "{}\nx{:02}({})={:>#18x} x{:02}({})={:>#18x} x{:02}({})={:>#18x} x{:02}({})={:>#18x}",
                 output,
-                format!(
-                    "x{:02}({})={:>#18x} x{:02}({})={:>#18x} x{:02}({})={:>#18x} x{:02}({})={:>#18x}",
-                    i,
-                    abi[i],
-                    self.regs[i],
-                    i + 1,
-                    abi[i + 1],
-                    self.regs[i + 1],
-                    i + 2,
-                    abi[i + 2],
-                    self.regs[i + 2],
-                    i + 3,
-                    abi[i + 3],
-                    self.regs[i + 3],
-                )
+                i,
+                ABI_NAMES[i],
+                self.regs[i],
+                i + 1,
+                ABI_NAMES[i + 1],
+                self.regs[i + 1],
+                i + 2,
+                ABI_NAMES[i + 2],
+                self.regs[i + 2],
+                i + 3,
+                ABI_NAMES[i + 3],
+                self.regs[i + 3],
             );
         }
         println!("{}", output);
     }
 
-    pub fn load(&mut self, addr: u32, size: u32) -> Result<u32, ()> {
-        let physical_addr = self.mmu(addr);
-        self.bus.load(physical_addr, size)
+    // address followed by its symbol and source line when known
+    pub fn describe(&self, addr: u32) -> String {
+        match self.debug_info.location(addr) {
+            Some(location) => format!("{:#x} {}", addr, location),
+            None => format!("{:#x}", addr),
+        }
+    }
+
+    // "instruction ... is not implemented at 0x400124 fib+0x24 (fib.c:3)"
+    pub fn describe_error(&self, error: &CpuError) -> String {
+        match error.pc() {
+            Some(pc) => format!("{} at {}", error, self.describe(pc)),
+            None => error.to_string(),
+        }
+    }
+
+    pub fn load(&mut self, addr: u32, size: u32) -> Result<u32, Exception> {
+        if addr & (size / 8 - 1) != 0 {
+            return Err(Exception::AddressErrorLoad(addr));
+        }
+        let physical_addr = self.mmu(addr, false)?;
+        let value = self.bus.load(physical_addr, size).map_err(|error| Exception::DataBusError { addr, error })?;
+        self.watch(addr, size, false);
+        Ok(value)
+    }
+
+    pub fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), Exception> {
+        if addr & (size / 8 - 1) != 0 {
+            return Err(Exception::AddressErrorStore(addr));
+        }
+        let physical_addr = self.mmu(addr, true)?;
+        if self.ll_bit && physical_addr & !3 == self.ll_addr {
+            self.ll_bit = false;
+        }
+        self.bus.store(physical_addr, size, value).map_err(|error| Exception::DataBusError { addr, error })?;
+        self.watch(addr, size, true);
+        Ok(())
     }
 
-    pub fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), ()> {
-        let physical_addr = self.mmu(addr);
-        self.bus.store(physical_addr, size, value)
+    // Byte at addr as a debugger sees it: without exceptions, watchpoints or
+    // ll/sc effects
+    pub fn peek(&self, addr: u32) -> Option<u8> {
+        let physical_addr = self.mmu(addr, false).ok()?;
+        self.bus.load(physical_addr, 8).ok().map(|byte| byte as u8)
     }
 
-    pub fn mmu(&mut self, addr: u32) -> u32 {
-        if KUSEG_BASE <= addr && addr < KUSEG_BASE + KUSEG_SIZE {
-            dbg!(format!("not implemented yet: page table"));
-        } else if KSEG0_BASE <= addr && addr < KSEG0_BASE + KSEG0_SIZE {
-            return addr - KSEG0_BASE;
-        } else if KSEG1_BASE <= addr && addr < KSEG1_BASE + KSEG1_SIZE {
-            return addr - KSEG1_BASE;
-        } else if KSEG2_BASE <= addr {
-            dbg!(format!("not implemented yet: page table"));
+    fn watch(&mut self, addr: u32, size: u32, store: bool) {
+        for watchpoint in self.watchpoints.iter() {
+            let kind_matches = match watchpoint.kind {
+                WatchKind::Write => store,
+                WatchKind::Read => !store,
+                WatchKind::Access => true,
+            };
+            let overlaps = (addr as u64) < watchpoint.addr as u64 + watchpoint.len as u64
+                && (watchpoint.addr as u64) < addr as u64 + (size / 8) as u64;
+            if kind_matches && overlaps {
+                self.watch_hit = Some(*watchpoint);
+            }
         }
-        return 0;
     }
 
-    pub fn fetch(&mut self) -> Result<u32, ()> {
-        match self.load(self.pc, 32) {
-            Ok(inst) => Ok(inst),
-            Err(_e) => Err(()),
+    // Physical address of a load, store or instruction fetch at addr. ERL
+    // leaves kuseg unmapped, otherwise kuseg, kseg2 and kseg3 go through the
+    // MMU selected by Config.MT: none, a TLB or the fixed mapping.
+    pub fn mmu(&self, addr: u32, store: bool) -> Result<u32, Exception> {
+        // user mode sees kuseg only, supervisor mode kuseg and sseg
+        let accessible = match self.cp0.mode() {
+            Mode::Kernel => true,
+            Mode::Supervisor => addr < KUSEG_BASE + KUSEG_SIZE || (SSEG_BASE..SSEG_BASE + SSEG_SIZE).contains(&addr),
+            Mode::User => addr < KUSEG_BASE + KUSEG_SIZE,
+        };
+        if !accessible {
+            return Err(if store { Exception::AddressErrorStore(addr) } else { Exception::AddressErrorLoad(addr) });
+        }
+        if (KSEG0_BASE..KSEG0_BASE + KSEG0_SIZE).contains(&addr) {
+            return Ok(addr - KSEG0_BASE);
+        }
+        if (KSEG1_BASE..KSEG1_BASE + KSEG1_SIZE).contains(&addr) {
+            return Ok(addr - KSEG1_BASE);
         }
+        let kuseg = (KUSEG_BASE..KUSEG_BASE + KUSEG_SIZE).contains(&addr);
+        if kuseg && self.cp0.status & STATUS_ERL != 0 {
+            return Ok(addr);
+        }
+        match self.cp0.config & CONFIG_MT {
+            CONFIG_MT_TLB => {
+                let asid = self.cp0.entry_hi & ENTRY_HI_ASID;
+                self.cp0.tlb.translate(addr, asid, store).map_err(|miss| match (miss, store) {
+                    (TlbMiss::Refill, false) => Exception::TlbRefillLoad(addr),
+                    (TlbMiss::Refill, true) => Exception::TlbRefillStore(addr),
+                    (TlbMiss::Invalid, false) => Exception::TlbInvalidLoad(addr),
+                    (TlbMiss::Invalid, true) => Exception::TlbInvalidStore(addr),
+                    (TlbMiss::Modified, _) => Exception::TlbModified(addr),
+                })
+            }
+            // kseg2 and kseg3 are identity mapped
+            CONFIG_MT_FMT if kuseg => Ok(addr - KUSEG_BASE + FMT_KUSEG_BASE),
+            _ => Ok(addr),
+        }
+    }
+
+    // Physical address of the instruction at pc. An exception on the fetch
+    // is taken here and the handler is fetched instead.
+    fn fetch_addr(&mut self) -> Result<u32, CpuError> {
+        let translated = if self.pc & 3 != 0 {
+            Err(Exception::AddressErrorLoad(self.pc))
+        } else {
+            self.mmu(self.pc, false)
+        };
+        match translated {
+            Ok(physical_addr) => Ok(physical_addr),
+            Err(exception) => {
+                self.pc = self.pc.wrapping_add(4);
+                self.exception(exception)?;
+                // the exception vectors are unmapped kernel addresses
+                self.fetch_addr()
+            }
+        }
+    }
+
+    // not a data access, bypasses watchpoints
+    pub fn fetch(&mut self) -> Result<u32, CpuError> {
+        let physical_addr = self.fetch_addr()?;
+        self.bus.load(physical_addr, 32).map_err(|error| CpuError::Fetch { pc: self.pc, error })
+    }
+
+    // fetch through the decoded-instruction cache, for execute_decoded
+    pub fn fetch_decoded(&mut self) -> Result<Decoded, CpuError> {
+        let physical_addr = self.fetch_addr()?;
+        self.bus.fetch_decoded(physical_addr).map_err(|error| CpuError::Fetch { pc: self.pc, error })
+    }
+
+    // fetch the translated block at pc, for execute_block
+    pub fn fetch_block(&mut self) -> Result<Rc<Block>, CpuError> {
+        let physical_addr = self.fetch_addr()?;
+        self.bus.fetch_block(physical_addr).map_err(|error| CpuError::Fetch { pc: self.pc, error })
+    }
+
+    // Whether pc is the delay slot of a branch that has been executed
+    #[cfg(feature = "jit")]
+    pub fn in_delay_slot(&self) -> bool {
+        self.branch_delay.is_some()
     }
 
-    pub fn execute(&mut self, inst: u32) -> Result<(), ()> {
-        let opcode = (inst & 0xfc000000) >> 26;
-        let rs = ((inst & 0x03e00000) >> 21) as usize;
-        let rt = ((inst & 0x001f0000) >> 16) as usize;
-        let rd = ((inst & 0x0000f800) >> 11) as usize;
+    // Continue at pc as the delay slot of a branch executed elsewhere, taken
+    // to target or not taken
+    #[cfg(feature = "jit")]
+    pub fn enter_delay_slot(&mut self, target: Option<u32>) {
+        self.branch_delay = Some(match target {
+            Some(target) => BranchDelay::Taken(target),
+            None => BranchDelay::NotTaken,
+        });
+    }
+
+    // Schedule a branch taken after the delay slot. Called after pc has been
+    // advanced to the delay slot.
+    fn branch(&mut self, taken: bool, target: u32) {
+        if taken {
+            self.branch_delay = Some(BranchDelay::Taken(target));
+        } else {
+            self.branch_delay = Some(BranchDelay::NotTaken);
+        }
+    }
+
+    // Branch likely: the delay slot is nullified when the branch is not taken.
+    fn branch_likely(&mut self, taken: bool, target: u32) {
+        if taken {
+            self.branch_delay = Some(BranchDelay::Taken(target));
+        } else {
+            self.pc = self.pc.wrapping_add(4);
+        }
+    }
+
+    // Enter the general exception handler. Called after pc has been advanced
+    // past the faulting instruction.
+    fn exception(&mut self, exception: Exception) -> Result<(), CpuError> {
+        let pc = self.pc.wrapping_sub(4);
+        if let SyscallAction::Exit(status) = self.syscall_handler.exception(exception) {
+            // no handler runs, report the access
+            if let Exception::DataBusError { addr, error } = exception {
+                return Err(CpuError::DataBus { pc, addr, error });
+            }
+            return Err(CpuError::Halt { status });
+        }
+
+        if self.cp0.status & STATUS_EXL == 0 {
+            if self.branch_delay.is_some() {
+                // restart from the branch instruction
+                self.cp0.epc = pc.wrapping_sub(4);
+                self.cp0.cause |= CAUSE_BD;
+            } else {
+                self.cp0.epc = pc;
+                self.cp0.cause &= !CAUSE_BD;
+            }
+        }
+        self.branch_delay = None;
+
+        self.cp0.cause = (self.cp0.cause & !CAUSE_EXC_CODE) | (exception.exc_code() << CAUSE_EXC_CODE_SHIFT);
+        if let Some(addr) = exception.bad_vaddr() {
+            self.cp0.bad_vaddr = addr;
+        }
+        if let Exception::CoprocessorUnusable(unit) = exception {
+            self.cp0.cause = (self.cp0.cause & !CAUSE_CE) | (unit << CAUSE_CE_SHIFT);
+        }
+        if let Some(addr) = exception.tlb_vaddr() {
+            self.cp0.tlb_miss(addr);
+        }
+        // nested TLB misses go to the general exception vector
+        let offset = if exception.is_tlb_refill() && self.cp0.status & STATUS_EXL == 0 {
+            TLB_REFILL_OFFSET
+        } else {
+            GENERAL_EXCEPTION_OFFSET
+        };
+        self.cp0.status |= STATUS_EXL;
+
+        let base = if self.cp0.status & STATUS_BEV != 0 {
+            BOOT_EXCEPTION_BASE
+        } else {
+            EXCEPTION_BASE
+        };
+        self.pc = base + offset;
+
+        if self.trace {
+            println!(
+                "exception={:?}, epc={}, nextpc={}",
+                exception,
+                self.describe(self.cp0.epc),
+                self.describe(self.pc)
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn execute(&mut self, inst: u32) -> Result<(), CpuError> {
+        self.execute_decoded(decode(inst))
+    }
+
+    // Execute a block fetched at pc without returning to the fetch loop. The
+    // block is left early after an instruction that does not fall through to
+    // the next one, i.e. a taken branch past its delay slot, a nullified
+    // delay slot or an exception, and after a store to cached code.
+    pub fn execute_block(&mut self, block: &Block) -> Result<(), CpuError> {
+        let generation = self.bus.code_generation();
+        for handler in block.handlers.iter() {
+            let next = self.pc.wrapping_add(4);
+            self.pc = next;
+            handler(self)?;
+            if self.pc != next || self.bus.code_generation() != generation {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Execute the instruction at pc - 4 of a translated block, which only
+    // reads and writes general purpose registers
+    pub fn execute_register(&mut self, instruction: Instruction, operation: impl Fn(&mut [u32; 32])) -> Result<(), CpuError> {
+        let inst_pc = self.pc.wrapping_sub(4);
+        self.regs[0] = 0;
+        self.cp0.count = self.cp0.count.wrapping_add(1);
+        operation(&mut self.regs);
+        self.finish(instruction, false, inst_pc)
+    }
+
+    // Execute the conditional branch at pc - 4 of a translated block, taken
+    // to pc + offset when the registers meet condition
+    pub fn execute_branch(
+        &mut self,
+        instruction: Instruction,
+        offset: u32,
+        condition: impl Fn(&[u32; 32]) -> bool,
+    ) -> Result<(), CpuError> {
+        let inst_pc = self.pc.wrapping_sub(4);
+        self.regs[0] = 0;
+        self.cp0.count = self.cp0.count.wrapping_add(1);
+        self.branch(condition(&self.regs), self.pc.wrapping_add(offset));
+        self.finish(instruction, true, inst_pc)
+    }
+
+    // Execute the instruction at pc - 4, as returned by decode
+    pub fn execute_decoded(&mut self, decoded: Result<Instruction, DecodeError>) -> Result<(), CpuError> {
+        let inst_pc = self.pc.wrapping_sub(4);
         let mut is_branch = false;
 
         self.regs[0] = 0;
+        self.cp0.count = self.cp0.count.wrapping_add(1);
 
-        match opcode {
-            0x00 => {
-                let funct = inst & 0x0000003f;
-                match funct {
-                    0x00 => {
-                        // noop
-                        // sll
-                        let shamt = (inst & 0x000007c0) >> 6;
-                        self.regs[rd] = self.regs[rt].wrapping_shl(shamt);
-                    }
-                    0x02 => {
-                        //srl
-                        let shamt = (inst & 0x000007c0) >> 6;
-                        self.regs[rd] = self.regs[rt].wrapping_shr(shamt);
-                    }
-                    0x03 => {
-                        // sra
-                        let shamt = (inst & 0x000007c0) >> 6;
-                        self.regs[rd] = (self.regs[rt] as i32).wrapping_shr(shamt) as u32;
-                    }
-                    0x04 => {
-                        // sllv
-                        self.regs[rd] = self.regs[rt].wrapping_shl(self.regs[rs]);
-                    }
-                    0x06 => {
-                        // srlv
-                        self.regs[rd] = self.regs[rt].wrapping_shr(self.regs[rs]);
-                    }
-                    0x07 => {
-                        // srav
-                        self.regs[rd] = (self.regs[rt] as i32).wrapping_shr(self.regs[rs]) as u32;
-                    }
-                    0x08 => {
-                        // jr
-                        is_branch = true;
-                        self.pc_branch_delay = Some(self.regs[rs]);
-                    }
-                    0x09 => {
-                        // jalr
-                        is_branch = true;
-                        self.regs[rd] = self.pc.wrapping_add(4);
-                        self.pc_branch_delay = Some(self.regs[rs]);
-                    }
-                    0x0d => {
-                        // break
-                        // TODO: excpetion handling
-                    }
-                    0x10 => {
-                        // mfhi
-                        self.regs[rd] = self.hi;
-                    }
-                    0x11 => {
-                        // mthi
-                        self.hi = self.regs[rs];
-                    }
-                    0x12 => {
-                        // mflo
-                        self.regs[rd] = self.lo;
-                    }
-                    0x13 => {
-                        // mtlo
-                        self.lo = self.regs[rs];
-                    }
-                    0x18 => {
-                        // mult
-                        let product = ((self.regs[rs] as i32) as i64).wrapping_mul((self.regs[rt] as i32) as i64) as u64;
-                        self.hi = (product >> 32) as u32;
-                        self.lo = product as u32;
-                    }
-                    0x19 => {
-                        // multu
-                        let product = (self.regs[rs] as u64).wrapping_mul(self.regs[rt] as u64);
-                        self.hi = (product >> 32) as u32;
-                        self.lo = product as u32;
-                    }
-                    0x1a => {
-                        // div
-                        // If the divisor in GPRrt is zero, the arithmetic result value is undefined.
-                        if self.regs[rt] != 0 {
-                            self.lo = (self.regs[rs] as i32).wrapping_div(self.regs[rt] as i32) as u32;
-                            self.hi = (self.regs[rs] as i32).wrapping_rem(self.regs[rt] as i32) as u32;
-                        }
-                    }
-                    0x1b => {
-                        // divu
-                        // If the divisor in GPRrt is zero, the arithmetic result value is undefined.
-                        if self.regs[rt] != 0 {
-                            self.lo = self.regs[rs].wrapping_div(self.regs[rt]);
-                            self.hi = self.regs[rs].wrapping_rem(self.regs[rt]);
-                        }
-                    }
-                    0x21 => {
-                        // addu
-                        self.regs[rd] = self.regs[rs].wrapping_add(self.regs[rt]);
-                    }
-                    0x23 => {
-                        // subu
-                        self.regs[rd] = self.regs[rs].wrapping_sub(self.regs[rt]);
-                    }
-                    0x24 => {
-                        // and
-                        self.regs[rd] = self.regs[rs] & self.regs[rt];
-                    }
-                    0x25 => {
-                        // or
-                        self.regs[rd] = self.regs[rs] | self.regs[rt];
-                    }
-                    0x26 => {
-                        // xor
-                        self.regs[rd] = self.regs[rs] ^ self.regs[rt];
-                    }
-                    0x27 => {
-                        // nor
-                        self.regs[rd] = !(self.regs[rs] | self.regs[rt]);
-                    }
-                    0x2a => {
-                        // slt
-                        if (self.regs[rs] as i32) < (self.regs[rt] as i32) {
-                            self.regs[rd] = 1u32;
-                        } else {
-                            self.regs[rd] = 0u32;
-                        }
-                    }
-                    0x2b => {
-                        // sltu
-                        if self.regs[rs] < self.regs[rt] {
-                            self.regs[rd] = 1u32;
-                        } else {
-                            self.regs[rd] = 0u32;
-                        }
-                    }
-                    _ => {
-                        dbg!(format!("not implemented yet: opcode {:#x} funct {:#x}", opcode, funct));
-                        return Err(());
-                    }
+        let instruction = match decoded {
+            Ok(instruction) => instruction,
+            Err(error) => {
+                // the coprocessor 1 check applies to the whole opcode
+                if error.opcode() == 0x11 && self.cp0.status & STATUS_CU1 == 0 {
+                    return self.exception(Exception::CoprocessorUnusable(1));
                 }
+                return match self.exception(Exception::ReservedInstruction) {
+                    // no handler runs, e.g. with --linux, report the encoding
+                    Err(CpuError::Halt { .. }) => Err(CpuError::Decode { pc: inst_pc, error }),
+                    result => result,
+                };
             }
-            0x01 => {
-                let funct = rt;
-                match funct {
-                    0x00 => {
-                        // bltz
-                        is_branch = true;
-                        let offset = ((inst & 0x0000ffff) as i16) as u32;
-                        if (self.regs[rs] as i32) < 0 {
-                            self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
-                        }
-                    }
-                    0x01 => {
-                        // bgez
-                        is_branch = true;
-                        let offset = ((inst & 0x0000ffff) as i16) as u32;
-                        if (self.regs[rs] as i32) >= 0 {
-                            self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
-                        }
-                    }
-                    0x10 => {
-                        // bltzal
-                        is_branch = true;
-                        let offset = ((inst & 0x0000ffff) as i16) as u32;
-                        if (self.regs[rs] as i32) < 0 {
-                            self.regs[31] = self.pc.wrapping_add(4);
-                            self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
-                        }
-                    }
-                    0x11 => {
-                        // bgezal
-                        is_branch = true;
-                        let offset = ((inst & 0x0000ffff) as i16) as u32;
-                        if (self.regs[rs] as i32) >= 0 {
-                            self.regs[31] = self.pc.wrapping_add(4);
-                            self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
-                        }
-                    }
-                    _ => {
-                        dbg!(format!("not implemented yet: opcode {:#x} funct {:#x}", opcode, funct));
-                        return Err(());
-                    }
+        };
+
+        if instruction.is_privileged() && !self.cp0.usable() {
+            return self.exception(Exception::CoprocessorUnusable(0));
+        }
+
+        match instruction {
+            Instruction::Sll { rd, rt, sa } => {
+                // noop
+                self.regs[rd] = self.regs[rt].wrapping_shl(sa);
+            }
+            Instruction::Movf { rd, rs, cc } | Instruction::Movt { rd, rs, cc } => {
+                if self.cp0.status & STATUS_CU1 == 0 {
+                    return self.exception(Exception::CoprocessorUnusable(1));
+                }
+                let tf = matches!(instruction, Instruction::Movt { .. });
+                if self.cp1.condition(cc) == tf {
+                    self.regs[rd] = self.regs[rs];
                 }
             }
-            0x02 => {
-                // j
-                is_branch = true;
-                let target = inst & 0x03ffffff;
-                self.pc_branch_delay = Some((self.pc & 0xf0000000) | (target << 2));
+            Instruction::Srl { rd, rt, sa } => {
+                self.regs[rd] = self.regs[rt].wrapping_shr(sa);
+            }
+            Instruction::Rotr { rd, rt, sa } => {
+                if self.isa < IsaRevision::Release2 {
+                    return self.exception(Exception::ReservedInstruction);
+                }
+                self.regs[rd] = self.regs[rt].rotate_right(sa);
+            }
+            Instruction::Sra { rd, rt, sa } => {
+                self.regs[rd] = (self.regs[rt] as i32).wrapping_shr(sa) as u32;
+            }
+            Instruction::Sllv { rd, rt, rs } => {
+                self.regs[rd] = self.regs[rt].wrapping_shl(self.regs[rs]);
+            }
+            Instruction::Srlv { rd, rt, rs } => {
+                self.regs[rd] = self.regs[rt].wrapping_shr(self.regs[rs]);
             }
-            0x03 => {
-                // jal
+            Instruction::Rotrv { rd, rt, rs } => {
+                if self.isa < IsaRevision::Release2 {
+                    return self.exception(Exception::ReservedInstruction);
+                }
+                self.regs[rd] = self.regs[rt].rotate_right(self.regs[rs] & 0x1f);
+            }
+            Instruction::Srav { rd, rt, rs } => {
+                self.regs[rd] = (self.regs[rt] as i32).wrapping_shr(self.regs[rs]) as u32;
+            }
+            Instruction::Jr { rs } => {
                 is_branch = true;
-                self.regs[31] = self.pc.wrapping_add(4);
-                let target = inst & 0x03ffffff;
-                self.pc_branch_delay = Some((self.pc & 0xf0000000) | (target << 2));
+                self.branch(true, self.regs[rs]);
             }
-            0x04 => {
-                // beq
+            Instruction::Jalr { rd, rs } => {
                 is_branch = true;
-                let offset = ((inst & 0x0000ffff) as i16) as u32;
+                self.regs[rd] = self.pc.wrapping_add(4);
+                self.branch(true, self.regs[rs]);
+            }
+            Instruction::Movz { rd, rs, rt } => {
+                if self.regs[rt] == 0 {
+                    self.regs[rd] = self.regs[rs];
+                }
+            }
+            Instruction::Movn { rd, rs, rt } => {
+                if self.regs[rt] != 0 {
+                    self.regs[rd] = self.regs[rs];
+                }
+            }
+            Instruction::Syscall { code } => {
+                let mut context = SyscallContext {
+                    regs: &mut self.regs,
+                    hi: &mut self.hi,
+                    lo: &mut self.lo,
+                    user_local: &mut self.cp0.user_local,
+                    bus: &mut self.bus,
+                    pc: inst_pc,
+                    code,
+                };
+                match self.syscall_handler.syscall(&mut context) {
+                    SyscallAction::Exception => return self.exception(Exception::Syscall),
+                    SyscallAction::Continue => {}
+                    SyscallAction::Exit(status) => return Err(CpuError::Halt { status }),
+                }
+            }
+            Instruction::Break { .. } => {
+                return self.exception(Exception::Breakpoint);
+            }
+            Instruction::Mfhi { rd } => {
+                self.regs[rd] = self.hi;
+            }
+            Instruction::Mthi { rs } => {
+                self.hi = self.regs[rs];
+            }
+            Instruction::Mflo { rd } => {
+                self.regs[rd] = self.lo;
+            }
+            Instruction::Mtlo { rs } => {
+                self.lo = self.regs[rs];
+            }
+            Instruction::Mult { rs, rt } => {
+                let product = ((self.regs[rs] as i32) as i64).wrapping_mul((self.regs[rt] as i32) as i64) as u64;
+                self.hi = (product >> 32) as u32;
+                self.lo = product as u32;
+            }
+            Instruction::Multu { rs, rt } => {
+                let product = (self.regs[rs] as u64).wrapping_mul(self.regs[rt] as u64);
+                self.hi = (product >> 32) as u32;
+                self.lo = product as u32;
+            }
+            Instruction::Div { rs, rt } => {
+                // If the divisor in GPRrt is zero, the arithmetic result value is undefined.
+                if self.regs[rt] != 0 {
+                    self.lo = (self.regs[rs] as i32).wrapping_div(self.regs[rt] as i32) as u32;
+                    self.hi = (self.regs[rs] as i32).wrapping_rem(self.regs[rt] as i32) as u32;
+                }
+            }
+            Instruction::Divu { rs, rt } => {
+                // If the divisor in GPRrt is zero, the arithmetic result value is undefined.
+                if self.regs[rt] != 0 {
+                    self.lo = self.regs[rs].wrapping_div(self.regs[rt]);
+                    self.hi = self.regs[rs].wrapping_rem(self.regs[rt]);
+                }
+            }
+            Instruction::Add { rd, rs, rt } => match (self.regs[rs] as i32).checked_add(self.regs[rt] as i32) {
+                Some(sum) => self.regs[rd] = sum as u32,
+                None => return self.exception(Exception::IntegerOverflow),
+            },
+            Instruction::Addu { rd, rs, rt } => {
+                self.regs[rd] = self.regs[rs].wrapping_add(self.regs[rt]);
+            }
+            Instruction::Sub { rd, rs, rt } => match (self.regs[rs] as i32).checked_sub(self.regs[rt] as i32) {
+                Some(difference) => self.regs[rd] = difference as u32,
+                None => return self.exception(Exception::IntegerOverflow),
+            },
+            Instruction::Subu { rd, rs, rt } => {
+                self.regs[rd] = self.regs[rs].wrapping_sub(self.regs[rt]);
+            }
+            Instruction::And { rd, rs, rt } => {
+                self.regs[rd] = self.regs[rs] & self.regs[rt];
+            }
+            Instruction::Or { rd, rs, rt } => {
+                self.regs[rd] = self.regs[rs] | self.regs[rt];
+            }
+            Instruction::Xor { rd, rs, rt } => {
+                self.regs[rd] = self.regs[rs] ^ self.regs[rt];
+            }
+            Instruction::Nor { rd, rs, rt } => {
+                self.regs[rd] = !(self.regs[rs] | self.regs[rt]);
+            }
+            Instruction::Slt { rd, rs, rt } => {
+                self.regs[rd] = ((self.regs[rs] as i32) < (self.regs[rt] as i32)) as u32;
+            }
+            Instruction::Sltu { rd, rs, rt } => {
+                self.regs[rd] = (self.regs[rs] < self.regs[rt]) as u32;
+            }
+            Instruction::Tge { rs, rt, .. } => {
+                if (self.regs[rs] as i32) >= (self.regs[rt] as i32) {
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Tgeu { rs, rt, .. } => {
+                if self.regs[rs] >= self.regs[rt] {
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Tlt { rs, rt, .. } => {
+                if (self.regs[rs] as i32) < (self.regs[rt] as i32) {
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Tltu { rs, rt, .. } => {
+                if self.regs[rs] < self.regs[rt] {
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Teq { rs, rt, .. } => {
                 if self.regs[rs] == self.regs[rt] {
-                    self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
+                    return self.exception(Exception::Trap);
                 }
             }
-            0x05 => {
-                // bne
-                is_branch = true;
-                let offset = ((inst & 0x0000ffff) as i16) as u32;
+            Instruction::Tne { rs, rt, .. } => {
                 if self.regs[rs] != self.regs[rt] {
-                    self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
+                    return self.exception(Exception::Trap);
                 }
             }
-            0x06 => {
-                // blez
+            Instruction::Bltz { rs, offset } => {
                 is_branch = true;
-                let offset = ((inst & 0x0000ffff) as i16) as u32;
-                if (self.regs[rs] as i32) <= 0 {
-                    self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
-                }
+                self.branch((self.regs[rs] as i32) < 0, self.pc.wrapping_add(offset));
             }
-            0x07 => {
-                // bgtz
+            Instruction::Bgez { rs, offset } => {
                 is_branch = true;
-                let offset = ((inst & 0x0000ffff) as i16) as u32;
-                if (self.regs[rs] as i32) > 0 {
-                    self.pc_branch_delay = Some(self.pc.wrapping_add(offset << 2));
+                self.branch((self.regs[rs] as i32) >= 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bltzl { rs, offset } => {
+                is_branch = true;
+                self.branch_likely((self.regs[rs] as i32) < 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bgezl { rs, offset } => {
+                is_branch = true;
+                self.branch_likely((self.regs[rs] as i32) >= 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Tgei { rs, imm } => {
+                if (self.regs[rs] as i32) >= (imm as i32) {
+                    return self.exception(Exception::Trap);
                 }
             }
-            0x09 => {
-                // addiu
-                let imm = ((inst & 0x0000ffff) as i16) as u32;
-                self.regs[rt] = self.regs[rs].wrapping_add(imm);
+            Instruction::Tgeiu { rs, imm } => {
+                if self.regs[rs] >= imm {
+                    return self.exception(Exception::Trap);
+                }
             }
-            0x0a => {
-                // slti
-                let imm = ((inst & 0x0000ffff) as i16) as i32;
-                if (self.regs[rs] as i32) < imm {
-                    self.regs[rt] = 1u32;
-                } else {
-                    self.regs[rt] = 0u32;
+            Instruction::Tlti { rs, imm } => {
+                if (self.regs[rs] as i32) < (imm as i32) {
+                    return self.exception(Exception::Trap);
                 }
             }
-            0x0b => {
-                // sltiu
-                let imm = inst & 0x0000ffff;
+            Instruction::Tltiu { rs, imm } => {
                 if self.regs[rs] < imm {
-                    self.regs[rt] = 1u32;
-                } else {
-                    self.regs[rt] = 0u32;
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Teqi { rs, imm } => {
+                if self.regs[rs] == imm {
+                    return self.exception(Exception::Trap);
+                }
+            }
+            Instruction::Tnei { rs, imm } => {
+                if self.regs[rs] != imm {
+                    return self.exception(Exception::Trap);
                 }
             }
-            0x0c => {
-                // andi
-                let imm = inst & 0x0000ffff;
+            Instruction::Bltzal { rs, offset } => {
+                is_branch = true;
+                let taken = (self.regs[rs] as i32) < 0;
+                self.regs[31] = self.pc.wrapping_add(4);
+                self.branch(taken, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bgezal { rs, offset } => {
+                is_branch = true;
+                let taken = (self.regs[rs] as i32) >= 0;
+                self.regs[31] = self.pc.wrapping_add(4);
+                self.branch(taken, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bltzall { rs, offset } => {
+                is_branch = true;
+                let taken = (self.regs[rs] as i32) < 0;
+                self.regs[31] = self.pc.wrapping_add(4);
+                self.branch_likely(taken, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bgezall { rs, offset } => {
+                is_branch = true;
+                let taken = (self.regs[rs] as i32) >= 0;
+                self.regs[31] = self.pc.wrapping_add(4);
+                self.branch_likely(taken, self.pc.wrapping_add(offset));
+            }
+            Instruction::J { target } => {
+                is_branch = true;
+                self.branch(true, (self.pc & 0xf0000000) | target);
+            }
+            Instruction::Jal { target } => {
+                is_branch = true;
+                self.regs[31] = self.pc.wrapping_add(4);
+                self.branch(true, (self.pc & 0xf0000000) | target);
+            }
+            Instruction::Beq { rs, rt, offset } => {
+                is_branch = true;
+                self.branch(self.regs[rs] == self.regs[rt], self.pc.wrapping_add(offset));
+            }
+            Instruction::Bne { rs, rt, offset } => {
+                is_branch = true;
+                self.branch(self.regs[rs] != self.regs[rt], self.pc.wrapping_add(offset));
+            }
+            Instruction::Blez { rs, offset } => {
+                is_branch = true;
+                self.branch((self.regs[rs] as i32) <= 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bgtz { rs, offset } => {
+                is_branch = true;
+                self.branch((self.regs[rs] as i32) > 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Addi { rt, rs, imm } => match (self.regs[rs] as i32).checked_add(imm as i32) {
+                Some(sum) => self.regs[rt] = sum as u32,
+                None => return self.exception(Exception::IntegerOverflow),
+            },
+            Instruction::Addiu { rt, rs, imm } => {
+                self.regs[rt] = self.regs[rs].wrapping_add(imm);
+            }
+            Instruction::Slti { rt, rs, imm } => {
+                self.regs[rt] = ((self.regs[rs] as i32) < (imm as i32)) as u32;
+            }
+            Instruction::Sltiu { rt, rs, imm } => {
+                self.regs[rt] = (self.regs[rs] < imm) as u32;
+            }
+            Instruction::Andi { rt, rs, imm } => {
                 self.regs[rt] = self.regs[rs] & imm;
             }
-            0x0d => {
-                // ori
-                let imm = inst & 0x0000ffff;
+            Instruction::Ori { rt, rs, imm } => {
                 self.regs[rt] = self.regs[rs] | imm;
             }
-            0x0e => {
-                // xori
-                let imm = inst & 0x0000ffff;
+            Instruction::Xori { rt, rs, imm } => {
                 self.regs[rt] = self.regs[rs] ^ imm;
             }
-            0x0f => {
-                // lui
-                let imm = inst & 0x0000ffff;
+            Instruction::Lui { rt, imm } => {
                 self.regs[rt] = imm << 16;
             }
-            0x1c => {
-                let funct = inst & 0x0000003f;
-                match funct {
-                    0x0 => {
-                        // madd
-                        let product = ((self.regs[rs] as i32) as i64).wrapping_mul((self.regs[rt] as i32) as i64) as u64;
-                        let acc = product.wrapping_add((self.hi as u64).wrapping_shl(32) + self.lo as u64);
-                        self.hi = (acc >> 32) as u32;
-                        self.lo = acc as u32;
+            Instruction::Beql { rs, rt, offset } => {
+                is_branch = true;
+                self.branch_likely(self.regs[rs] == self.regs[rt], self.pc.wrapping_add(offset));
+            }
+            Instruction::Bnel { rs, rt, offset } => {
+                is_branch = true;
+                self.branch_likely(self.regs[rs] != self.regs[rt], self.pc.wrapping_add(offset));
+            }
+            Instruction::Blezl { rs, offset } => {
+                is_branch = true;
+                self.branch_likely((self.regs[rs] as i32) <= 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Bgtzl { rs, offset } => {
+                is_branch = true;
+                self.branch_likely((self.regs[rs] as i32) > 0, self.pc.wrapping_add(offset));
+            }
+            Instruction::Mfc0 { rt, rd, sel } => {
+                self.regs[rt] = self.cp0.read(rd, sel);
+            }
+            Instruction::Mtc0 { rt, rd, sel } => {
+                self.cp0.write(rd, sel, self.regs[rt]);
+            }
+            Instruction::Di { rt } | Instruction::Ei { rt } => {
+                if self.isa < IsaRevision::Release2 {
+                    return self.exception(Exception::ReservedInstruction);
+                }
+                self.regs[rt] = self.cp0.status;
+                if let Instruction::Ei { .. } = instruction {
+                    self.cp0.status |= STATUS_IE;
+                } else {
+                    self.cp0.status &= !STATUS_IE;
+                }
+            }
+            Instruction::Tlbr => self.cp0.tlb_read(),
+            Instruction::Tlbwi => self.cp0.tlb_write(self.cp0.index),
+            Instruction::Tlbwr => self.cp0.tlb_write(self.cp0.random()),
+            Instruction::Tlbp => self.cp0.tlb_probe(),
+            Instruction::Eret => {
+                if self.cp0.status & STATUS_ERL != 0 {
+                    self.pc = self.cp0.error_epc;
+                    self.cp0.status &= !STATUS_ERL;
+                } else {
+                    self.pc = self.cp0.epc;
+                    self.cp0.status &= !STATUS_EXL;
+                }
+                self.ll_bit = false;
+            }
+            Instruction::Mfc1 { .. }
+            | Instruction::Cfc1 { .. }
+            | Instruction::Mfhc1 { .. }
+            | Instruction::Mtc1 { .. }
+            | Instruction::Ctc1 { .. }
+            | Instruction::Mthc1 { .. }
+            | Instruction::Bc1f { .. }
+            | Instruction::Bc1t { .. }
+            | Instruction::Bc1fl { .. }
+            | Instruction::Bc1tl { .. }
+            | Instruction::AddFmt { .. }
+            | Instruction::SubFmt { .. }
+            | Instruction::MulFmt { .. }
+            | Instruction::DivFmt { .. }
+            | Instruction::SqrtFmt { .. }
+            | Instruction::AbsFmt { .. }
+            | Instruction::MovFmt { .. }
+            | Instruction::NegFmt { .. }
+            | Instruction::RoundW { .. }
+            | Instruction::TruncW { .. }
+            | Instruction::CeilW { .. }
+            | Instruction::FloorW { .. }
+            | Instruction::MovfFmt { .. }
+            | Instruction::MovtFmt { .. }
+            | Instruction::MovzFmt { .. }
+            | Instruction::MovnFmt { .. }
+            | Instruction::CvtS { .. }
+            | Instruction::CvtD { .. }
+            | Instruction::CvtW { .. }
+            | Instruction::CFmt { .. } => {
+                if self.cp0.status & STATUS_CU1 == 0 {
+                    return self.exception(Exception::CoprocessorUnusable(1));
+                }
+                is_branch = matches!(
+                    instruction,
+                    Instruction::Bc1f { .. } | Instruction::Bc1t { .. } | Instruction::Bc1fl { .. } | Instruction::Bc1tl { .. }
+                );
+                return self.execute_cop1(instruction, is_branch, inst_pc);
+            }
+            Instruction::Madd { rs, rt } => {
+                let product = ((self.regs[rs] as i32) as i64).wrapping_mul((self.regs[rt] as i32) as i64) as u64;
+                let acc = product.wrapping_add((self.hi as u64).wrapping_shl(32) + self.lo as u64);
+                self.hi = (acc >> 32) as u32;
+                self.lo = acc as u32;
+            }
+            Instruction::Maddu { rs, rt } => {
+                let product = (self.regs[rs] as u64).wrapping_mul(self.regs[rt] as u64);
+                let acc = product.wrapping_add((self.hi as u64).wrapping_shl(32) + self.lo as u64);
+                self.hi = (acc >> 32) as u32;
+                self.lo = acc as u32;
+            }
+            Instruction::Mul { rd, rs, rt } => {
+                self.regs[rd] = ((self.regs[rs] as i64).wrapping_mul(self.regs[rt] as i64)) as u32;
+            }
+            Instruction::Clz { rd, rs } => {
+                self.regs[rd] = self.regs[rs].leading_zeros();
+            }
+            Instruction::Clo { rd, rs } => {
+                self.regs[rd] = self.regs[rs].leading_ones();
+            }
+            Instruction::Ext { .. }
+            | Instruction::Ins { .. }
+            | Instruction::Wsbh { .. }
+            | Instruction::Seb { .. }
+            | Instruction::Seh { .. }
+                if self.isa < IsaRevision::Release2 =>
+            {
+                return self.exception(Exception::ReservedInstruction);
+            }
+            Instruction::Rdhwr { .. } if self.isa < IsaRevision::Release2 => {
+                return self.reserved_instruction(instruction, inst_pc);
+            }
+            Instruction::Ext { rt, rs, pos, size } => {
+                self.regs[rt] = (self.regs[rs] >> pos) & (u32::MAX >> (32 - size));
+            }
+            Instruction::Ins { rt, rs, pos, size } => {
+                if size > 0 {
+                    let mask = (u32::MAX >> (32 - size)) << pos;
+                    self.regs[rt] = (self.regs[rt] & !mask) | ((self.regs[rs] << pos) & mask);
+                }
+            }
+            Instruction::Wsbh { rd, rt } => {
+                let value = self.regs[rt];
+                self.regs[rd] = ((value & 0x00ff00ff) << 8) | ((value >> 8) & 0x00ff00ff);
+            }
+            Instruction::Seb { rd, rt } => {
+                self.regs[rd] = ((self.regs[rt] as u8) as i8) as u32;
+            }
+            Instruction::Seh { rd, rt } => {
+                self.regs[rd] = ((self.regs[rt] as u16) as i16) as u32;
+            }
+            Instruction::Rdhwr { rt, rd } => {
+                match rd {
+                    0x00 => {
+                        // CPUNum
+                        self.regs[rt] = 0u32;
                     }
-                    0x1 => {
-                        // maddu
-                        let product = (self.regs[rs] as u64).wrapping_mul(self.regs[rt] as u64);
-                        let acc = product.wrapping_add((self.hi as u64).wrapping_shl(32) + self.lo as u64);
-                        self.hi = (acc >> 32) as u32;
-                        self.lo = acc as u32;
+                    0x01 => {
+                        // SYNCI_Step, no caches to synchronize
+                        self.regs[rt] = 0u32;
                     }
-                    0x2 => {
-                        // mul
-                        self.regs[rd] = ((self.regs[rs] as i64).wrapping_mul(self.regs[rt] as i64)) as u32;
+                    0x02 => {
+                        // CC
+                        self.regs[rt] = self.cp0.count;
                     }
-                    0x20 => {
-                        // clz
-                        self.regs[rt] = self.regs[rs].leading_zeros();
+                    0x03 => {
+                        // CCRes, count increments every instruction
+                        self.regs[rt] = 1u32;
                     }
-                    0x21 => {
-                        // clo
-                        self.regs[rt] = self.regs[rs].leading_ones();
+                    0x1d => {
+                        // ULR
+                        self.regs[rt] = self.cp0.user_local;
                     }
-                    _ => {
-                        dbg!(format!("not implemented yet: opcode {:#x} funct {:#x}", opcode, funct));
-                        return Err(());
+                    _ => return self.reserved_instruction(instruction, inst_pc),
+                }
+            }
+            Instruction::Lb { rt, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 8) {
+                Ok(value) => self.regs[rt] = ((value as u8) as i8) as u32,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Lh { rt, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 16) {
+                Ok(value) => self.regs[rt] = ((value as u16) as i16) as u32,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Lwl { rt, base, offset } => {
+                // little endian: bytes from the aligned word up to addr fill rt from the most significant byte
+                let addr = self.regs[base].wrapping_add(offset);
+                let shift = 8 * (3 - (addr & 3));
+                let mut value = self.regs[rt];
+                for byte_addr in (addr & !3)..=addr {
+                    match self.load(byte_addr, 8) {
+                        Ok(byte) => {
+                            let pos = shift + 8 * (byte_addr & 3);
+                            value = (value & !(0xff << pos)) | (byte << pos);
+                        }
+                        Err(exception) => return self.exception(exception),
+                    }
+                }
+                self.regs[rt] = value;
+            }
+            Instruction::Lw { rt, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 32) {
+                Ok(value) => self.regs[rt] = value,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Lbu { rt, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 8) {
+                Ok(value) => self.regs[rt] = value,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Lhu { rt, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 16) {
+                Ok(value) => self.regs[rt] = value,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Lwr { rt, base, offset } => {
+                // little endian: bytes from addr up to the end of the aligned word fill rt from the least significant byte
+                let addr = self.regs[base].wrapping_add(offset);
+                let shift = 8 * (addr & 3);
+                let mut value = self.regs[rt];
+                for byte_addr in addr..=(addr | 3) {
+                    match self.load(byte_addr, 8) {
+                        Ok(byte) => {
+                            let pos = 8 * (byte_addr & 3) - shift;
+                            value = (value & !(0xff << pos)) | (byte << pos);
+                        }
+                        Err(exception) => return self.exception(exception),
                     }
                 }
+                self.regs[rt] = value;
+            }
+            Instruction::Sb { rt, base, offset } => {
+                if let Err(exception) = self.store(self.regs[base].wrapping_add(offset), 8, self.regs[rt]) {
+                    return self.exception(exception);
+                }
+            }
+            Instruction::Sh { rt, base, offset } => {
+                if let Err(exception) = self.store(self.regs[base].wrapping_add(offset), 16, self.regs[rt]) {
+                    return self.exception(exception);
+                }
+            }
+            Instruction::Swl { rt, base, offset } => {
+                // little endian: the most significant bytes of rt are stored from the aligned word up to addr
+                let addr = self.regs[base].wrapping_add(offset);
+                let shift = 8 * (3 - (addr & 3));
+                for byte_addr in (addr & !3)..=addr {
+                    let pos = shift + 8 * (byte_addr & 3);
+                    if let Err(exception) = self.store(byte_addr, 8, self.regs[rt] >> pos) {
+                        return self.exception(exception);
+                    }
+                }
+            }
+            Instruction::Sw { rt, base, offset } => {
+                if let Err(exception) = self.store(self.regs[base].wrapping_add(offset), 32, self.regs[rt]) {
+                    return self.exception(exception);
+                }
+            }
+            Instruction::Swr { rt, base, offset } => {
+                // little endian: the least significant bytes of rt are stored from addr up to the end of the aligned word
+                let addr = self.regs[base].wrapping_add(offset);
+                let shift = 8 * (addr & 3);
+                for byte_addr in addr..=(addr | 3) {
+                    let pos = 8 * (byte_addr & 3) - shift;
+                    if let Err(exception) = self.store(byte_addr, 8, self.regs[rt] >> pos) {
+                        return self.exception(exception);
+                    }
+                }
+            }
+            Instruction::Ll { rt, base, offset } => {
+                let addr = self.regs[base].wrapping_add(offset);
+                match self.load(addr, 32) {
+                    Ok(value) => {
+                        self.regs[rt] = value;
+                        self.ll_bit = true;
+                        self.ll_addr = self.mmu(addr, false).unwrap_or(addr);
+                    }
+                    Err(exception) => return self.exception(exception),
+                }
+            }
+            Instruction::Sc { rt, base, offset } => {
+                if self.ll_bit {
+                    if let Err(exception) = self.store(self.regs[base].wrapping_add(offset), 32, self.regs[rt]) {
+                        return self.exception(exception);
+                    }
+                    self.ll_bit = false;
+                    self.regs[rt] = 1u32;
+                } else {
+                    self.regs[rt] = 0u32;
+                }
+            }
+            // loads and stores complete in order and there are no caches
+            Instruction::Sync { .. } | Instruction::Cache { .. } | Instruction::Pref { .. } => {}
+            Instruction::Lwc1 { .. } | Instruction::Ldc1 { .. } | Instruction::Swc1 { .. } | Instruction::Sdc1 { .. } => {
+                if self.cp0.status & STATUS_CU1 == 0 {
+                    return self.exception(Exception::CoprocessorUnusable(1));
+                }
+                return self.execute_cop1(instruction, false, inst_pc);
+            }
+        }
+
+        self.finish(instruction, is_branch, inst_pc)
+    }
+
+    // Raise Reserved Instruction for the instruction at inst_pc, unless the
+    // syscall handler emulates it the way a kernel would
+    fn reserved_instruction(&mut self, instruction: Instruction, inst_pc: u32) -> Result<(), CpuError> {
+        let mut context = SyscallContext {
+            regs: &mut self.regs,
+            hi: &mut self.hi,
+            lo: &mut self.lo,
+            user_local: &mut self.cp0.user_local,
+            bus: &mut self.bus,
+            pc: inst_pc,
+            code: 0,
+        };
+        match self.syscall_handler.reserved_instruction(instruction, &mut context) {
+            SyscallAction::Exception => self.exception(Exception::ReservedInstruction),
+            SyscallAction::Continue => self.finish(instruction, false, inst_pc),
+            SyscallAction::Exit(status) => Err(CpuError::Halt { status }),
+        }
+    }
 
+    // Floating point instructions, coprocessor 1 is usable
+    fn execute_cop1(&mut self, instruction: Instruction, is_branch: bool, inst_pc: u32) -> Result<(), CpuError> {
+        match instruction {
+            Instruction::Mfc1 { rt, fs } => {
+                self.regs[rt] = self.cp1.fpr[fs];
             }
-            0x20 => {
-                // lb
-                let imm = ((inst & 0x0000ffff) as i16) as u32;
-                self.regs[rt] = self.load(self.regs[rs].wrapping_add(imm), 8)?
+            Instruction::Cfc1 { rt, fs } => {
+                self.regs[rt] = self.cp1.read_control(fs);
             }
-            0x23 => {
-                // lw
-                let imm = ((inst & 0x0000ffff) as i16) as u32;
-                self.regs[rt] = self.load(self.regs[rs].wrapping_add(imm), 32)?
+            Instruction::Mfhc1 { rt, fs } => {
+                if self.isa < IsaRevision::Release2 {
+                    return self.exception(Exception::ReservedInstruction);
+                }
+                self.regs[rt] = self.cp1.fpr[fs | 1];
+            }
+            Instruction::Mtc1 { rt, fs } => {
+                self.cp1.fpr[fs] = self.regs[rt];
             }
-            0x28 => {
-                // sb
-                let imm = ((inst & 0x0000ffff) as i16) as u32;
-                self.store(self.regs[rs].wrapping_add(imm), 8, self.regs[rt])?
+            Instruction::Ctc1 { rt, fs } => {
+                if self.cp1.write_control(fs, self.regs[rt]) {
+                    return self.exception(Exception::FloatingPoint);
+                }
+            }
+            Instruction::Mthc1 { rt, fs } => {
+                if self.isa < IsaRevision::Release2 {
+                    return self.exception(Exception::ReservedInstruction);
+                }
+                self.cp1.fpr[fs | 1] = self.regs[rt];
             }
-            0x2b => {
-                // sw
-                let imm = ((inst & 0x0000ffff) as i16) as u32;
-                self.store(self.regs[rs].wrapping_add(imm), 32, self.regs[rt])?
+            Instruction::Bc1f { cc, offset } => {
+                self.branch(!self.cp1.condition(cc), self.pc.wrapping_add(offset));
             }
-            _ => {
-                dbg!(format!("not implemented yet: opcode {:#x}", opcode));
-                return Err(());
+            Instruction::Bc1t { cc, offset } => {
+                self.branch(self.cp1.condition(cc), self.pc.wrapping_add(offset));
             }
+            Instruction::Bc1fl { cc, offset } => {
+                self.branch_likely(!self.cp1.condition(cc), self.pc.wrapping_add(offset));
+            }
+            Instruction::Bc1tl { cc, offset } => {
+                self.branch_likely(self.cp1.condition(cc), self.pc.wrapping_add(offset));
+            }
+            Instruction::MovFmt { fmt, fd, fs } => {
+                let a = self.cp1.read(fmt, fs);
+                self.cp1.write(fmt, fd, a);
+            }
+            Instruction::MovfFmt { fmt, fd, fs, cc } => {
+                if !self.cp1.condition(cc) {
+                    let a = self.cp1.read(fmt, fs);
+                    self.cp1.write(fmt, fd, a);
+                }
+            }
+            Instruction::MovtFmt { fmt, fd, fs, cc } => {
+                if self.cp1.condition(cc) {
+                    let a = self.cp1.read(fmt, fs);
+                    self.cp1.write(fmt, fd, a);
+                }
+            }
+            Instruction::MovzFmt { fmt, fd, fs, rt } => {
+                if self.regs[rt] == 0 {
+                    let a = self.cp1.read(fmt, fs);
+                    self.cp1.write(fmt, fd, a);
+                }
+            }
+            Instruction::MovnFmt { fmt, fd, fs, rt } => {
+                if self.regs[rt] != 0 {
+                    let a = self.cp1.read(fmt, fs);
+                    self.cp1.write(fmt, fd, a);
+                }
+            }
+            Instruction::CFmt { fmt, cond, cc, fs, ft } => {
+                let (condition, flags) = compare(fmt, self.cp1.read(fmt, fs), self.cp1.read(fmt, ft), cond);
+                if self.cp1.signal(flags) {
+                    return self.exception(Exception::FloatingPoint);
+                }
+                self.cp1.set_condition(cc, condition);
+            }
+            Instruction::AddFmt { fmt, fd, fs, ft }
+            | Instruction::SubFmt { fmt, fd, fs, ft }
+            | Instruction::MulFmt { fmt, fd, fs, ft }
+            | Instruction::DivFmt { fmt, fd, fs, ft } => {
+                let operation = match instruction {
+                    Instruction::AddFmt { .. } => Operation::Add,
+                    Instruction::SubFmt { .. } => Operation::Sub,
+                    Instruction::MulFmt { .. } => Operation::Mul,
+                    _ => Operation::Div,
+                };
+                let (a, b) = (self.cp1.read(fmt, fs), self.cp1.read(fmt, ft));
+                let (result, flags) = arithmetic(operation, fmt, a, b, self.cp1.rounding_mode());
+                if self.cp1.signal(flags) {
+                    return self.exception(Exception::FloatingPoint);
+                }
+                self.cp1.write(fmt, fd, result);
+            }
+            Instruction::SqrtFmt { fmt, fd, fs }
+            | Instruction::AbsFmt { fmt, fd, fs }
+            | Instruction::NegFmt { fmt, fd, fs }
+            | Instruction::RoundW { fmt, fd, fs }
+            | Instruction::TruncW { fmt, fd, fs }
+            | Instruction::CeilW { fmt, fd, fs }
+            | Instruction::FloorW { fmt, fd, fs }
+            | Instruction::CvtS { fmt, fd, fs }
+            | Instruction::CvtD { fmt, fd, fs }
+            | Instruction::CvtW { fmt, fd, fs } => {
+                let rm = self.cp1.rounding_mode();
+                let a = self.cp1.read(fmt, fs);
+                let (to, (result, flags)) = match instruction {
+                    Instruction::SqrtFmt { .. } => (fmt, arithmetic(Operation::Sqrt, fmt, a, a, rm)),
+                    Instruction::AbsFmt { .. } => (fmt, sign(fmt, a, false)),
+                    Instruction::NegFmt { .. } => (fmt, sign(fmt, a, true)),
+                    Instruction::RoundW { .. } => (Format::Word, convert(fmt, Format::Word, a, RoundingMode::Nearest)),
+                    Instruction::TruncW { .. } => (Format::Word, convert(fmt, Format::Word, a, RoundingMode::Zero)),
+                    Instruction::CeilW { .. } => (Format::Word, convert(fmt, Format::Word, a, RoundingMode::PlusInfinity)),
+                    Instruction::FloorW { .. } => (Format::Word, convert(fmt, Format::Word, a, RoundingMode::MinusInfinity)),
+                    Instruction::CvtS { .. } => (Format::Single, convert(fmt, Format::Single, a, rm)),
+                    Instruction::CvtD { .. } => (Format::Double, convert(fmt, Format::Double, a, rm)),
+                    _ => (Format::Word, convert(fmt, Format::Word, a, rm)),
+                };
+                if self.cp1.signal(flags) {
+                    return self.exception(Exception::FloatingPoint);
+                }
+                self.cp1.write(to, fd, result);
+            }
+            Instruction::Lwc1 { ft, base, offset } => match self.load(self.regs[base].wrapping_add(offset), 32) {
+                Ok(value) => self.cp1.fpr[ft] = value,
+                Err(exception) => return self.exception(exception),
+            },
+            Instruction::Ldc1 { ft, base, offset } => {
+                let addr = self.regs[base].wrapping_add(offset);
+                if addr & 0x7 != 0 {
+                    return self.exception(Exception::AddressErrorLoad(addr));
+                }
+                let low = match self.load(addr, 32) {
+                    Ok(value) => value,
+                    Err(exception) => return self.exception(exception),
+                };
+                let high = match self.load(addr.wrapping_add(4), 32) {
+                    Ok(value) => value,
+                    Err(exception) => return self.exception(exception),
+                };
+                self.cp1.write(Format::Double, ft, (low as u64) | ((high as u64) << 32));
+            }
+            Instruction::Swc1 { ft, base, offset } => {
+                if let Err(exception) = self.store(self.regs[base].wrapping_add(offset), 32, self.cp1.fpr[ft]) {
+                    return self.exception(exception);
+                }
+            }
+            Instruction::Sdc1 { ft, base, offset } => {
+                let addr = self.regs[base].wrapping_add(offset);
+                if addr & 0x7 != 0 {
+                    return self.exception(Exception::AddressErrorStore(addr));
+                }
+                let value = self.cp1.read(Format::Double, ft);
+                if let Err(exception) = self.store(addr, 32, value as u32) {
+                    return self.exception(exception);
+                }
+                if let Err(exception) = self.store(addr.wrapping_add(4), 32, (value >> 32) as u32) {
+                    return self.exception(exception);
+                }
+            }
+            _ => unreachable!("{:?} is not a coprocessor 1 instruction", instruction),
         }
 
+        self.finish(instruction, is_branch, inst_pc)
+    }
+
+    // Complete an instruction that did not raise an exception
+    fn finish(&mut self, instruction: Instruction, is_branch: bool, inst_pc: u32) -> Result<(), CpuError> {
         // assume there's not branch instruction in branch delay slot
         if !is_branch {
-            match self.pc_branch_delay {
-                Some(pc) => {
-                    // current instruction is in branch delay slot
-                    self.pc = pc;
-                    self.pc_branch_delay = None;
-                }
-                None => {}
+            // current instruction is in branch delay slot
+            if let Some(BranchDelay::Taken(pc)) = self.branch_delay.take() {
+                self.pc = pc;
             }
         }
 
-        println!("{}", format!(
-            "nextpc={:#x}, opcode={:#x}, rs={}, rt={}, rd={}",
-            self.pc, opcode, rs, rt, rd
-        ));
+        if self.trace {
+            println!(
+                "pc={}, inst={}, nextpc={}",
+                self.describe(inst_pc),
+                format_instruction(&instruction, inst_pc, &self.debug_info).replace('\t', " "),
+                self.describe(self.pc)
+            );
+        }
 
         Ok(())
     }
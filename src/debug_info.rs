@@ -0,0 +1,388 @@
+use crate::elf::*;
+use crate::error::*;
+
+// DWARF line number program opcodes
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// DWARF 5 directory and file name entry formats
+const DW_LNCT_PATH: u64 = 1;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> Self {
+        Self { data, offset }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DwarfError> {
+        let truncated = DwarfError::Truncated { offset: self.offset };
+        let end = self.offset.checked_add(len).ok_or(truncated)?;
+        let bytes = self.data.get(self.offset..end).ok_or(truncated)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DwarfError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DwarfError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DwarfError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, DwarfError> {
+        Ok(self.u32()? as u64 | ((self.u32()? as u64) << 32))
+    }
+
+    // 4 bytes in 32-bit DWARF, 8 bytes in 64-bit DWARF
+    fn offset(&mut self, offset_size: usize) -> Result<usize, DwarfError> {
+        if offset_size == 8 {
+            Ok(self.u64()? as usize)
+        } else {
+            Ok(self.u32()? as usize)
+        }
+    }
+
+    fn uleb128(&mut self) -> Result<u64, DwarfError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Result<i64, DwarfError> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1i64 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DwarfError> {
+        let truncated = DwarfError::Truncated { offset: self.offset };
+        let rest = self.data.get(self.offset..).ok_or(truncated)?;
+        let len = rest.iter().position(|&byte| byte == 0).ok_or(truncated)?;
+        self.offset += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+}
+
+fn string_at(section: &[u8], offset: usize) -> Result<String, DwarfError> {
+    Reader::new(section, offset).string()
+}
+
+// file name without its directory, as printed in annotations
+fn file_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+// DWARF 5 directory or file name table, returns the paths
+fn parse_entries(
+    reader: &mut Reader,
+    offset_size: usize,
+    line_str: &[u8],
+    debug_str: &[u8],
+) -> Result<Vec<String>, DwarfError> {
+    let format_count = reader.u8()?;
+    let mut formats = Vec::new();
+    for _ in 0..format_count {
+        formats.push((reader.uleb128()?, reader.uleb128()?));
+    }
+
+    let count = reader.uleb128()?;
+    let mut paths = Vec::new();
+    for _ in 0..count {
+        let mut path = String::new();
+        for &(content_type, form) in formats.iter() {
+            let value = match form {
+                DW_FORM_STRING => Some(reader.string()?),
+                DW_FORM_LINE_STRP => Some(string_at(line_str, reader.offset(offset_size)?)?),
+                DW_FORM_STRP => Some(string_at(debug_str, reader.offset(offset_size)?)?),
+                DW_FORM_UDATA => {
+                    reader.uleb128()?;
+                    None
+                }
+                DW_FORM_DATA1 | DW_FORM_DATA2 | DW_FORM_DATA4 | DW_FORM_DATA8 | DW_FORM_DATA16 => {
+                    let len = match form {
+                        DW_FORM_DATA1 => 1,
+                        DW_FORM_DATA2 => 2,
+                        DW_FORM_DATA4 => 4,
+                        DW_FORM_DATA8 => 8,
+                        _ => 16,
+                    };
+                    reader.bytes(len)?;
+                    None
+                }
+                DW_FORM_BLOCK => {
+                    let len = reader.uleb128()? as usize;
+                    reader.bytes(len)?;
+                    None
+                }
+                _ => return Err(DwarfError::UnsupportedForm { form }),
+            };
+            if let (DW_LNCT_PATH, Some(value)) = (content_type, value) {
+                path = value;
+            }
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+// A row of the line number matrix
+#[derive(Debug, Clone, Copy)]
+struct LineRow {
+    addr: u32,
+    file: usize,
+    line: u32,
+    // first address after a sequence of instructions
+    end_sequence: bool,
+}
+
+// Symbols and source lines of the loaded program, for diagnostics
+#[derive(Default)]
+pub struct DebugInfo {
+    symbols: Vec<Symbol>,
+    files: Vec<String>,
+    lines: Vec<LineRow>,
+}
+
+impl DebugInfo {
+    pub fn new(elf: &Elf) -> DebugInfo {
+        let mut debug_info = DebugInfo {
+            symbols: elf.symbols.clone(),
+            files: Vec::new(),
+            lines: Vec::new(),
+        };
+        if let Some(debug_line) = elf.section(".debug_line") {
+            let line_str = elf.section(".debug_line_str").unwrap_or(&[]);
+            let debug_str = elf.section(".debug_str").unwrap_or(&[]);
+            if let Err(error) = debug_info.parse_line_programs(debug_line, line_str, debug_str) {
+                eprintln!("simp: malformed .debug_line, source lines are not available: {}", error);
+                debug_info.files.clear();
+                debug_info.lines.clear();
+            }
+        }
+        // at equal addresses the end of a sequence comes before the start of the next one
+        debug_info.lines.sort_by_key(|row| (row.addr, !row.end_sequence));
+        debug_info
+    }
+
+    fn parse_line_programs(&mut self, debug_line: &[u8], line_str: &[u8], debug_str: &[u8]) -> Result<(), DwarfError> {
+        let mut reader = Reader::new(debug_line, 0);
+        while reader.offset < debug_line.len() {
+            let unit_start = reader.offset;
+            let invalid = DwarfError::InvalidHeader { offset: unit_start };
+            let (unit_length, offset_size) = match reader.u32()? {
+                0xffff_ffff => (reader.u64()? as usize, 8),
+                length => (length as usize, 4),
+            };
+            let unit_end = reader.offset.checked_add(unit_length).ok_or(invalid)?;
+            if unit_end > debug_line.len() {
+                return Err(invalid);
+            }
+
+            let version = reader.u16()?;
+            if !(2..=5).contains(&version) {
+                return Err(DwarfError::UnsupportedVersion { version });
+            }
+            if version >= 5 {
+                // address_size, segment_selector_size
+                reader.bytes(2)?;
+            }
+            let header_length = reader.offset(offset_size)?;
+            let program_start = reader.offset.checked_add(header_length).ok_or(invalid)?;
+            let min_inst_length = reader.u8()? as u32;
+            if version >= 4 {
+                // maximum_operations_per_instruction
+                reader.u8()?;
+            }
+            // default_is_stmt
+            reader.u8()?;
+            let line_base = reader.u8()? as i8 as i64;
+            let line_range = reader.u8()?;
+            let opcode_base = reader.u8()?;
+            if line_range == 0 || opcode_base == 0 {
+                return Err(invalid);
+            }
+            let standard_opcode_lengths = reader.bytes(opcode_base as usize - 1)?;
+
+            // indices into self.files, numbered from 1 before DWARF 5 and from 0 since
+            let mut files = Vec::new();
+            if version >= 5 {
+                // directories are not needed for annotations
+                parse_entries(&mut reader, offset_size, line_str, debug_str)?;
+                for path in parse_entries(&mut reader, offset_size, line_str, debug_str)? {
+                    files.push(self.files.len());
+                    self.files.push(file_name(&path));
+                }
+            } else {
+                while !reader.string()?.is_empty() {}
+                files.push(usize::MAX);
+                loop {
+                    let path = reader.string()?;
+                    if path.is_empty() {
+                        break;
+                    }
+                    // directory index, modification time, length
+                    reader.uleb128()?;
+                    reader.uleb128()?;
+                    reader.uleb128()?;
+                    files.push(self.files.len());
+                    self.files.push(file_name(&path));
+                }
+            }
+
+            reader.offset = program_start;
+            let mut addr = 0u32;
+            let mut file = 1usize;
+            let mut line = 1i64;
+            while reader.offset < unit_end {
+                let opcode = reader.u8()?;
+                let mut emit = false;
+                let mut end_sequence = false;
+                if opcode >= opcode_base {
+                    // special opcode
+                    let adjusted = opcode - opcode_base;
+                    addr = addr.wrapping_add((adjusted / line_range) as u32 * min_inst_length);
+                    line = line.wrapping_add(line_base + (adjusted % line_range) as i64);
+                    emit = true;
+                } else if opcode == 0 {
+                    // extended opcode
+                    let len = reader.uleb128()? as usize;
+                    let end = reader.offset.checked_add(len).ok_or(DwarfError::Truncated { offset: reader.offset })?;
+                    match reader.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            emit = true;
+                            end_sequence = true;
+                        }
+                        DW_LNE_SET_ADDRESS => addr = reader.u32()?,
+                        DW_LNE_DEFINE_FILE => {
+                            let path = reader.string()?;
+                            files.push(self.files.len());
+                            self.files.push(file_name(&path));
+                        }
+                        _ => {}
+                    }
+                    reader.offset = end;
+                } else {
+                    match opcode {
+                        DW_LNS_COPY => emit = true,
+                        DW_LNS_ADVANCE_PC => {
+                            addr = addr.wrapping_add((reader.uleb128()? as u32).wrapping_mul(min_inst_length));
+                        }
+                        DW_LNS_ADVANCE_LINE => line = line.wrapping_add(reader.sleb128()?),
+                        DW_LNS_SET_FILE => file = reader.uleb128()? as usize,
+                        DW_LNS_CONST_ADD_PC => {
+                            addr = addr.wrapping_add(((255 - opcode_base) / line_range) as u32 * min_inst_length);
+                        }
+                        DW_LNS_FIXED_ADVANCE_PC => addr = addr.wrapping_add(reader.u16()? as u32),
+                        _ => {
+                            // skip the operands of opcodes without effect on the address or line
+                            for _ in 0..standard_opcode_lengths[opcode as usize - 1] {
+                                reader.uleb128()?;
+                            }
+                        }
+                    }
+                }
+
+                if emit {
+                    self.lines.push(LineRow {
+                        addr,
+                        file: files.get(file).copied().unwrap_or(usize::MAX),
+                        line: line as u32,
+                        end_sequence,
+                    });
+                }
+                if end_sequence {
+                    addr = 0;
+                    file = 1;
+                    line = 1;
+                }
+            }
+            reader.offset = unit_end;
+        }
+        Ok(())
+    }
+
+    // function or object containing addr, with the offset into it
+    pub fn symbol(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        let symbol = self.symbols[..index]
+            .iter()
+            .rev()
+            .find(|symbol| addr - symbol.addr < symbol.size.max(1))?;
+        Some((&symbol.name, addr - symbol.addr))
+    }
+
+    // address of the function or object called name
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.addr)
+    }
+
+    // source file and line of the instruction at addr
+    fn line(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self.lines.partition_point(|row| row.addr <= addr);
+        let row = self.lines[..index].last()?;
+        if row.end_sequence {
+            return None;
+        }
+        let file = self.files.get(row.file).map(|file| file.as_str()).unwrap_or("??");
+        Some((file, row.line))
+    }
+
+    // "fib+0x24 (fib.c:3)", or None when nothing is known about addr
+    pub fn location(&self, addr: u32) -> Option<String> {
+        match (self.symbol(addr), self.line(addr)) {
+            (Some((name, offset)), Some((file, line))) => Some(format!("{}+{:#x} ({}:{})", name, offset, file, line)),
+            (Some((name, offset)), None) => Some(format!("{}+{:#x}", name, offset)),
+            (None, Some((file, line))) => Some(format!("({}:{})", file, line)),
+            (None, None) => None,
+        }
+    }
+}
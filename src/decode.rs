@@ -0,0 +1,559 @@
+use std::fmt;
+
+use crate::cp1::Format;
+
+// A decoded instruction. Immediates are extended the way the instruction uses
+// them, branch offsets are in bytes relative to the delay slot and jump
+// targets are the low 28 bits of the address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // special
+    Sll { rd: usize, rt: usize, sa: u32 },
+    Movf { rd: usize, rs: usize, cc: u32 },
+    Movt { rd: usize, rs: usize, cc: u32 },
+    Srl { rd: usize, rt: usize, sa: u32 },
+    Rotr { rd: usize, rt: usize, sa: u32 },
+    Sra { rd: usize, rt: usize, sa: u32 },
+    Sllv { rd: usize, rt: usize, rs: usize },
+    Srlv { rd: usize, rt: usize, rs: usize },
+    Rotrv { rd: usize, rt: usize, rs: usize },
+    Srav { rd: usize, rt: usize, rs: usize },
+    Jr { rs: usize },
+    Jalr { rd: usize, rs: usize },
+    Movz { rd: usize, rs: usize, rt: usize },
+    Movn { rd: usize, rs: usize, rt: usize },
+    Syscall { code: u32 },
+    Break { code: u32 },
+    Mfhi { rd: usize },
+    Mthi { rs: usize },
+    Mflo { rd: usize },
+    Mtlo { rs: usize },
+    Mult { rs: usize, rt: usize },
+    Multu { rs: usize, rt: usize },
+    Div { rs: usize, rt: usize },
+    Divu { rs: usize, rt: usize },
+    Add { rd: usize, rs: usize, rt: usize },
+    Addu { rd: usize, rs: usize, rt: usize },
+    Sub { rd: usize, rs: usize, rt: usize },
+    Subu { rd: usize, rs: usize, rt: usize },
+    And { rd: usize, rs: usize, rt: usize },
+    Or { rd: usize, rs: usize, rt: usize },
+    Xor { rd: usize, rs: usize, rt: usize },
+    Nor { rd: usize, rs: usize, rt: usize },
+    Slt { rd: usize, rs: usize, rt: usize },
+    Sltu { rd: usize, rs: usize, rt: usize },
+    Tge { rs: usize, rt: usize, code: u32 },
+    Tgeu { rs: usize, rt: usize, code: u32 },
+    Tlt { rs: usize, rt: usize, code: u32 },
+    Tltu { rs: usize, rt: usize, code: u32 },
+    Teq { rs: usize, rt: usize, code: u32 },
+    Tne { rs: usize, rt: usize, code: u32 },
+    Sync { stype: u32 },
+    // regimm
+    Bltz { rs: usize, offset: u32 },
+    Bgez { rs: usize, offset: u32 },
+    Bltzl { rs: usize, offset: u32 },
+    Bgezl { rs: usize, offset: u32 },
+    Tgei { rs: usize, imm: u32 },
+    Tgeiu { rs: usize, imm: u32 },
+    Tlti { rs: usize, imm: u32 },
+    Tltiu { rs: usize, imm: u32 },
+    Teqi { rs: usize, imm: u32 },
+    Tnei { rs: usize, imm: u32 },
+    Bltzal { rs: usize, offset: u32 },
+    Bgezal { rs: usize, offset: u32 },
+    Bltzall { rs: usize, offset: u32 },
+    Bgezall { rs: usize, offset: u32 },
+    // jumps, branches and immediates
+    J { target: u32 },
+    Jal { target: u32 },
+    Beq { rs: usize, rt: usize, offset: u32 },
+    Bne { rs: usize, rt: usize, offset: u32 },
+    Blez { rs: usize, offset: u32 },
+    Bgtz { rs: usize, offset: u32 },
+    Addi { rt: usize, rs: usize, imm: u32 },
+    Addiu { rt: usize, rs: usize, imm: u32 },
+    Slti { rt: usize, rs: usize, imm: u32 },
+    Sltiu { rt: usize, rs: usize, imm: u32 },
+    Andi { rt: usize, rs: usize, imm: u32 },
+    Ori { rt: usize, rs: usize, imm: u32 },
+    Xori { rt: usize, rs: usize, imm: u32 },
+    Lui { rt: usize, imm: u32 },
+    Beql { rs: usize, rt: usize, offset: u32 },
+    Bnel { rs: usize, rt: usize, offset: u32 },
+    Blezl { rs: usize, offset: u32 },
+    Bgtzl { rs: usize, offset: u32 },
+    // cop0
+    Mfc0 { rt: usize, rd: usize, sel: u32 },
+    Mtc0 { rt: usize, rd: usize, sel: u32 },
+    Di { rt: usize },
+    Ei { rt: usize },
+    Tlbr,
+    Tlbwi,
+    Tlbwr,
+    Tlbp,
+    Eret,
+    // cop1
+    Mfc1 { rt: usize, fs: usize },
+    Cfc1 { rt: usize, fs: usize },
+    Mfhc1 { rt: usize, fs: usize },
+    Mtc1 { rt: usize, fs: usize },
+    Ctc1 { rt: usize, fs: usize },
+    Mthc1 { rt: usize, fs: usize },
+    Bc1f { cc: u32, offset: u32 },
+    Bc1t { cc: u32, offset: u32 },
+    Bc1fl { cc: u32, offset: u32 },
+    Bc1tl { cc: u32, offset: u32 },
+    AddFmt { fmt: Format, fd: usize, fs: usize, ft: usize },
+    SubFmt { fmt: Format, fd: usize, fs: usize, ft: usize },
+    MulFmt { fmt: Format, fd: usize, fs: usize, ft: usize },
+    DivFmt { fmt: Format, fd: usize, fs: usize, ft: usize },
+    SqrtFmt { fmt: Format, fd: usize, fs: usize },
+    AbsFmt { fmt: Format, fd: usize, fs: usize },
+    MovFmt { fmt: Format, fd: usize, fs: usize },
+    NegFmt { fmt: Format, fd: usize, fs: usize },
+    RoundW { fmt: Format, fd: usize, fs: usize },
+    TruncW { fmt: Format, fd: usize, fs: usize },
+    CeilW { fmt: Format, fd: usize, fs: usize },
+    FloorW { fmt: Format, fd: usize, fs: usize },
+    MovfFmt { fmt: Format, fd: usize, fs: usize, cc: u32 },
+    MovtFmt { fmt: Format, fd: usize, fs: usize, cc: u32 },
+    MovzFmt { fmt: Format, fd: usize, fs: usize, rt: usize },
+    MovnFmt { fmt: Format, fd: usize, fs: usize, rt: usize },
+    // cvt.s.fmt, cvt.d.fmt and cvt.w.fmt, fmt is the source format
+    CvtS { fmt: Format, fd: usize, fs: usize },
+    CvtD { fmt: Format, fd: usize, fs: usize },
+    CvtW { fmt: Format, fd: usize, fs: usize },
+    CFmt { fmt: Format, cond: u32, cc: u32, fs: usize, ft: usize },
+    // special2
+    Madd { rs: usize, rt: usize },
+    Maddu { rs: usize, rt: usize },
+    Mul { rd: usize, rs: usize, rt: usize },
+    Clz { rd: usize, rs: usize },
+    Clo { rd: usize, rs: usize },
+    // special3, ins with msb < pos has size 0
+    Ext { rt: usize, rs: usize, pos: u32, size: u32 },
+    Ins { rt: usize, rs: usize, pos: u32, size: u32 },
+    Wsbh { rd: usize, rt: usize },
+    Seb { rd: usize, rt: usize },
+    Seh { rd: usize, rt: usize },
+    Rdhwr { rt: usize, rd: usize },
+    // loads and stores
+    Lb { rt: usize, base: usize, offset: u32 },
+    Lh { rt: usize, base: usize, offset: u32 },
+    Lwl { rt: usize, base: usize, offset: u32 },
+    Lw { rt: usize, base: usize, offset: u32 },
+    Lbu { rt: usize, base: usize, offset: u32 },
+    Lhu { rt: usize, base: usize, offset: u32 },
+    Lwr { rt: usize, base: usize, offset: u32 },
+    Sb { rt: usize, base: usize, offset: u32 },
+    Sh { rt: usize, base: usize, offset: u32 },
+    Swl { rt: usize, base: usize, offset: u32 },
+    Sw { rt: usize, base: usize, offset: u32 },
+    Swr { rt: usize, base: usize, offset: u32 },
+    Ll { rt: usize, base: usize, offset: u32 },
+    Lwc1 { ft: usize, base: usize, offset: u32 },
+    Ldc1 { ft: usize, base: usize, offset: u32 },
+    Sc { rt: usize, base: usize, offset: u32 },
+    Swc1 { ft: usize, base: usize, offset: u32 },
+    Sdc1 { ft: usize, base: usize, offset: u32 },
+    // cache maintenance and prefetch hints, no-ops without caches
+    Cache { op: u32, base: usize, offset: u32 },
+    Pref { hint: u32, base: usize, offset: u32 },
+}
+
+impl Instruction {
+    // Branches and jumps, which are followed by a delay slot
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Jr { .. }
+                | Instruction::Jalr { .. }
+                | Instruction::Bltz { .. }
+                | Instruction::Bgez { .. }
+                | Instruction::Bltzl { .. }
+                | Instruction::Bgezl { .. }
+                | Instruction::Bltzal { .. }
+                | Instruction::Bgezal { .. }
+                | Instruction::Bltzall { .. }
+                | Instruction::Bgezall { .. }
+                | Instruction::J { .. }
+                | Instruction::Jal { .. }
+                | Instruction::Beq { .. }
+                | Instruction::Bne { .. }
+                | Instruction::Blez { .. }
+                | Instruction::Bgtz { .. }
+                | Instruction::Beql { .. }
+                | Instruction::Bnel { .. }
+                | Instruction::Blezl { .. }
+                | Instruction::Bgtzl { .. }
+                | Instruction::Bc1f { .. }
+                | Instruction::Bc1t { .. }
+                | Instruction::Bc1fl { .. }
+                | Instruction::Bc1tl { .. }
+        )
+    }
+
+    // CP0 instructions, which need kernel mode or Status.CU0
+    pub fn is_privileged(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Mfc0 { .. }
+                | Instruction::Mtc0 { .. }
+                | Instruction::Di { .. }
+                | Instruction::Ei { .. }
+                | Instruction::Tlbr
+                | Instruction::Tlbwi
+                | Instruction::Tlbwr
+                | Instruction::Tlbp
+                | Instruction::Eret
+                | Instruction::Cache { .. }
+        )
+    }
+
+    // CP0 writes that may change how the following instructions are mapped
+    pub fn changes_translation(&self) -> bool {
+        matches!(self, Instruction::Mtc0 { .. } | Instruction::Tlbwi | Instruction::Tlbwr)
+    }
+}
+
+// The fields selecting an encoding SIMP does not implement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownField {
+    Opcode { opcode: u32 },
+    Funct { opcode: u32, funct: u32 },
+    Rs { opcode: u32, rs: usize },
+    Fmt { opcode: u32, fmt: usize },
+    FmtFunct { opcode: u32, fmt: usize, funct: u32 },
+    FunctSa { opcode: u32, funct: u32, sa: u32 },
+}
+
+impl fmt::Display for UnknownField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnknownField::Opcode { opcode } => write!(f, "opcode {:#x}", opcode),
+            UnknownField::Funct { opcode, funct } => write!(f, "opcode {:#x} funct {:#x}", opcode, funct),
+            UnknownField::Rs { opcode, rs } => write!(f, "opcode {:#x} rs {:#x}", opcode, rs),
+            UnknownField::Fmt { opcode, fmt } => write!(f, "opcode {:#x} fmt {:#x}", opcode, fmt),
+            UnknownField::FmtFunct { opcode, fmt, funct } => {
+                write!(f, "opcode {:#x} fmt {:#x} funct {:#x}", opcode, fmt, funct)
+            }
+            UnknownField::FunctSa { opcode, funct, sa } => {
+                write!(f, "opcode {:#x} funct {:#x} sa {:#x}", opcode, funct, sa)
+            }
+        }
+    }
+}
+
+// A word SIMP does not implement, kept for the error reported when no
+// Reserved Instruction handler runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub word: u32,
+    pub field: UnknownField,
+}
+
+impl DecodeError {
+    pub fn opcode(&self) -> u32 {
+        opcode(self.word)
+    }
+}
+
+fn opcode(inst: u32) -> u32 {
+    (inst & 0xfc000000) >> 26
+}
+
+fn rs(inst: u32) -> usize {
+    ((inst & 0x03e00000) >> 21) as usize
+}
+
+fn rt(inst: u32) -> usize {
+    ((inst & 0x001f0000) >> 16) as usize
+}
+
+fn rd(inst: u32) -> usize {
+    ((inst & 0x0000f800) >> 11) as usize
+}
+
+// shift amount, fd of floating point instructions
+fn sa(inst: u32) -> u32 {
+    (inst & 0x000007c0) >> 6
+}
+
+fn funct(inst: u32) -> u32 {
+    inst & 0x0000003f
+}
+
+// zero extended immediate
+fn imm(inst: u32) -> u32 {
+    inst & 0x0000ffff
+}
+
+// sign extended immediate
+fn simm(inst: u32) -> u32 {
+    ((inst & 0x0000ffff) as i16) as u32
+}
+
+// branch offset in bytes
+fn offset(inst: u32) -> u32 {
+    simm(inst) << 2
+}
+
+// condition code of floating point branches and moves
+fn cc(inst: u32) -> u32 {
+    (inst & 0x001c0000) >> 18
+}
+
+// branch or move on true rather than false
+fn tf(inst: u32) -> bool {
+    inst & 0x00010000 != 0
+}
+
+// code of trap instructions
+fn trap_code(inst: u32) -> u32 {
+    (inst & 0x0000ffc0) >> 6
+}
+
+pub fn decode(inst: u32) -> Result<Instruction, DecodeError> {
+    decode_fields(inst).map_err(|field| DecodeError { word: inst, field })
+}
+
+fn decode_fields(inst: u32) -> Result<Instruction, UnknownField> {
+    let opcode = opcode(inst);
+    let rs = rs(inst);
+    let rt = rt(inst);
+    let rd = rd(inst);
+    let sa = sa(inst);
+
+    let instruction = match opcode {
+        0x00 => {
+            let funct = funct(inst);
+            match funct {
+                0x00 => Instruction::Sll { rd, rt, sa },
+                0x01 if tf(inst) => Instruction::Movt { rd, rs, cc: cc(inst) },
+                0x01 => Instruction::Movf { rd, rs, cc: cc(inst) },
+                0x02 if inst & 0x00200000 != 0 => Instruction::Rotr { rd, rt, sa },
+                0x02 => Instruction::Srl { rd, rt, sa },
+                0x03 => Instruction::Sra { rd, rt, sa },
+                0x04 => Instruction::Sllv { rd, rt, rs },
+                0x06 if inst & 0x00000040 != 0 => Instruction::Rotrv { rd, rt, rs },
+                0x06 => Instruction::Srlv { rd, rt, rs },
+                0x07 => Instruction::Srav { rd, rt, rs },
+                0x08 => Instruction::Jr { rs },
+                0x09 => Instruction::Jalr { rd, rs },
+                0x0a => Instruction::Movz { rd, rs, rt },
+                0x0b => Instruction::Movn { rd, rs, rt },
+                0x0c => Instruction::Syscall {
+                    code: (inst & 0x03ffffc0) >> 6,
+                },
+                0x0d => Instruction::Break {
+                    code: (inst & 0x03ffffc0) >> 6,
+                },
+                0x0f => Instruction::Sync { stype: sa },
+                0x10 => Instruction::Mfhi { rd },
+                0x11 => Instruction::Mthi { rs },
+                0x12 => Instruction::Mflo { rd },
+                0x13 => Instruction::Mtlo { rs },
+                0x18 => Instruction::Mult { rs, rt },
+                0x19 => Instruction::Multu { rs, rt },
+                0x1a => Instruction::Div { rs, rt },
+                0x1b => Instruction::Divu { rs, rt },
+                0x20 => Instruction::Add { rd, rs, rt },
+                0x21 => Instruction::Addu { rd, rs, rt },
+                0x22 => Instruction::Sub { rd, rs, rt },
+                0x23 => Instruction::Subu { rd, rs, rt },
+                0x24 => Instruction::And { rd, rs, rt },
+                0x25 => Instruction::Or { rd, rs, rt },
+                0x26 => Instruction::Xor { rd, rs, rt },
+                0x27 => Instruction::Nor { rd, rs, rt },
+                0x2a => Instruction::Slt { rd, rs, rt },
+                0x2b => Instruction::Sltu { rd, rs, rt },
+                0x30 => Instruction::Tge { rs, rt, code: trap_code(inst) },
+                0x31 => Instruction::Tgeu { rs, rt, code: trap_code(inst) },
+                0x32 => Instruction::Tlt { rs, rt, code: trap_code(inst) },
+                0x33 => Instruction::Tltu { rs, rt, code: trap_code(inst) },
+                0x34 => Instruction::Teq { rs, rt, code: trap_code(inst) },
+                0x36 => Instruction::Tne { rs, rt, code: trap_code(inst) },
+                _ => return Err(UnknownField::Funct { opcode, funct }),
+            }
+        }
+        0x01 => {
+            let offset = offset(inst);
+            let imm = simm(inst);
+            match rt {
+                0x00 => Instruction::Bltz { rs, offset },
+                0x01 => Instruction::Bgez { rs, offset },
+                0x02 => Instruction::Bltzl { rs, offset },
+                0x03 => Instruction::Bgezl { rs, offset },
+                0x08 => Instruction::Tgei { rs, imm },
+                0x09 => Instruction::Tgeiu { rs, imm },
+                0x0a => Instruction::Tlti { rs, imm },
+                0x0b => Instruction::Tltiu { rs, imm },
+                0x0c => Instruction::Teqi { rs, imm },
+                0x0e => Instruction::Tnei { rs, imm },
+                0x10 => Instruction::Bltzal { rs, offset },
+                0x11 => Instruction::Bgezal { rs, offset },
+                0x12 => Instruction::Bltzall { rs, offset },
+                0x13 => Instruction::Bgezall { rs, offset },
+                _ => return Err(UnknownField::Funct { opcode, funct: rt as u32 }),
+            }
+        }
+        0x02 => Instruction::J {
+            target: (inst & 0x03ffffff) << 2,
+        },
+        0x03 => Instruction::Jal {
+            target: (inst & 0x03ffffff) << 2,
+        },
+        0x04 => Instruction::Beq { rs, rt, offset: offset(inst) },
+        0x05 => Instruction::Bne { rs, rt, offset: offset(inst) },
+        0x06 => Instruction::Blez { rs, offset: offset(inst) },
+        0x07 => Instruction::Bgtz { rs, offset: offset(inst) },
+        0x08 => Instruction::Addi { rt, rs, imm: simm(inst) },
+        0x09 => Instruction::Addiu { rt, rs, imm: simm(inst) },
+        0x0a => Instruction::Slti { rt, rs, imm: simm(inst) },
+        // the immediate is sign extended and compared unsigned
+        0x0b => Instruction::Sltiu { rt, rs, imm: simm(inst) },
+        0x0c => Instruction::Andi { rt, rs, imm: imm(inst) },
+        0x0d => Instruction::Ori { rt, rs, imm: imm(inst) },
+        0x0e => Instruction::Xori { rt, rs, imm: imm(inst) },
+        0x0f => Instruction::Lui { rt, imm: imm(inst) },
+        0x10 => {
+            let sel = inst & 0x00000007;
+            match rs {
+                0x00 => Instruction::Mfc0 { rt, rd, sel },
+                0x04 => Instruction::Mtc0 { rt, rd, sel },
+                0x0b if inst & 0x00000020 != 0 => Instruction::Ei { rt },
+                0x0b => Instruction::Di { rt },
+                0x10..=0x1f => match funct(inst) {
+                    0x01 => Instruction::Tlbr,
+                    0x02 => Instruction::Tlbwi,
+                    0x06 => Instruction::Tlbwr,
+                    0x08 => Instruction::Tlbp,
+                    0x18 => Instruction::Eret,
+                    funct => return Err(UnknownField::Funct { opcode, funct }),
+                },
+                _ => return Err(UnknownField::Rs { opcode, rs }),
+            }
+        }
+        0x11 => {
+            let fmt = rs;
+            let (ft, fs, fd) = (rt, rd, sa as usize);
+            match fmt {
+                0x00 => Instruction::Mfc1 { rt, fs },
+                0x02 => Instruction::Cfc1 { rt, fs },
+                0x03 => Instruction::Mfhc1 { rt, fs },
+                0x04 => Instruction::Mtc1 { rt, fs },
+                0x06 => Instruction::Ctc1 { rt, fs },
+                0x07 => Instruction::Mthc1 { rt, fs },
+                0x08 => {
+                    let (cc, offset) = (cc(inst), offset(inst));
+                    match (inst & 0x00020000 != 0, tf(inst)) {
+                        (false, false) => Instruction::Bc1f { cc, offset },
+                        (false, true) => Instruction::Bc1t { cc, offset },
+                        (true, false) => Instruction::Bc1fl { cc, offset },
+                        (true, true) => Instruction::Bc1tl { cc, offset },
+                    }
+                }
+                0x10 | 0x11 => {
+                    let fmt = if rs == 0x10 { Format::Single } else { Format::Double };
+                    match funct(inst) {
+                        0x00 => Instruction::AddFmt { fmt, fd, fs, ft },
+                        0x01 => Instruction::SubFmt { fmt, fd, fs, ft },
+                        0x02 => Instruction::MulFmt { fmt, fd, fs, ft },
+                        0x03 => Instruction::DivFmt { fmt, fd, fs, ft },
+                        0x04 => Instruction::SqrtFmt { fmt, fd, fs },
+                        0x05 => Instruction::AbsFmt { fmt, fd, fs },
+                        0x06 => Instruction::MovFmt { fmt, fd, fs },
+                        0x07 => Instruction::NegFmt { fmt, fd, fs },
+                        0x0c => Instruction::RoundW { fmt, fd, fs },
+                        0x0d => Instruction::TruncW { fmt, fd, fs },
+                        0x0e => Instruction::CeilW { fmt, fd, fs },
+                        0x0f => Instruction::FloorW { fmt, fd, fs },
+                        0x11 if tf(inst) => Instruction::MovtFmt { fmt, fd, fs, cc: cc(inst) },
+                        0x11 => Instruction::MovfFmt { fmt, fd, fs, cc: cc(inst) },
+                        0x12 => Instruction::MovzFmt { fmt, fd, fs, rt },
+                        0x13 => Instruction::MovnFmt { fmt, fd, fs, rt },
+                        0x20 if fmt == Format::Double => Instruction::CvtS { fmt, fd, fs },
+                        0x21 if fmt == Format::Single => Instruction::CvtD { fmt, fd, fs },
+                        0x24 => Instruction::CvtW { fmt, fd, fs },
+                        funct @ 0x30..=0x3f => Instruction::CFmt {
+                            fmt,
+                            cond: funct & 0xf,
+                            cc: (inst & 0x00000700) >> 8,
+                            fs,
+                            ft,
+                        },
+                        funct => return Err(UnknownField::FmtFunct { opcode, fmt: rs, funct }),
+                    }
+                }
+                0x14 => match funct(inst) {
+                    0x20 => Instruction::CvtS { fmt: Format::Word, fd, fs },
+                    0x21 => Instruction::CvtD { fmt: Format::Word, fd, fs },
+                    funct => return Err(UnknownField::FmtFunct { opcode, fmt, funct }),
+                },
+                _ => return Err(UnknownField::Fmt { opcode, fmt }),
+            }
+        }
+        0x14 => Instruction::Beql { rs, rt, offset: offset(inst) },
+        0x15 => Instruction::Bnel { rs, rt, offset: offset(inst) },
+        0x16 => Instruction::Blezl { rs, offset: offset(inst) },
+        0x17 => Instruction::Bgtzl { rs, offset: offset(inst) },
+        0x1c => match funct(inst) {
+            0x00 => Instruction::Madd { rs, rt },
+            0x01 => Instruction::Maddu { rs, rt },
+            0x02 => Instruction::Mul { rd, rs, rt },
+            0x20 => Instruction::Clz { rd, rs },
+            0x21 => Instruction::Clo { rd, rs },
+            funct => return Err(UnknownField::Funct { opcode, funct }),
+        },
+        0x1f => match funct(inst) {
+            0x00 => Instruction::Ext {
+                rt,
+                rs,
+                pos: sa,
+                size: rd as u32 + 1,
+            },
+            0x04 => Instruction::Ins {
+                rt,
+                rs,
+                pos: sa,
+                size: (rd as u32 + 1).saturating_sub(sa),
+            },
+            0x20 => match sa {
+                0x02 => Instruction::Wsbh { rd, rt },
+                0x10 => Instruction::Seb { rd, rt },
+                0x18 => Instruction::Seh { rd, rt },
+                _ => return Err(UnknownField::FunctSa { opcode, funct: 0x20, sa }),
+            },
+            0x3b => Instruction::Rdhwr { rt, rd },
+            funct => return Err(UnknownField::Funct { opcode, funct }),
+        },
+        _ => {
+            let (base, offset) = (rs, simm(inst));
+            match opcode {
+                0x20 => Instruction::Lb { rt, base, offset },
+                0x21 => Instruction::Lh { rt, base, offset },
+                0x22 => Instruction::Lwl { rt, base, offset },
+                0x23 => Instruction::Lw { rt, base, offset },
+                0x24 => Instruction::Lbu { rt, base, offset },
+                0x25 => Instruction::Lhu { rt, base, offset },
+                0x26 => Instruction::Lwr { rt, base, offset },
+                0x28 => Instruction::Sb { rt, base, offset },
+                0x29 => Instruction::Sh { rt, base, offset },
+                0x2a => Instruction::Swl { rt, base, offset },
+                0x2b => Instruction::Sw { rt, base, offset },
+                0x2e => Instruction::Swr { rt, base, offset },
+                0x2f => Instruction::Cache { op: rt as u32, base, offset },
+                0x30 => Instruction::Ll { rt, base, offset },
+                0x31 => Instruction::Lwc1 { ft: rt, base, offset },
+                0x33 => Instruction::Pref { hint: rt as u32, base, offset },
+                0x35 => Instruction::Ldc1 { ft: rt, base, offset },
+                0x38 => Instruction::Sc { rt, base, offset },
+                0x39 => Instruction::Swc1 { ft: rt, base, offset },
+                0x3d => Instruction::Sdc1 { ft: rt, base, offset },
+                _ => return Err(UnknownField::Opcode { opcode }),
+            }
+        }
+    };
+    Ok(instruction)
+}
@@ -0,0 +1,145 @@
+use std::rc::Rc;
+
+use crate::block::*;
+use crate::bus::*;
+use crate::decode::*;
+use crate::memory::*;
+
+pub const PAGE_SIZE: u32 = 4096;
+
+// longest block, so that a long straight-line run still returns to the loop
+pub const MAX_BLOCK_LEN: usize = 64;
+
+const MEMORY_PAGES: usize = (MEMORY_SIZE / PAGE_SIZE) as usize;
+const BOOT_ROM_PAGES: usize = (BOOT_ROM_SIZE / PAGE_SIZE) as usize;
+const USER_MEMORY_PAGES: usize = (USER_MEMORY_SIZE / PAGE_SIZE) as usize;
+const PAGES: usize = MEMORY_PAGES + BOOT_ROM_PAGES + USER_MEMORY_PAGES;
+const PAGE_WORDS: usize = (PAGE_SIZE / 4) as usize;
+
+pub type Decoded = Result<Instruction, DecodeError>;
+
+struct Page {
+    decoded: Box<[Decoded]>,
+    // blocks translated so far, indexed by the word they start at
+    blocks: Box<[Option<Rc<Block>>]>,
+}
+
+// Decoded instructions of the pages executed so far, indexed by physical
+// page. Only the boot ROM and the memories are cached, a page is dropped
+// as a whole when it is written.
+pub struct DecodeCache {
+    pages: Vec<Option<Page>>,
+    // bumped whenever a cached page is dropped, in total and per page
+    generation: u64,
+    versions: Vec<u32>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            pages: (0..PAGES).map(|_| None).collect(),
+            generation: 0,
+            versions: vec![0; PAGES],
+        }
+    }
+
+    fn index(addr: u32) -> Option<usize> {
+        if (PHY_BOOT_ROM_BASE..PHY_BOOT_ROM_BASE + BOOT_ROM_SIZE).contains(&addr) {
+            Some(MEMORY_PAGES + ((addr - PHY_BOOT_ROM_BASE) / PAGE_SIZE) as usize)
+        } else if (PHY_MEMORY_BASE..PHY_MEMORY_BASE + MEMORY_SIZE).contains(&addr) {
+            Some(((addr - PHY_MEMORY_BASE) / PAGE_SIZE) as usize)
+        } else if (PHY_USER_MEMORY_BASE..PHY_USER_MEMORY_BASE + USER_MEMORY_SIZE).contains(&addr) {
+            Some(MEMORY_PAGES + BOOT_ROM_PAGES + ((addr - PHY_USER_MEMORY_BASE) / PAGE_SIZE) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn word(addr: u32) -> usize {
+        ((addr % PAGE_SIZE) / 4) as usize
+    }
+
+    // Whether the page of a physical address can be cached
+    pub fn cacheable(addr: u32) -> bool {
+        Self::index(addr).is_some()
+    }
+
+    // Changes whenever cached instructions are dropped, so that a running
+    // block can tell it has been overwritten
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Changes whenever the cached instructions of the page at a physical
+    // address are dropped
+    #[cfg(feature = "jit")]
+    pub fn version(&self, addr: u32) -> u32 {
+        Self::index(addr).map_or(0, |index| self.versions[index])
+    }
+
+    // Decoded instruction at a word aligned physical address, if its page
+    // is cached
+    pub fn get(&self, addr: u32) -> Option<Decoded> {
+        let page = self.pages[Self::index(addr)?].as_ref()?;
+        Some(page.decoded[Self::word(addr)])
+    }
+
+    // Cache the decoded words of the page at a physical address
+    pub fn insert(&mut self, addr: u32, decoded: Box<[Decoded]>) {
+        if let Some(index) = Self::index(addr) {
+            self.pages[index] = Some(Page {
+                decoded,
+                blocks: (0..PAGE_WORDS).map(|_| None).collect(),
+            });
+        }
+    }
+
+    // Block starting at a word aligned physical address, translated from
+    // its cached page on the first call. None when the page is not cached.
+    pub fn block(&mut self, addr: u32) -> Option<Rc<Block>> {
+        let page = self.pages[Self::index(addr)?].as_mut()?;
+        let start = Self::word(addr);
+        if let Some(block) = &page.blocks[start] {
+            return Some(block.clone());
+        }
+        let mut end = start;
+        while end < PAGE_WORDS && end - start < MAX_BLOCK_LEN {
+            end += 1;
+            match page.decoded[end - 1] {
+                // the delay slot belongs to the block when it is on the same page
+                Ok(instruction) if instruction.is_branch() => {
+                    end = (end + 1).min(PAGE_WORDS);
+                    break;
+                }
+                // pc is translated again after it
+                Ok(instruction) if instruction.changes_translation() => break,
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        let block = Rc::new(Block::translate(&page.decoded[start..end]));
+        page.blocks[start] = Some(block.clone());
+        Some(block)
+    }
+
+    // Drop the pages overlapping len bytes at a physical address
+    pub fn invalidate(&mut self, addr: u32, len: u32) {
+        if len == 0 {
+            return;
+        }
+        let last = addr.saturating_add(len - 1);
+        let mut page = addr - addr % PAGE_SIZE;
+        loop {
+            if let Some(index) = Self::index(page) {
+                if self.pages[index].take().is_some() {
+                    self.generation += 1;
+                    self.versions[index] = self.versions[index].wrapping_add(1);
+                }
+            }
+            match page.checked_add(PAGE_SIZE) {
+                Some(next) if next <= last => page = next,
+                _ => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use crate::cp1::Format;
+use crate::cpu::*;
+use crate::debug_info::*;
+use crate::decode::*;
+use crate::elf::*;
+
+// cp0 register names of the MIPS32 Release 2 privileged architecture
+const CP0_NAMES: [&str; 32] = [
+    "c0_index", "c0_random", "c0_entrylo0", "c0_entrylo1", "c0_context", "c0_pagemask", "c0_wired", "c0_hwrena",
+    "c0_badvaddr", "c0_count", "c0_entryhi", "c0_compare", "c0_status", "c0_cause", "c0_epc", "c0_prid",
+    "c0_config", "c0_lladdr", "c0_watchlo", "c0_watchhi", "c0_xcontext", "$21", "$22", "c0_debug",
+    "c0_depc", "c0_perfcnt", "c0_errctl", "c0_cacheerr", "c0_taglo", "c0_taghi", "c0_errorepc", "c0_desave",
+];
+
+// cp0 registers with a select other than 0, as (register, select, name)
+const CP0_SEL_NAMES: [(usize, u32, &str); 8] = [
+    (4, 2, "c0_userlocal"),
+    (12, 1, "c0_intctl"),
+    (12, 2, "c0_srsctl"),
+    (12, 3, "c0_srsmap"),
+    (15, 1, "c0_ebase"),
+    (16, 1, "c0_config1"),
+    (16, 2, "c0_config2"),
+    (16, 3, "c0_config3"),
+];
+
+// c.cond.fmt conditions
+const CONDITIONS: [&str; 16] = [
+    "f", "un", "eq", "ueq", "olt", "ult", "ole", "ule", "sf", "ngle", "seq", "ngl", "lt", "nge", "le", "ngt",
+];
+
+fn gpr(reg: usize) -> &'static str {
+    ABI_NAMES[reg]
+}
+
+fn cp0_register(reg: usize, sel: u32) -> String {
+    if sel == 0 {
+        return String::from(CP0_NAMES[reg]);
+    }
+    match CP0_SEL_NAMES.iter().find(|(r, s, _)| *r == reg && *s == sel) {
+        Some((_, _, name)) => String::from(*name),
+        None => format!("${},{}", reg, sel),
+    }
+}
+
+// "bfc00040 <main+0x20>" like objdump prints branch and jump targets
+fn address(addr: u32, debug_info: &DebugInfo) -> String {
+    match debug_info.symbol(addr) {
+        Some((name, 0)) => format!("{:x} <{}>", addr, name),
+        Some((name, offset)) => format!("{:x} <{}+{:#x}>", addr, name, offset),
+        None => format!("{:x}", addr),
+    }
+}
+
+// "lw\tt1,0(t0)"
+fn memory(name: &str, reg: &str, base: usize, offset: u32) -> String {
+    format!("{}\t{},{}({})", name, reg, offset as i32, gpr(base))
+}
+
+fn format_name(fmt: Format) -> &'static str {
+    match fmt {
+        Format::Single => "s",
+        Format::Double => "d",
+        Format::Word => "w",
+    }
+}
+
+// An instruction at pc in the syntax of GNU objdump, mnemonic and operands
+// separated by a tab
+pub fn format_instruction(instruction: &Instruction, pc: u32, debug_info: &DebugInfo) -> String {
+    let branch = |offset: u32| address(pc.wrapping_add(4).wrapping_add(offset), debug_info);
+    let jump = |target: u32| address((pc.wrapping_add(4) & 0xf0000000) | target, debug_info);
+    let three = |name: &str, rd: usize, rs: usize, rt: usize| format!("{}\t{},{},{}", name, gpr(rd), gpr(rs), gpr(rt));
+    let two = |name: &str, a: usize, b: usize| format!("{}\t{},{}", name, gpr(a), gpr(b));
+    let one = |name: &str, a: usize| format!("{}\t{}", name, gpr(a));
+    let shift = |name: &str, rd: usize, rt: usize, sa: u32| format!("{}\t{},{},{:#x}", name, gpr(rd), gpr(rt), sa);
+    let immediate = |name: &str, rt: usize, rs: usize, imm: u32| format!("{}\t{},{},{}", name, gpr(rt), gpr(rs), imm as i32);
+    let logical = |name: &str, rt: usize, rs: usize, imm: u32| format!("{}\t{},{},{:#x}", name, gpr(rt), gpr(rs), imm);
+    let trap = |name: &str, rs: usize, rt: usize, code: u32| {
+        if code == 0 {
+            format!("{}\t{},{}", name, gpr(rs), gpr(rt))
+        } else {
+            format!("{}\t{},{},{:#x}", name, gpr(rs), gpr(rt), code)
+        }
+    };
+    let trap_immediate = |name: &str, rs: usize, imm: u32| format!("{}\t{},{}", name, gpr(rs), imm as i32);
+    let branch_zero = |name: &str, rs: usize, offset: u32| format!("{}\t{},{}", name, gpr(rs), branch(offset));
+    let branch_compare =
+        |name: &str, rs: usize, rt: usize, offset: u32| format!("{}\t{},{},{}", name, gpr(rs), gpr(rt), branch(offset));
+    let branch_cc = |name: &str, cc: u32, offset: u32| match cc {
+        0 => format!("{}\t{}", name, branch(offset)),
+        cc => format!("{}\t$fcc{},{}", name, cc, branch(offset)),
+    };
+    let fp_two = |name: &str, fmt: Format, fd: usize, fs: usize| format!("{}.{}\t$f{},$f{}", name, format_name(fmt), fd, fs);
+    let fp_three = |name: &str, fmt: Format, fd: usize, fs: usize, ft: usize| {
+        format!("{}.{}\t$f{},$f{},$f{}", name, format_name(fmt), fd, fs, ft)
+    };
+    let fpr = |ft: usize| format!("$f{}", ft);
+
+    match *instruction {
+        Instruction::Sll { rd: 0, rt: 0, sa: 0 } => String::from("nop"),
+        Instruction::Sll { rd: 0, rt: 0, sa: 1 } => String::from("ssnop"),
+        Instruction::Sll { rd: 0, rt: 0, sa: 3 } => String::from("ehb"),
+        Instruction::Sll { rd, rt, sa } => shift("sll", rd, rt, sa),
+        Instruction::Movf { rd, rs, cc } => format!("movf\t{},{},$fcc{}", gpr(rd), gpr(rs), cc),
+        Instruction::Movt { rd, rs, cc } => format!("movt\t{},{},$fcc{}", gpr(rd), gpr(rs), cc),
+        Instruction::Srl { rd, rt, sa } => shift("srl", rd, rt, sa),
+        Instruction::Rotr { rd, rt, sa } => shift("rotr", rd, rt, sa),
+        Instruction::Sra { rd, rt, sa } => shift("sra", rd, rt, sa),
+        Instruction::Sllv { rd, rt, rs } => three("sllv", rd, rt, rs),
+        Instruction::Srlv { rd, rt, rs } => three("srlv", rd, rt, rs),
+        Instruction::Rotrv { rd, rt, rs } => three("rotrv", rd, rt, rs),
+        Instruction::Srav { rd, rt, rs } => three("srav", rd, rt, rs),
+        Instruction::Jr { rs } => one("jr", rs),
+        Instruction::Jalr { rd: 31, rs } => one("jalr", rs),
+        Instruction::Jalr { rd, rs } => two("jalr", rd, rs),
+        Instruction::Movz { rd, rs, rt } => three("movz", rd, rs, rt),
+        Instruction::Movn { rd, rs, rt } => three("movn", rd, rs, rt),
+        Instruction::Syscall { code: 0 } => String::from("syscall"),
+        Instruction::Syscall { code } => format!("syscall\t{:#x}", code),
+        // code is split into two fields for the debugger
+        Instruction::Break { code } => match (code >> 10, code & 0x3ff) {
+            (0, 0) => String::from("break"),
+            (code, 0) => format!("break\t{:#x}", code),
+            (code, code2) => format!("break\t{:#x},{:#x}", code, code2),
+        },
+        Instruction::Mfhi { rd } => one("mfhi", rd),
+        Instruction::Mthi { rs } => one("mthi", rs),
+        Instruction::Mflo { rd } => one("mflo", rd),
+        Instruction::Mtlo { rs } => one("mtlo", rs),
+        Instruction::Mult { rs, rt } => two("mult", rs, rt),
+        Instruction::Multu { rs, rt } => two("multu", rs, rt),
+        Instruction::Div { rs, rt } => three("div", 0, rs, rt),
+        Instruction::Divu { rs, rt } => three("divu", 0, rs, rt),
+        Instruction::Add { rd, rs, rt } => three("add", rd, rs, rt),
+        Instruction::Addu { rd, rs, rt: 0 } => two("move", rd, rs),
+        Instruction::Addu { rd, rs, rt } => three("addu", rd, rs, rt),
+        Instruction::Sub { rd, rs: 0, rt } => two("neg", rd, rt),
+        Instruction::Sub { rd, rs, rt } => three("sub", rd, rs, rt),
+        Instruction::Subu { rd, rs: 0, rt } => two("negu", rd, rt),
+        Instruction::Subu { rd, rs, rt } => three("subu", rd, rs, rt),
+        Instruction::And { rd, rs, rt } => three("and", rd, rs, rt),
+        Instruction::Or { rd, rs, rt: 0 } => two("move", rd, rs),
+        Instruction::Or { rd, rs, rt } => three("or", rd, rs, rt),
+        Instruction::Xor { rd, rs, rt } => three("xor", rd, rs, rt),
+        Instruction::Nor { rd, rs, rt: 0 } => two("not", rd, rs),
+        Instruction::Nor { rd, rs, rt } => three("nor", rd, rs, rt),
+        Instruction::Slt { rd, rs, rt } => three("slt", rd, rs, rt),
+        Instruction::Sltu { rd, rs, rt } => three("sltu", rd, rs, rt),
+        Instruction::Tge { rs, rt, code } => trap("tge", rs, rt, code),
+        Instruction::Tgeu { rs, rt, code } => trap("tgeu", rs, rt, code),
+        Instruction::Tlt { rs, rt, code } => trap("tlt", rs, rt, code),
+        Instruction::Tltu { rs, rt, code } => trap("tltu", rs, rt, code),
+        Instruction::Teq { rs, rt, code } => trap("teq", rs, rt, code),
+        Instruction::Tne { rs, rt, code } => trap("tne", rs, rt, code),
+        Instruction::Sync { stype: 0 } => String::from("sync"),
+        Instruction::Sync { stype } => format!("sync\t{:#x}", stype),
+        Instruction::Bltz { rs, offset } => branch_zero("bltz", rs, offset),
+        Instruction::Bgez { rs, offset } => branch_zero("bgez", rs, offset),
+        Instruction::Bltzl { rs, offset } => branch_zero("bltzl", rs, offset),
+        Instruction::Bgezl { rs, offset } => branch_zero("bgezl", rs, offset),
+        Instruction::Tgei { rs, imm } => trap_immediate("tgei", rs, imm),
+        Instruction::Tgeiu { rs, imm } => trap_immediate("tgeiu", rs, imm),
+        Instruction::Tlti { rs, imm } => trap_immediate("tlti", rs, imm),
+        Instruction::Tltiu { rs, imm } => trap_immediate("tltiu", rs, imm),
+        Instruction::Teqi { rs, imm } => trap_immediate("teqi", rs, imm),
+        Instruction::Tnei { rs, imm } => trap_immediate("tnei", rs, imm),
+        Instruction::Bltzal { rs, offset } => branch_zero("bltzal", rs, offset),
+        Instruction::Bgezal { rs: 0, offset } => format!("bal\t{}", branch(offset)),
+        Instruction::Bgezal { rs, offset } => branch_zero("bgezal", rs, offset),
+        Instruction::Bltzall { rs, offset } => branch_zero("bltzall", rs, offset),
+        Instruction::Bgezall { rs, offset } => branch_zero("bgezall", rs, offset),
+        Instruction::J { target } => format!("j\t{}", jump(target)),
+        Instruction::Jal { target } => format!("jal\t{}", jump(target)),
+        Instruction::Beq { rs: 0, rt: 0, offset } => format!("b\t{}", branch(offset)),
+        Instruction::Beq { rs, rt: 0, offset } => branch_zero("beqz", rs, offset),
+        Instruction::Beq { rs, rt, offset } => branch_compare("beq", rs, rt, offset),
+        Instruction::Bne { rs, rt: 0, offset } => branch_zero("bnez", rs, offset),
+        Instruction::Bne { rs, rt, offset } => branch_compare("bne", rs, rt, offset),
+        Instruction::Blez { rs, offset } => branch_zero("blez", rs, offset),
+        Instruction::Bgtz { rs, offset } => branch_zero("bgtz", rs, offset),
+        Instruction::Addi { rt, rs, imm } => immediate("addi", rt, rs, imm),
+        Instruction::Addiu { rt, rs: 0, imm } => format!("li\t{},{}", gpr(rt), imm as i32),
+        Instruction::Addiu { rt, rs, imm } => immediate("addiu", rt, rs, imm),
+        Instruction::Slti { rt, rs, imm } => immediate("slti", rt, rs, imm),
+        Instruction::Sltiu { rt, rs, imm } => immediate("sltiu", rt, rs, imm),
+        Instruction::Andi { rt, rs, imm } => logical("andi", rt, rs, imm),
+        Instruction::Ori { rt, rs: 0, imm } => format!("li\t{},{:#x}", gpr(rt), imm),
+        Instruction::Ori { rt, rs, imm } => logical("ori", rt, rs, imm),
+        Instruction::Xori { rt, rs, imm } => logical("xori", rt, rs, imm),
+        Instruction::Lui { rt, imm } => format!("lui\t{},{:#x}", gpr(rt), imm),
+        Instruction::Beql { rs, rt, offset } => branch_compare("beql", rs, rt, offset),
+        Instruction::Bnel { rs, rt, offset } => branch_compare("bnel", rs, rt, offset),
+        Instruction::Blezl { rs, offset } => branch_zero("blezl", rs, offset),
+        Instruction::Bgtzl { rs, offset } => branch_zero("bgtzl", rs, offset),
+        Instruction::Mfc0 { rt, rd, sel } => format!("mfc0\t{},{}", gpr(rt), cp0_register(rd, sel)),
+        Instruction::Mtc0 { rt, rd, sel } => format!("mtc0\t{},{}", gpr(rt), cp0_register(rd, sel)),
+        Instruction::Di { rt: 0 } => String::from("di"),
+        Instruction::Di { rt } => one("di", rt),
+        Instruction::Ei { rt: 0 } => String::from("ei"),
+        Instruction::Ei { rt } => one("ei", rt),
+        Instruction::Tlbr => String::from("tlbr"),
+        Instruction::Tlbwi => String::from("tlbwi"),
+        Instruction::Tlbwr => String::from("tlbwr"),
+        Instruction::Tlbp => String::from("tlbp"),
+        Instruction::Eret => String::from("eret"),
+        Instruction::Mfc1 { rt, fs } => format!("mfc1\t{},$f{}", gpr(rt), fs),
+        Instruction::Cfc1 { rt, fs } => format!("cfc1\t{},${}", gpr(rt), fs),
+        Instruction::Mfhc1 { rt, fs } => format!("mfhc1\t{},$f{}", gpr(rt), fs),
+        Instruction::Mtc1 { rt, fs } => format!("mtc1\t{},$f{}", gpr(rt), fs),
+        Instruction::Ctc1 { rt, fs } => format!("ctc1\t{},${}", gpr(rt), fs),
+        Instruction::Mthc1 { rt, fs } => format!("mthc1\t{},$f{}", gpr(rt), fs),
+        Instruction::Bc1f { cc, offset } => branch_cc("bc1f", cc, offset),
+        Instruction::Bc1t { cc, offset } => branch_cc("bc1t", cc, offset),
+        Instruction::Bc1fl { cc, offset } => branch_cc("bc1fl", cc, offset),
+        Instruction::Bc1tl { cc, offset } => branch_cc("bc1tl", cc, offset),
+        Instruction::AddFmt { fmt, fd, fs, ft } => fp_three("add", fmt, fd, fs, ft),
+        Instruction::SubFmt { fmt, fd, fs, ft } => fp_three("sub", fmt, fd, fs, ft),
+        Instruction::MulFmt { fmt, fd, fs, ft } => fp_three("mul", fmt, fd, fs, ft),
+        Instruction::DivFmt { fmt, fd, fs, ft } => fp_three("div", fmt, fd, fs, ft),
+        Instruction::SqrtFmt { fmt, fd, fs } => fp_two("sqrt", fmt, fd, fs),
+        Instruction::AbsFmt { fmt, fd, fs } => fp_two("abs", fmt, fd, fs),
+        Instruction::MovFmt { fmt, fd, fs } => fp_two("mov", fmt, fd, fs),
+        Instruction::NegFmt { fmt, fd, fs } => fp_two("neg", fmt, fd, fs),
+        Instruction::RoundW { fmt, fd, fs } => fp_two("round.w", fmt, fd, fs),
+        Instruction::TruncW { fmt, fd, fs } => fp_two("trunc.w", fmt, fd, fs),
+        Instruction::CeilW { fmt, fd, fs } => fp_two("ceil.w", fmt, fd, fs),
+        Instruction::FloorW { fmt, fd, fs } => fp_two("floor.w", fmt, fd, fs),
+        Instruction::MovfFmt { fmt, fd, fs, cc } => format!("movf.{}\t$f{},$f{},$fcc{}", format_name(fmt), fd, fs, cc),
+        Instruction::MovtFmt { fmt, fd, fs, cc } => format!("movt.{}\t$f{},$f{},$fcc{}", format_name(fmt), fd, fs, cc),
+        Instruction::MovzFmt { fmt, fd, fs, rt } => format!("movz.{}\t$f{},$f{},{}", format_name(fmt), fd, fs, gpr(rt)),
+        Instruction::MovnFmt { fmt, fd, fs, rt } => format!("movn.{}\t$f{},$f{},{}", format_name(fmt), fd, fs, gpr(rt)),
+        Instruction::CvtS { fmt, fd, fs } => fp_two("cvt.s", fmt, fd, fs),
+        Instruction::CvtD { fmt, fd, fs } => fp_two("cvt.d", fmt, fd, fs),
+        Instruction::CvtW { fmt, fd, fs } => fp_two("cvt.w", fmt, fd, fs),
+        Instruction::CFmt { fmt, cond, cc: 0, fs, ft } => {
+            format!("c.{}.{}\t$f{},$f{}", CONDITIONS[cond as usize], format_name(fmt), fs, ft)
+        }
+        Instruction::CFmt { fmt, cond, cc, fs, ft } => {
+            format!("c.{}.{}\t$fcc{},$f{},$f{}", CONDITIONS[cond as usize], format_name(fmt), cc, fs, ft)
+        }
+        Instruction::Madd { rs, rt } => two("madd", rs, rt),
+        Instruction::Maddu { rs, rt } => two("maddu", rs, rt),
+        Instruction::Mul { rd, rs, rt } => three("mul", rd, rs, rt),
+        Instruction::Clz { rd, rs } => two("clz", rd, rs),
+        Instruction::Clo { rd, rs } => two("clo", rd, rs),
+        Instruction::Ext { rt, rs, pos, size } => format!("ext\t{},{},{:#x},{:#x}", gpr(rt), gpr(rs), pos, size),
+        Instruction::Ins { rt, rs, pos, size } => format!("ins\t{},{},{:#x},{:#x}", gpr(rt), gpr(rs), pos, size),
+        Instruction::Wsbh { rd, rt } => two("wsbh", rd, rt),
+        Instruction::Seb { rd, rt } => two("seb", rd, rt),
+        Instruction::Seh { rd, rt } => two("seh", rd, rt),
+        Instruction::Rdhwr { rt, rd } => format!("rdhwr\t{},${}", gpr(rt), rd),
+        Instruction::Lb { rt, base, offset } => memory("lb", gpr(rt), base, offset),
+        Instruction::Lh { rt, base, offset } => memory("lh", gpr(rt), base, offset),
+        Instruction::Lwl { rt, base, offset } => memory("lwl", gpr(rt), base, offset),
+        Instruction::Lw { rt, base, offset } => memory("lw", gpr(rt), base, offset),
+        Instruction::Lbu { rt, base, offset } => memory("lbu", gpr(rt), base, offset),
+        Instruction::Lhu { rt, base, offset } => memory("lhu", gpr(rt), base, offset),
+        Instruction::Lwr { rt, base, offset } => memory("lwr", gpr(rt), base, offset),
+        Instruction::Sb { rt, base, offset } => memory("sb", gpr(rt), base, offset),
+        Instruction::Sh { rt, base, offset } => memory("sh", gpr(rt), base, offset),
+        Instruction::Swl { rt, base, offset } => memory("swl", gpr(rt), base, offset),
+        Instruction::Sw { rt, base, offset } => memory("sw", gpr(rt), base, offset),
+        Instruction::Swr { rt, base, offset } => memory("swr", gpr(rt), base, offset),
+        Instruction::Ll { rt, base, offset } => memory("ll", gpr(rt), base, offset),
+        Instruction::Lwc1 { ft, base, offset } => memory("lwc1", &fpr(ft), base, offset),
+        Instruction::Ldc1 { ft, base, offset } => memory("ldc1", &fpr(ft), base, offset),
+        Instruction::Sc { rt, base, offset } => memory("sc", gpr(rt), base, offset),
+        Instruction::Swc1 { ft, base, offset } => memory("swc1", &fpr(ft), base, offset),
+        Instruction::Sdc1 { ft, base, offset } => memory("sdc1", &fpr(ft), base, offset),
+        Instruction::Cache { op, base, offset } => memory("cache", &format!("{:#x}", op), base, offset),
+        Instruction::Pref { hint, base, offset } => memory("pref", &format!("{:#x}", hint), base, offset),
+    }
+}
+
+// Disassemble the word at pc, words SIMP cannot execute are printed as data
+pub fn disassemble(inst: u32, pc: u32, debug_info: &DebugInfo) -> String {
+    match decode(inst) {
+        Ok(instruction) => format_instruction(&instruction, pc, debug_info),
+        Err(_) => format!(".word\t{:#x}", inst),
+    }
+}
+
+// objdump -d style listing of the words in code starting at addr
+fn dump_code(code: &[u8], addr: u32, debug_info: &DebugInfo) {
+    for (i, word) in code.chunks_exact(4).enumerate() {
+        let pc = addr.wrapping_add(i as u32 * 4);
+        if let Some((name, 0)) = debug_info.symbol(pc) {
+            println!("\n{:08x} <{}>:", pc, name);
+        }
+        let inst = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+        println!("{:8x}:\t{:08x} \t{}", pc, inst, disassemble(inst, pc, debug_info));
+    }
+}
+
+// `simp disas <file>`: disassemble the executable sections of an ELF file,
+// or a raw binary as the boot ROM
+pub fn dump(filename: &str, binary: Vec<u8>) -> std::io::Result<()> {
+    if !Elf::is_elf(&binary) {
+        println!("{}:     file format binary\n\n\nDisassembly of section .data:", filename);
+        dump_code(&binary, BOOT_EXCEPTION_VECTOR, &DebugInfo::default());
+        return Ok(());
+    }
+    let elf = Elf::parse(binary)?;
+    let debug_info = DebugInfo::new(&elf);
+    println!("{}:     file format elf32-tradlittlemips\n", filename);
+    for (name, addr, code) in elf.code_sections() {
+        println!("\nDisassembly of section {}:", name);
+        if debug_info.symbol(addr).map(|(_, offset)| offset) != Some(0) {
+            println!("\n{:08x} <{}>:", addr, name);
+        }
+        dump_code(code, addr, &debug_info);
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use std::io;
+
+use crate::cpu::*;
+use crate::debug_info::*;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_MIPS: u16 = 8;
+
+const ELF_HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_PHDR: u32 = 6;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_EXECINSTR: u32 = 0x4;
+const SHN_UNDEF: u16 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub offset: u32,
+    pub vaddr: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+}
+
+struct SectionHeader {
+    name: String,
+    sh_type: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+
+// ELF32 little endian MIPS executable
+pub struct Elf {
+    pub entry: u32,
+    pub phoff: u32,
+    pub program_headers: Vec<ProgramHeader>,
+    sections: Vec<SectionHeader>,
+    // function and object symbols of .symtab, sorted by address
+    pub symbols: Vec<Symbol>,
+    data: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("elf: {}", message))
+}
+
+fn read16(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) | ((data[offset + 1] as u16) << 8)
+}
+
+fn read32(data: &[u8], offset: usize) -> u32 {
+    (data[offset] as u32)
+        | ((data[offset + 1] as u32) << 8)
+        | ((data[offset + 2] as u32) << 16)
+        | ((data[offset + 3] as u32) << 24)
+}
+
+fn read_string(data: &[u8], offset: usize) -> String {
+    let bytes = data.get(offset..).unwrap_or(&[]);
+    let len = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+impl Elf {
+    pub fn is_elf(binary: &[u8]) -> bool {
+        binary.len() >= 4 && binary[0..4] == ELF_MAGIC
+    }
+
+    pub fn parse(data: Vec<u8>) -> io::Result<Elf> {
+        if data.len() < ELF_HEADER_SIZE || data[0..4] != ELF_MAGIC {
+            return Err(invalid("not an ELF file"));
+        }
+        if data[4] != ELFCLASS32 || data[5] != ELFDATA2LSB {
+            return Err(invalid("not a 32-bit little endian ELF file"));
+        }
+        if read16(&data, 18) != EM_MIPS {
+            return Err(invalid("not a MIPS ELF file"));
+        }
+
+        let entry = read32(&data, 24);
+        let phoff = read32(&data, 28);
+        let phentsize = read16(&data, 42) as usize;
+        let phnum = read16(&data, 44) as usize;
+        if phnum != 0 && phentsize < PROGRAM_HEADER_SIZE {
+            return Err(invalid("bad program header size"));
+        }
+
+        let mut program_headers = Vec::new();
+        for i in 0..phnum {
+            let offset = phoff as usize + i * phentsize;
+            if offset + PROGRAM_HEADER_SIZE > data.len() {
+                return Err(invalid("program header out of range"));
+            }
+            let header = ProgramHeader {
+                p_type: read32(&data, offset),
+                offset: read32(&data, offset + 4),
+                vaddr: read32(&data, offset + 8),
+                filesz: read32(&data, offset + 16),
+                memsz: read32(&data, offset + 20),
+            };
+            if header.offset as usize + header.filesz as usize > data.len() {
+                return Err(invalid("segment out of range"));
+            }
+            program_headers.push(header);
+        }
+
+        let sections = Elf::parse_sections(&data)?;
+        let mut elf = Elf {
+            entry,
+            phoff,
+            program_headers,
+            sections,
+            symbols: Vec::new(),
+            data,
+        };
+        elf.symbols = elf.parse_symbols()?;
+        Ok(elf)
+    }
+
+    fn parse_sections(data: &[u8]) -> io::Result<Vec<SectionHeader>> {
+        let shoff = read32(data, 32) as usize;
+        let shentsize = read16(data, 46) as usize;
+        let shnum = read16(data, 48) as usize;
+        let shstrndx = read16(data, 50) as usize;
+        if shnum != 0 && shentsize < SECTION_HEADER_SIZE {
+            return Err(invalid("bad section header size"));
+        }
+
+        let mut sections = Vec::new();
+        let mut names = Vec::new();
+        for i in 0..shnum {
+            let offset = shoff + i * shentsize;
+            if offset + SECTION_HEADER_SIZE > data.len() {
+                return Err(invalid("section header out of range"));
+            }
+            names.push(read32(data, offset) as usize);
+            sections.push(SectionHeader {
+                name: String::new(),
+                sh_type: read32(data, offset + 4),
+                flags: read32(data, offset + 8),
+                addr: read32(data, offset + 12),
+                offset: read32(data, offset + 16),
+                size: read32(data, offset + 20),
+                link: read32(data, offset + 24),
+            });
+        }
+
+        // section names live in the section header string table
+        if let Some(strtab) = sections.get(shstrndx) {
+            let base = strtab.offset as usize;
+            let names: Vec<String> = names.iter().map(|name| read_string(data, base + name)).collect();
+            for (section, name) in sections.iter_mut().zip(names) {
+                section.name = name;
+            }
+        }
+        Ok(sections)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        let section = self.sections.iter().find(|section| section.name == name)?;
+        self.section_data(section).ok()
+    }
+
+    // name, address and contents of the sections holding instructions
+    pub fn code_sections(&self) -> Vec<(&str, u32, &[u8])> {
+        self.sections
+            .iter()
+            .filter(|section| section.flags & SHF_EXECINSTR != 0 && section.sh_type != SHT_NOBITS)
+            .filter_map(|section| Some((section.name.as_str(), section.addr, self.section_data(section).ok()?)))
+            .collect()
+    }
+
+    fn section_data(&self, section: &SectionHeader) -> io::Result<&[u8]> {
+        let start = section.offset as usize;
+        self.data
+            .get(start..start + section.size as usize)
+            .ok_or_else(|| invalid(&format!("section {} out of range", section.name)))
+    }
+
+    fn parse_symbols(&self) -> io::Result<Vec<Symbol>> {
+        let mut symbols = Vec::new();
+        let symtab = match self.sections.iter().find(|section| section.sh_type == SHT_SYMTAB) {
+            Some(symtab) => symtab,
+            None => return Ok(symbols),
+        };
+        let strtab = match self.sections.get(symtab.link as usize) {
+            Some(strtab) => self.section_data(strtab)?,
+            None => return Err(invalid("symbol table without string table")),
+        };
+
+        for entry in self.section_data(symtab)?.chunks_exact(SYMBOL_SIZE) {
+            let kind = entry[12] & 0xf;
+            let shndx = read16(entry, 14);
+            if (kind != STT_FUNC && kind != STT_OBJECT) || shndx == SHN_UNDEF {
+                continue;
+            }
+            let name = read_string(strtab, read32(entry, 0) as usize);
+            if name.is_empty() {
+                continue;
+            }
+            symbols.push(Symbol {
+                name,
+                addr: read32(entry, 4),
+                size: read32(entry, 8),
+            });
+        }
+        symbols.sort_by_key(|symbol| symbol.addr);
+        Ok(symbols)
+    }
+
+    // Copy every PT_LOAD segment to the physical address its virtual address
+    // translates to, zero the rest of the segment (.bss) and start at e_entry
+    pub fn load(&self, cpu: &mut Cpu) -> io::Result<()> {
+        for header in self.program_headers.iter().filter(|header| header.p_type == PT_LOAD) {
+            if header.filesz > header.memsz {
+                return Err(invalid("segment file size exceeds its memory size"));
+            }
+            // reset leaves kuseg unmapped
+            let addr = cpu.mmu(header.vaddr, false).map_err(|_| {
+                invalid(&format!("segment at {:#x} is mapped through the TLB", header.vaddr))
+            })?;
+            // the whole segment must fit in one device, .bss is zeroed in place
+            let loaded = addr.checked_add(header.memsz).is_some()
+                && cpu
+                    .bus
+                    .zero_image(addr, header.memsz)
+                    .and_then(|_| cpu.bus.load_image(addr, self.segment_data(header)))
+                    .is_ok();
+            if !loaded {
+                return Err(invalid(&format!(
+                    "segment at {:#x} (physical {:#x}) outside of mapped memory",
+                    header.vaddr, addr
+                )));
+            }
+        }
+        cpu.pc = self.entry;
+        cpu.debug_info = DebugInfo::new(self);
+        Ok(())
+    }
+
+    // File contents of a segment, memsz - filesz bytes of zeros follow it in memory
+    pub fn segment_data(&self, header: &ProgramHeader) -> &[u8] {
+        let start = header.offset as usize;
+        &self.data[start..start + header.filesz as usize]
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+use crate::decode::*;
+
+// A physical access that no device completed. size is in bits, as in
+// Bus::load and Bus::store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    // no device decodes the address
+    Unmapped { addr: u32, size: u32 },
+    // the device has no access of that width
+    InvalidWidth { addr: u32, size: u32 },
+}
+
+impl BusError {
+    // the same error for a device at base, devices report their own offsets
+    pub fn offset(self, base: u32) -> Self {
+        match self {
+            BusError::Unmapped { addr, size } => BusError::Unmapped { addr: addr + base, size },
+            BusError::InvalidWidth { addr, size } => BusError::InvalidWidth { addr: addr + base, size },
+        }
+    }
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::Unmapped { addr, size } => write!(f, "no device at physical {:#x} ({} bits)", addr, size),
+            BusError::InvalidWidth { addr, size } => {
+                write!(f, "no {} bit access at physical {:#x}", size, addr)
+            }
+        }
+    }
+}
+
+// Why the fetch/execute loop stopped other than by reaching pc 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    // the instruction at pc could not be read
+    Fetch { pc: u32, error: BusError },
+    // the load or store at pc to the virtual address addr failed and no Bus
+    // Error handler ran
+    DataBus { pc: u32, addr: u32, error: BusError },
+    // the word at pc is an instruction SIMP does not implement and no
+    // Reserved Instruction handler ran
+    Decode { pc: u32, error: DecodeError },
+    // the program exited, through a syscall or an uncaught exception
+    Halt { status: i32 },
+}
+
+impl CpuError {
+    // address of the instruction the error is about, printed by
+    // Cpu::describe_error
+    pub fn pc(&self) -> Option<u32> {
+        match self {
+            CpuError::Fetch { pc, .. } | CpuError::DataBus { pc, .. } | CpuError::Decode { pc, .. } => Some(*pc),
+            CpuError::Halt { .. } => None,
+        }
+    }
+
+    // exit status of simp, the program's own one when it exited
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CpuError::Fetch { .. } | CpuError::DataBus { .. } => 2,
+            CpuError::Decode { .. } => 3,
+            CpuError::Halt { status } => *status,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Fetch { error, .. } => write!(f, "instruction fetch failed, {}", error),
+            CpuError::DataBus { addr, error, .. } => write!(f, "data access to {:#x} failed, {}", addr, error),
+            CpuError::Decode { error, .. } => {
+                write!(f, "instruction {:#010x} ({}) is not implemented", error.word, error.field)
+            }
+            CpuError::Halt { status } => write!(f, "program exited with status {}", status),
+        }
+    }
+}
+
+// Why .debug_line could not be read, offsets are into the section holding
+// the malformed data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DwarfError {
+    // a field runs past the end of the section
+    Truncated { offset: usize },
+    // a line program header that cannot be used
+    InvalidHeader { offset: usize },
+    UnsupportedVersion { version: u16 },
+    // a DWARF 5 entry format SIMP cannot skip
+    UnsupportedForm { form: u64 },
+}
+
+impl fmt::Display for DwarfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DwarfError::Truncated { offset } => write!(f, "truncated at offset {:#x}", offset),
+            DwarfError::InvalidHeader { offset } => write!(f, "invalid line program header at offset {:#x}", offset),
+            DwarfError::UnsupportedVersion { version } => write!(f, "unsupported DWARF version {}", version),
+            DwarfError::UnsupportedForm { form } => write!(f, "unsupported form {:#x}", form),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use crate::error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    // Store to a TLB page that is not dirty, carries the faulting virtual address
+    TlbModified(u32),
+    // No TLB entry on load or instruction fetch, carries the faulting virtual address
+    TlbRefillLoad(u32),
+    // No TLB entry on store, carries the faulting virtual address
+    TlbRefillStore(u32),
+    // Invalid TLB page on load or instruction fetch, carries the faulting virtual address
+    TlbInvalidLoad(u32),
+    // Invalid TLB page on store, carries the faulting virtual address
+    TlbInvalidStore(u32),
+    // Address error on load or instruction fetch, carries the faulting virtual address
+    AddressErrorLoad(u32),
+    // Address error on store, carries the faulting virtual address
+    AddressErrorStore(u32),
+    // Load or store no device completed, carries the virtual address and the
+    // physical access
+    DataBusError { addr: u32, error: BusError },
+    Syscall,
+    Breakpoint,
+    ReservedInstruction,
+    // carries the number of the unusable coprocessor
+    CoprocessorUnusable(u32),
+    IntegerOverflow,
+    Trap,
+    FloatingPoint,
+}
+
+impl Exception {
+    pub fn exc_code(&self) -> u32 {
+        match self {
+            Exception::TlbModified(_) => 0x01,
+            Exception::TlbRefillLoad(_) | Exception::TlbInvalidLoad(_) => 0x02,
+            Exception::TlbRefillStore(_) | Exception::TlbInvalidStore(_) => 0x03,
+            Exception::AddressErrorLoad(_) => 0x04,
+            Exception::AddressErrorStore(_) => 0x05,
+            Exception::DataBusError { .. } => 0x07,
+            Exception::Syscall => 0x08,
+            Exception::Breakpoint => 0x09,
+            Exception::ReservedInstruction => 0x0a,
+            Exception::CoprocessorUnusable(_) => 0x0b,
+            Exception::IntegerOverflow => 0x0c,
+            Exception::Trap => 0x0d,
+            Exception::FloatingPoint => 0x0f,
+        }
+    }
+
+    pub fn bad_vaddr(&self) -> Option<u32> {
+        match self {
+            Exception::AddressErrorLoad(addr)
+            | Exception::AddressErrorStore(addr)
+            | Exception::DataBusError { addr, .. } => Some(*addr),
+            _ => self.tlb_vaddr(),
+        }
+    }
+
+    // faulting virtual address of a TLB exception
+    pub fn tlb_vaddr(&self) -> Option<u32> {
+        match self {
+            Exception::TlbModified(addr)
+            | Exception::TlbRefillLoad(addr)
+            | Exception::TlbRefillStore(addr)
+            | Exception::TlbInvalidLoad(addr)
+            | Exception::TlbInvalidStore(addr) => Some(*addr),
+            _ => None,
+        }
+    }
+
+    // taken at the TLB refill vector unless EXL is already set
+    pub fn is_tlb_refill(&self) -> bool {
+        matches!(self, Exception::TlbRefillLoad(_) | Exception::TlbRefillStore(_))
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+
+use crate::cp0::*;
+use crate::cpu::*;
+use crate::error::*;
+
+// register numbers of the GDB MIPS target description
+const REG_STATUS: usize = 32;
+const REG_LO: usize = 33;
+const REG_HI: usize = 34;
+const REG_BADVADDR: usize = 35;
+const REG_CAUSE: usize = 36;
+const REG_PC: usize = 37;
+const REG_F0: usize = 38;
+const REG_FCSR: usize = 70;
+const REG_FIR: usize = 71;
+const NUM_REGS: usize = 72;
+
+// GDB signal numbers
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+
+// instructions executed between checks for a Ctrl-C from gdb
+const INTERRUPT_POLL_INTERVAL: u32 = 4096;
+
+enum Stop {
+    Signal(u8),
+    Watch(Watchpoint),
+    Exited(i32),
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<architecture>mips</architecture>\n",
+    );
+    xml.push_str("<feature name=\"org.gnu.gdb.mips.cpu\">\n");
+    for i in 0..32 {
+        xml.push_str(&format!("<reg name=\"r{}\" bitsize=\"32\" regnum=\"{}\"/>\n", i, i));
+    }
+    xml.push_str(&format!("<reg name=\"lo\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_LO));
+    xml.push_str(&format!("<reg name=\"hi\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_HI));
+    xml.push_str(&format!("<reg name=\"pc\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_PC));
+    xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.mips.cp0\">\n");
+    xml.push_str(&format!("<reg name=\"status\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_STATUS));
+    xml.push_str(&format!("<reg name=\"badvaddr\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_BADVADDR));
+    xml.push_str(&format!("<reg name=\"cause\" bitsize=\"32\" regnum=\"{}\"/>\n", REG_CAUSE));
+    xml.push_str("</feature>\n<feature name=\"org.gnu.gdb.mips.fpu\">\n");
+    for i in 0..32 {
+        xml.push_str(&format!(
+            "<reg name=\"f{}\" bitsize=\"32\" type=\"ieee_single\" regnum=\"{}\"/>\n",
+            i,
+            REG_F0 + i
+        ));
+    }
+    xml.push_str(&format!("<reg name=\"fcsr\" bitsize=\"32\" group=\"float\" regnum=\"{}\"/>\n", REG_FCSR));
+    xml.push_str(&format!("<reg name=\"fir\" bitsize=\"32\" group=\"float\" regnum=\"{}\"/>\n", REG_FIR));
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn read_register(cpu: &Cpu, reg: usize) -> Option<u32> {
+    match reg {
+        0..=31 => Some(cpu.regs[reg]),
+        REG_STATUS => Some(cpu.cp0.status),
+        REG_LO => Some(cpu.lo),
+        REG_HI => Some(cpu.hi),
+        REG_BADVADDR => Some(cpu.cp0.bad_vaddr),
+        REG_CAUSE => Some(cpu.cp0.cause),
+        REG_PC => Some(cpu.pc),
+        REG_F0..=69 => Some(cpu.cp1.fpr[reg - REG_F0]),
+        REG_FCSR => Some(cpu.cp1.fcsr),
+        REG_FIR => Some(cpu.cp1.fir),
+        _ => None,
+    }
+}
+
+fn write_register(cpu: &mut Cpu, reg: usize, value: u32) -> bool {
+    match reg {
+        0 => {}
+        1..=31 => cpu.regs[reg] = value,
+        // read only and reserved bits keep their value
+        REG_STATUS => cpu.cp0.write(CP0_STATUS, 0, value),
+        REG_LO => cpu.lo = value,
+        REG_HI => cpu.hi = value,
+        REG_BADVADDR => cpu.cp0.bad_vaddr = value,
+        REG_CAUSE => cpu.cp0.write(CP0_CAUSE, 0, value),
+        REG_PC => cpu.pc = value,
+        REG_F0..=69 => cpu.cp1.fpr[reg - REG_F0] = value,
+        REG_FCSR => cpu.cp1.fcsr = value,
+        // read only
+        REG_FIR => {}
+        _ => return false,
+    }
+    true
+}
+
+// registers are sent in target (little endian) byte order
+fn encode_u32(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_u32(hex: &str) -> Option<u32> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != 4 {
+        return None;
+    }
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+// "addr,len" of m, M, Z and z packets
+fn parse_addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, u32::from_str_radix(len, 16).ok()?))
+}
+
+struct GdbStub {
+    stream: TcpStream,
+    breakpoints: HashSet<u32>,
+    // why the program last stopped, unless at pc 0 or a breakpoint
+    stopped: Option<CpuError>,
+}
+
+impl GdbStub {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8; 1];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    // Returns the next packet, or None when gdb sent an interrupt (Ctrl-C)
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => break,
+                0x03 => return Ok(None),
+                // acks and noise between packets
+                _ => {}
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte()?, self.read_byte()?];
+        let expected = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        let sum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if expected != Some(sum) {
+            self.stream.write_all(b"-")?;
+            return self.read_packet();
+        }
+        self.stream.write_all(b"+")?;
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, sum);
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            // resend until gdb acknowledges
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // A pending Ctrl-C from gdb, checked without blocking
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = matches!(self.stream.read(&mut byte), Ok(1) if byte[0] == 0x03);
+        let _ = self.stream.set_nonblocking(false);
+        interrupted
+    }
+
+    // Execute one instruction
+    fn step(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        cpu.watch_hit = None;
+        let stepped = cpu.fetch_decoded().and_then(|decoded| {
+            cpu.pc = cpu.pc.wrapping_add(4);
+            cpu.execute_decoded(decoded)
+        });
+        if let Err(error) = stepped {
+            self.stopped = Some(error);
+            return Some(match error {
+                CpuError::Halt { status } => Stop::Exited(status),
+                CpuError::Decode { .. } => Stop::Signal(SIGILL),
+                CpuError::Fetch { .. } => Stop::Signal(SIGSEGV),
+                CpuError::DataBus { .. } => Stop::Signal(SIGBUS),
+            });
+        }
+        if cpu.pc == 0 {
+            return Some(Stop::Exited(0));
+        }
+        cpu.watch_hit.map(Stop::Watch)
+    }
+
+    fn resume(&mut self, cpu: &mut Cpu, single_step: bool) -> Stop {
+        let mut count = 0u32;
+        loop {
+            // a breakpoint at the resume address has already been reported
+            if let Some(stop) = self.step(cpu) {
+                return stop;
+            }
+            if single_step || self.breakpoints.contains(&cpu.pc) {
+                return Stop::Signal(SIGTRAP);
+            }
+            count += 1;
+            if count == INTERRUPT_POLL_INTERVAL {
+                count = 0;
+                if self.interrupted() {
+                    return Stop::Signal(SIGINT);
+                }
+            }
+        }
+    }
+
+    fn stop_reply(stop: &Stop) -> String {
+        match stop {
+            Stop::Signal(signal) => format!("S{:02x}", signal),
+            Stop::Watch(watchpoint) => {
+                let kind = match watchpoint.kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, kind, watchpoint.addr)
+            }
+            Stop::Exited(status) => format!("W{:02x}", status & 0xff),
+        }
+    }
+
+    // Memory accesses of the debugger translate addresses like the program
+    // but raise no exceptions, hit no watchpoints and keep the link bit
+    fn read_memory(cpu: &Cpu, addr: u32, len: u32) -> Option<String> {
+        let mut hex = String::new();
+        for i in 0..len {
+            let byte = cpu.peek(addr.wrapping_add(i))?;
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        Some(hex)
+    }
+
+    // Nothing is written unless every byte is writable. Like ptrace, clean
+    // TLB pages can be written.
+    fn write_memory(cpu: &mut Cpu, addr: u32, bytes: &[u8]) -> Option<()> {
+        let physical_addrs = (0..bytes.len() as u32)
+            .map(|i| cpu.mmu(addr.wrapping_add(i), false).ok().filter(|&addr| cpu.bus.writable(addr)))
+            .collect::<Option<Vec<u32>>>()?;
+        for (physical_addr, byte) in physical_addrs.into_iter().zip(bytes) {
+            cpu.bus.store(physical_addr, 8, *byte as u32).ok()?;
+        }
+        Some(())
+    }
+
+    fn set_breakpoint(&mut self, cpu: &mut Cpu, args: &str, insert: bool) -> Option<()> {
+        let (kind, args) = args.split_once(',')?;
+        let (addr, len) = parse_addr_len(args.split(';').next()?)?;
+        let watch_kind = match kind {
+            "0" => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return Some(());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+        let watchpoint = Watchpoint {
+            addr,
+            len,
+            kind: watch_kind,
+        };
+        if insert {
+            cpu.watchpoints.push(watchpoint);
+        } else {
+            cpu.watchpoints.retain(|w| *w != watchpoint);
+        }
+        Some(())
+    }
+
+    // Reply to a packet, or None when the session ends. Some(true) when gdb
+    // detached and the program keeps running.
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> io::Result<Option<bool>> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => String::from("S05"),
+            Some(b'g') => (0..NUM_REGS).map(|reg| encode_u32(read_register(cpu, reg).unwrap_or(0))).collect(),
+            Some(b'G') => {
+                let hex = &packet[1..];
+                for reg in 0..NUM_REGS.min(hex.len() / 8) {
+                    if let Some(value) = decode_u32(&hex[reg * 8..reg * 8 + 8]) {
+                        write_register(cpu, reg, value);
+                    }
+                }
+                String::from("OK")
+            }
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16).ok().and_then(|reg| read_register(cpu, reg)) {
+                Some(value) => encode_u32(value),
+                None => String::from("E01"),
+            },
+            Some(b'P') => {
+                let written = packet[1..].split_once('=').and_then(|(reg, value)| {
+                    let reg = usize::from_str_radix(reg, 16).ok()?;
+                    Some(write_register(cpu, reg, decode_u32(value)?))
+                });
+                match written {
+                    Some(true) => String::from("OK"),
+                    _ => String::from("E01"),
+                }
+            }
+            Some(b'm') => match parse_addr_len(&packet[1..]).and_then(|(addr, len)| Self::read_memory(cpu, addr, len)) {
+                Some(hex) => hex,
+                None => String::from("E01"),
+            },
+            Some(b'M') => {
+                let written = packet[1..].split_once(':').and_then(|(args, data)| {
+                    let (addr, len) = parse_addr_len(args)?;
+                    let bytes = decode_hex(data)?;
+                    if bytes.len() != len as usize {
+                        return None;
+                    }
+                    Self::write_memory(cpu, addr, &bytes)
+                });
+                match written {
+                    Some(()) => String::from("OK"),
+                    None => String::from("E01"),
+                }
+            }
+            Some(b'c') | Some(b's') => {
+                if let Ok(addr) = u32::from_str_radix(&packet[1..], 16) {
+                    cpu.pc = addr;
+                }
+                let stop = self.resume(cpu, packet.starts_with('s'));
+                self.send_packet(&Self::stop_reply(&stop))?;
+                return Ok(match stop {
+                    Stop::Exited(_) => None,
+                    _ => Some(false),
+                });
+            }
+            Some(b'Z') | Some(b'z') => match self.set_breakpoint(cpu, &packet[1..], packet.starts_with('Z')) {
+                Some(()) => String::from("OK"),
+                None => String::new(),
+            },
+            Some(b'H') => String::from("OK"),
+            Some(b'k') => return Ok(None),
+            Some(b'D') => {
+                self.send_packet("OK")?;
+                return Ok(Some(true));
+            }
+            Some(b'q') => self.query(packet),
+            // unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        self.send_packet(&reply)?;
+        Ok(Some(false))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            String::from("PacketSize=4000;qXfer:features:read+")
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml();
+            match parse_addr_len(args) {
+                Some((offset, len)) => {
+                    let start = (offset as usize).min(xml.len());
+                    let end = (start + len as usize).min(xml.len());
+                    let marker = if end == xml.len() { 'l' } else { 'm' };
+                    format!("{}{}", marker, &xml[start..end])
+                }
+                None => String::from("E01"),
+            }
+        } else if packet == "qAttached" {
+            String::from("1")
+        } else if packet == "qfThreadInfo" {
+            String::from("m1")
+        } else if packet == "qsThreadInfo" {
+            String::from("l")
+        } else if packet == "qC" {
+            String::from("QC1")
+        } else {
+            String::new()
+        }
+    }
+}
+
+// Wait for gdb on localhost and serve it until it kills the program, the
+// program exits, or gdb detaches. Returns None when the program should keep
+// running after a detach, otherwise the error it last stopped with.
+pub fn serve(cpu: &mut Cpu, port: u16) -> io::Result<Option<Result<(), CpuError>>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for gdb on 127.0.0.1:{}", port);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    let mut stub = GdbStub {
+        stream,
+        breakpoints: HashSet::new(),
+        stopped: None,
+    };
+    let stopped = |stub: &GdbStub| Some(stub.stopped.map_or(Ok(()), Err));
+    loop {
+        let packet = match stub.read_packet() {
+            Ok(Some(packet)) => packet,
+            // interrupt while stopped
+            Ok(None) => {
+                stub.send_packet("S02")?;
+                continue;
+            }
+            // gdb went away
+            Err(_) => return Ok(stopped(&stub)),
+        };
+        match stub.handle(cpu, &packet)? {
+            Some(false) => {}
+            Some(true) => {
+                cpu.watchpoints.clear();
+                return Ok(None);
+            }
+            None => return Ok(stopped(&stub)),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::io;
+
+use crate::bus::*;
+
+// Intel HEX record types
+const IHEX_DATA: u8 = 0x00;
+const IHEX_END_OF_FILE: u8 = 0x01;
+const IHEX_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const IHEX_START_SEGMENT_ADDRESS: u8 = 0x03;
+const IHEX_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const IHEX_START_LINEAR_ADDRESS: u8 = 0x05;
+
+fn invalid(format: &str, line: usize, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: line {}: {}", format, line, message))
+}
+
+// "0A1B.." to bytes
+fn decode_hex(digits: &str) -> Result<Vec<u8>, String> {
+    if digits.len() & 1 != 0 {
+        return Err(String::from("odd number of hex digits"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("invalid hex digits {:?}", &digits[i..]))
+        })
+        .collect()
+}
+
+fn load_record(bus: &mut Bus, addr: u32, data: &[u8]) -> Result<(), String> {
+    bus.load_image(addr, data).map_err(|_| {
+        format!(
+            "record at {:#x}..{:#x} falls outside mapped devices",
+            addr,
+            addr as u64 + data.len() as u64
+        )
+    })
+}
+
+// Load an Intel HEX image to the physical addresses of its records.
+// Execution starts at the reset vector, start address records are ignored.
+pub fn load_ihex(bus: &mut Bus, text: &str) -> io::Result<()> {
+    let mut base = 0u32;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| invalid("hex", number, "record does not start with ':'"))?;
+        let bytes = decode_hex(digits).map_err(|message| invalid("hex", number, &message))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(invalid("hex", number, "record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(invalid("hex", number, "checksum mismatch"));
+        }
+
+        let offset = ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            IHEX_DATA => {
+                load_record(bus, base.wrapping_add(offset), data).map_err(|message| invalid("hex", number, &message))?;
+            }
+            IHEX_END_OF_FILE => return Ok(()),
+            IHEX_EXTENDED_SEGMENT_ADDRESS | IHEX_EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let value = ((data[0] as u32) << 8) | data[1] as u32;
+                base = if bytes[3] == IHEX_EXTENDED_SEGMENT_ADDRESS {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            IHEX_START_SEGMENT_ADDRESS | IHEX_START_LINEAR_ADDRESS if data.len() == 4 => {}
+            record_type => {
+                return Err(invalid("hex", number, &format!("bad record of type {:#04x}", record_type)));
+            }
+        }
+    }
+    Err(invalid("hex", text.lines().count(), "missing end of file record"))
+}
+
+// Load a Motorola S-record image to the physical addresses of its records.
+// Execution starts at the reset vector, termination record addresses are ignored.
+pub fn load_srec(bus: &mut Bus, text: &str) -> io::Result<()> {
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err(invalid("srec", number, "record does not start with 'S'"));
+        }
+        let record_type = chars.next().and_then(|c| c.to_digit(10)).ok_or_else(|| invalid("srec", number, "bad record type"))?;
+        let bytes = decode_hex(&line[2..]).map_err(|message| invalid("srec", number, &message))?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(invalid("srec", number, "record length does not match its byte count"));
+        }
+        let (checksum, bytes) = bytes.split_last().unwrap();
+        if !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != *checksum {
+            return Err(invalid("srec", number, "checksum mismatch"));
+        }
+
+        let address_size = match record_type {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(invalid("srec", number, &format!("bad record type S{}", record_type))),
+        };
+        if bytes.len() < 1 + address_size {
+            return Err(invalid("srec", number, "record too short for its address"));
+        }
+        let addr = bytes[1..=address_size].iter().fold(0u32, |addr, byte| (addr << 8) | *byte as u32);
+        let data = &bytes[1 + address_size..];
+        match record_type {
+            1..=3 => load_record(bus, addr, data).map_err(|message| invalid("srec", number, &message))?,
+            7..=9 => return Ok(()),
+            // header and record counts
+            _ => {}
+        }
+    }
+    Err(invalid("srec", text.lines().count(), "missing termination record"))
+}
@@ -0,0 +1,984 @@
+use std::collections::HashMap;
+use std::io;
+use std::ptr;
+
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, FuncRef, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use crate::cpu::*;
+use crate::decode::*;
+use crate::decode_cache::*;
+use crate::error::*;
+
+// runs of a block in the interpreter before it is compiled
+const HOT_THRESHOLD: u32 = 16;
+
+// compiled blocks overwritten by stores before the module is rebuilt to free
+// their code
+const MAX_STALE_BLOCKS: usize = 1024;
+
+// Exit::delay
+const DELAY_NONE: u32 = 0;
+const DELAY_TAKEN: u32 = 1;
+const DELAY_NOT_TAKEN: u32 = 2;
+
+// return values of jit_store
+const STORE_DONE: u32 = 0;
+const STORE_FAULT: u32 = 1;
+const STORE_CODE_CHANGED: u32 = 2;
+
+// hi and lo follow the general purpose registers in the translator
+const HI: usize = 32;
+const LO: usize = 33;
+
+// Where a compiled block stopped, written before it returns the number of
+// instructions it executed
+#[repr(C)]
+#[derive(Default)]
+struct Exit {
+    pc: u32,
+    // DELAY_TAKEN or DELAY_NOT_TAKEN when pc is the delay slot of a branch
+    // the block executed
+    delay: u32,
+    target: u32,
+}
+
+type CompiledBlock = unsafe extern "C" fn(*mut Cpu, *mut u32, *mut u32, *mut u32, *mut Exit) -> u32;
+
+#[derive(Clone, Copy)]
+enum State {
+    // runs so far
+    Counting(u32),
+    Compiled(CompiledBlock),
+    // starts with an instruction the translator does not support
+    Interpreted,
+}
+
+// A block by the virtual address it starts at
+struct Entry {
+    physical_addr: u32,
+    // code version of the page the block was translated from
+    version: u32,
+    state: State,
+}
+
+fn error(message: impl ToString) -> io::Error {
+    io::Error::other(message.to_string())
+}
+
+// Load for compiled code: the value, or bit 32 set when the access faults.
+// The interpreter then executes the instruction again to raise the exception.
+extern "C" fn jit_load(cpu: *mut Cpu, addr: u32, size: u32) -> u64 {
+    let cpu = unsafe { &mut *cpu };
+    match cpu.load(addr, size) {
+        Ok(value) => value as u64,
+        Err(_) => 1 << 32,
+    }
+}
+
+// Store for compiled code
+extern "C" fn jit_store(cpu: *mut Cpu, addr: u32, size: u32, value: u32) -> u32 {
+    let cpu = unsafe { &mut *cpu };
+    let generation = cpu.bus.code_generation();
+    match cpu.store(addr, size, value) {
+        Err(_) => STORE_FAULT,
+        Ok(()) if cpu.bus.code_generation() != generation => STORE_CODE_CHANGED,
+        Ok(()) => STORE_DONE,
+    }
+}
+
+// Instructions the translator compiles, everything else is left to the
+// interpreter
+fn supported(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Sll { .. }
+            | Instruction::Srl { .. }
+            | Instruction::Sra { .. }
+            | Instruction::Sllv { .. }
+            | Instruction::Srlv { .. }
+            | Instruction::Srav { .. }
+            | Instruction::Jr { .. }
+            | Instruction::Jalr { .. }
+            | Instruction::Movz { .. }
+            | Instruction::Movn { .. }
+            | Instruction::Mfhi { .. }
+            | Instruction::Mthi { .. }
+            | Instruction::Mflo { .. }
+            | Instruction::Mtlo { .. }
+            | Instruction::Mult { .. }
+            | Instruction::Multu { .. }
+            | Instruction::Addu { .. }
+            | Instruction::Subu { .. }
+            | Instruction::And { .. }
+            | Instruction::Or { .. }
+            | Instruction::Xor { .. }
+            | Instruction::Nor { .. }
+            | Instruction::Slt { .. }
+            | Instruction::Sltu { .. }
+            | Instruction::Bltz { .. }
+            | Instruction::Bgez { .. }
+            | Instruction::Bltzal { .. }
+            | Instruction::Bgezal { .. }
+            | Instruction::J { .. }
+            | Instruction::Jal { .. }
+            | Instruction::Beq { .. }
+            | Instruction::Bne { .. }
+            | Instruction::Blez { .. }
+            | Instruction::Bgtz { .. }
+            | Instruction::Addiu { .. }
+            | Instruction::Slti { .. }
+            | Instruction::Sltiu { .. }
+            | Instruction::Andi { .. }
+            | Instruction::Ori { .. }
+            | Instruction::Xori { .. }
+            | Instruction::Lui { .. }
+            | Instruction::Mul { .. }
+            | Instruction::Lb { .. }
+            | Instruction::Lh { .. }
+            | Instruction::Lw { .. }
+            | Instruction::Lbu { .. }
+            | Instruction::Lhu { .. }
+            | Instruction::Sb { .. }
+            | Instruction::Sh { .. }
+            | Instruction::Sw { .. }
+    )
+}
+
+// Number of instructions at the start of a block the translator compiles. A
+// branch is compiled together with its delay slot or not at all.
+fn compiled_len(block: &[Decoded]) -> usize {
+    let mut len = 0;
+    while let Some(Ok(instruction)) = block.get(len) {
+        if !supported(instruction) {
+            break;
+        }
+        if instruction.is_branch() {
+            if let Some(Ok(slot)) = block.get(len + 1) {
+                if supported(slot) && !slot.is_branch() {
+                    len += 2;
+                }
+            }
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+// Translates the instructions of a block into a Cranelift function. The
+// registers live in variables, loaded on first use and written back to the
+// Cpu at every exit.
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    cpu: Value,
+    regs: Value,
+    hi: Value,
+    lo: Value,
+    exit: Value,
+    load: FuncRef,
+    store: FuncRef,
+    loaded: [bool; 34],
+    dirty: [bool; 34],
+    // condition and target of the branch whose delay slot is being translated
+    branch: Option<(Value, Value)>,
+}
+
+impl<'a> Translator<'a> {
+    fn location(&self, reg: usize) -> (Value, i32) {
+        match reg {
+            HI => (self.hi, 0),
+            LO => (self.lo, 0),
+            _ => (self.regs, 4 * reg as i32),
+        }
+    }
+
+    fn get(&mut self, reg: usize) -> Value {
+        if !self.loaded[reg] {
+            let (base, offset) = self.location(reg);
+            let value = self.builder.ins().load(types::I32, MemFlags::trusted(), base, offset);
+            self.builder.def_var(Variable::new(reg), value);
+            self.loaded[reg] = true;
+        }
+        self.builder.use_var(Variable::new(reg))
+    }
+
+    fn set(&mut self, reg: usize, value: Value) {
+        self.builder.def_var(Variable::new(reg), value);
+        self.loaded[reg] = true;
+        self.dirty[reg] = true;
+    }
+
+    fn constant(&mut self, value: u32) -> Value {
+        self.builder.ins().iconst(types::I32, value as i64)
+    }
+
+    // 1 when the condition holds, 0 otherwise
+    fn flag(&mut self, cc: IntCC, x: Value, y: Value) -> Value {
+        let condition = self.builder.ins().icmp(cc, x, y);
+        self.builder.ins().uextend(types::I32, condition)
+    }
+
+    // Write back the registers and return how far the block got
+    fn exit(&mut self, pc: Value, executed: usize, delay: Value, target: Value) {
+        for reg in 0..self.dirty.len() {
+            if self.dirty[reg] {
+                let value = self.builder.use_var(Variable::new(reg));
+                let (base, offset) = self.location(reg);
+                self.builder.ins().store(MemFlags::trusted(), value, base, offset);
+            }
+        }
+        for (value, offset) in [(pc, 0), (delay, 4), (target, 8)].iter() {
+            self.builder.ins().store(MemFlags::trusted(), *value, self.exit, *offset);
+        }
+        let executed = self.constant(executed as u32);
+        self.builder.ins().return_(&[executed]);
+    }
+
+    // Stop before the instruction at index, so that the interpreter executes
+    // it again and raises its exception
+    fn fault(&mut self, index: usize, pc: u32) {
+        let pc = self.constant(pc);
+        match self.branch {
+            Some((taken, target)) => {
+                let taken_delay = self.constant(DELAY_TAKEN);
+                let not_taken_delay = self.constant(DELAY_NOT_TAKEN);
+                let delay = self.builder.ins().select(taken, taken_delay, not_taken_delay);
+                self.exit(pc, index, delay, target);
+            }
+            None => {
+                let none = self.constant(DELAY_NONE);
+                self.exit(pc, index, none, none);
+            }
+        }
+    }
+
+    // pc after the instruction at pc, past the delay slot of a branch
+    fn next_pc(&mut self, pc: u32) -> Value {
+        let next = self.constant(pc.wrapping_add(4));
+        match self.branch {
+            Some((taken, target)) => self.builder.ins().select(taken, target, next),
+            None => next,
+        }
+    }
+
+    fn load_memory(&mut self, base: usize, offset: u32, size: u32, index: usize, pc: u32) -> Value {
+        let base = self.get(base);
+        let offset = self.constant(offset);
+        let addr = self.builder.ins().iadd(base, offset);
+        let size = self.constant(size);
+        let call = self.builder.ins().call(self.load, &[self.cpu, addr, size]);
+        let result = self.builder.inst_results(call)[0];
+        let faulted = self.builder.ins().ushr_imm(result, 32);
+        let fault = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.ins().brif(faulted, fault, &[], done, &[]);
+        self.builder.switch_to_block(fault);
+        self.builder.seal_block(fault);
+        self.fault(index, pc);
+        self.builder.switch_to_block(done);
+        self.builder.seal_block(done);
+        self.builder.ins().ireduce(types::I32, result)
+    }
+
+    fn store_memory(&mut self, base: usize, offset: u32, size: u32, rt: usize, index: usize, pc: u32) {
+        let base = self.get(base);
+        let offset = self.constant(offset);
+        let addr = self.builder.ins().iadd(base, offset);
+        let size = self.constant(size);
+        let value = self.get(rt);
+        let call = self.builder.ins().call(self.store, &[self.cpu, addr, size, value]);
+        let status = self.builder.inst_results(call)[0];
+        let failed = self.builder.create_block();
+        let fault = self.builder.create_block();
+        let code_changed = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.ins().brif(status, failed, &[], done, &[]);
+        self.builder.switch_to_block(failed);
+        self.builder.seal_block(failed);
+        let faulted = self.builder.ins().icmp_imm(IntCC::Equal, status, STORE_FAULT as i64);
+        self.builder.ins().brif(faulted, fault, &[], code_changed, &[]);
+        self.builder.switch_to_block(fault);
+        self.builder.seal_block(fault);
+        self.fault(index, pc);
+        // the rest of the block may have been overwritten
+        self.builder.switch_to_block(code_changed);
+        self.builder.seal_block(code_changed);
+        let next = self.next_pc(pc);
+        let none = self.constant(DELAY_NONE);
+        self.exit(next, index + 1, none, none);
+        self.builder.switch_to_block(done);
+        self.builder.seal_block(done);
+    }
+
+    fn binary(&mut self, rd: usize, rs: usize, rt: usize, op: fn(&mut FunctionBuilder, Value, Value) -> Value) {
+        let x = self.get(rs);
+        let y = self.get(rt);
+        let value = op(&mut self.builder, x, y);
+        self.set(rd, value);
+    }
+
+    fn immediate(&mut self, rt: usize, rs: usize, imm: u32, op: fn(&mut FunctionBuilder, Value, Value) -> Value) {
+        let x = self.get(rs);
+        let y = self.constant(imm);
+        let value = op(&mut self.builder, x, y);
+        self.set(rt, value);
+    }
+
+    fn branch(&mut self, condition: Value, target: Value) {
+        self.branch = Some((condition, target));
+    }
+
+    fn compare_zero(&mut self, rs: usize, cc: IntCC) -> Value {
+        let value = self.get(rs);
+        self.builder.ins().icmp_imm(cc, value, 0)
+    }
+
+    // Translate the instruction at index of the block, at pc
+    fn translate(&mut self, instruction: Instruction, index: usize, pc: u32) {
+        // like execute_decoded, r0 reads as zero
+        let zero = self.constant(0);
+        self.set(0, zero);
+        // relative branches and links count from the delay slot
+        let delay_slot = pc.wrapping_add(4);
+        let link = pc.wrapping_add(8);
+        match instruction {
+            Instruction::Sll { rd, rt, sa } => {
+                let value = self.get(rt);
+                let value = self.builder.ins().ishl_imm(value, sa as i64);
+                self.set(rd, value);
+            }
+            Instruction::Srl { rd, rt, sa } => {
+                let value = self.get(rt);
+                let value = self.builder.ins().ushr_imm(value, sa as i64);
+                self.set(rd, value);
+            }
+            Instruction::Sra { rd, rt, sa } => {
+                let value = self.get(rt);
+                let value = self.builder.ins().sshr_imm(value, sa as i64);
+                self.set(rd, value);
+            }
+            // shift amounts are taken modulo 32 like wrapping_shl
+            Instruction::Sllv { rd, rt, rs } => self.binary(rd, rt, rs, |b, x, y| b.ins().ishl(x, y)),
+            Instruction::Srlv { rd, rt, rs } => self.binary(rd, rt, rs, |b, x, y| b.ins().ushr(x, y)),
+            Instruction::Srav { rd, rt, rs } => self.binary(rd, rt, rs, |b, x, y| b.ins().sshr(x, y)),
+            Instruction::Jr { rs } => {
+                let taken = self.builder.ins().iconst(types::I8, 1);
+                let target = self.get(rs);
+                self.branch(taken, target);
+            }
+            Instruction::Jalr { rd, rs } => {
+                // rd is written before rs is read, as in execute_decoded
+                let link = self.constant(link);
+                self.set(rd, link);
+                let taken = self.builder.ins().iconst(types::I8, 1);
+                let target = self.get(rs);
+                self.branch(taken, target);
+            }
+            Instruction::Movz { rd, rs, rt } | Instruction::Movn { rd, rs, rt } => {
+                let cc = if let Instruction::Movz { .. } = instruction { IntCC::Equal } else { IntCC::NotEqual };
+                let condition = self.compare_zero(rt, cc);
+                let source = self.get(rs);
+                let old = self.get(rd);
+                let value = self.builder.ins().select(condition, source, old);
+                self.set(rd, value);
+            }
+            Instruction::Mfhi { rd } => {
+                let value = self.get(HI);
+                self.set(rd, value);
+            }
+            Instruction::Mthi { rs } => {
+                let value = self.get(rs);
+                self.set(HI, value);
+            }
+            Instruction::Mflo { rd } => {
+                let value = self.get(LO);
+                self.set(rd, value);
+            }
+            Instruction::Mtlo { rs } => {
+                let value = self.get(rs);
+                self.set(LO, value);
+            }
+            Instruction::Mult { rs, rt } | Instruction::Multu { rs, rt } => {
+                let x = self.get(rs);
+                let y = self.get(rt);
+                let (x, y) = if let Instruction::Mult { .. } = instruction {
+                    (self.builder.ins().sextend(types::I64, x), self.builder.ins().sextend(types::I64, y))
+                } else {
+                    (self.builder.ins().uextend(types::I64, x), self.builder.ins().uextend(types::I64, y))
+                };
+                let product = self.builder.ins().imul(x, y);
+                let high = self.builder.ins().ushr_imm(product, 32);
+                let high = self.builder.ins().ireduce(types::I32, high);
+                let low = self.builder.ins().ireduce(types::I32, product);
+                self.set(HI, high);
+                self.set(LO, low);
+            }
+            Instruction::Addu { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().iadd(x, y)),
+            Instruction::Subu { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().isub(x, y)),
+            Instruction::And { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().band(x, y)),
+            Instruction::Or { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().bor(x, y)),
+            Instruction::Xor { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().bxor(x, y)),
+            Instruction::Nor { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| {
+                let or = b.ins().bor(x, y);
+                b.ins().bnot(or)
+            }),
+            Instruction::Slt { rd, rs, rt } => {
+                let x = self.get(rs);
+                let y = self.get(rt);
+                let value = self.flag(IntCC::SignedLessThan, x, y);
+                self.set(rd, value);
+            }
+            Instruction::Sltu { rd, rs, rt } => {
+                let x = self.get(rs);
+                let y = self.get(rt);
+                let value = self.flag(IntCC::UnsignedLessThan, x, y);
+                self.set(rd, value);
+            }
+            Instruction::Mul { rd, rs, rt } => self.binary(rd, rs, rt, |b, x, y| b.ins().imul(x, y)),
+            Instruction::Bltz { rs, offset } | Instruction::Bltzal { rs, offset } => {
+                let taken = self.compare_zero(rs, IntCC::SignedLessThan);
+                if let Instruction::Bltzal { .. } = instruction {
+                    let link = self.constant(link);
+                    self.set(31, link);
+                }
+                let target = self.constant(delay_slot.wrapping_add(offset));
+                self.branch(taken, target);
+            }
+            Instruction::Bgez { rs, offset } | Instruction::Bgezal { rs, offset } => {
+                let taken = self.compare_zero(rs, IntCC::SignedGreaterThanOrEqual);
+                if let Instruction::Bgezal { .. } = instruction {
+                    let link = self.constant(link);
+                    self.set(31, link);
+                }
+                let target = self.constant(delay_slot.wrapping_add(offset));
+                self.branch(taken, target);
+            }
+            Instruction::J { target } | Instruction::Jal { target } => {
+                if let Instruction::Jal { .. } = instruction {
+                    let link = self.constant(link);
+                    self.set(31, link);
+                }
+                let taken = self.builder.ins().iconst(types::I8, 1);
+                let target = self.constant((delay_slot & 0xf0000000) | target);
+                self.branch(taken, target);
+            }
+            Instruction::Beq { rs, rt, offset } | Instruction::Bne { rs, rt, offset } => {
+                let cc = if let Instruction::Beq { .. } = instruction { IntCC::Equal } else { IntCC::NotEqual };
+                let x = self.get(rs);
+                let y = self.get(rt);
+                let taken = self.builder.ins().icmp(cc, x, y);
+                let target = self.constant(delay_slot.wrapping_add(offset));
+                self.branch(taken, target);
+            }
+            Instruction::Blez { rs, offset } => {
+                let taken = self.compare_zero(rs, IntCC::SignedLessThanOrEqual);
+                let target = self.constant(delay_slot.wrapping_add(offset));
+                self.branch(taken, target);
+            }
+            Instruction::Bgtz { rs, offset } => {
+                let taken = self.compare_zero(rs, IntCC::SignedGreaterThan);
+                let target = self.constant(delay_slot.wrapping_add(offset));
+                self.branch(taken, target);
+            }
+            Instruction::Addiu { rt, rs, imm } => self.immediate(rt, rs, imm, |b, x, y| b.ins().iadd(x, y)),
+            Instruction::Slti { rt, rs, imm } => {
+                let x = self.get(rs);
+                let y = self.constant(imm);
+                let value = self.flag(IntCC::SignedLessThan, x, y);
+                self.set(rt, value);
+            }
+            Instruction::Sltiu { rt, rs, imm } => {
+                let x = self.get(rs);
+                let y = self.constant(imm);
+                let value = self.flag(IntCC::UnsignedLessThan, x, y);
+                self.set(rt, value);
+            }
+            Instruction::Andi { rt, rs, imm } => self.immediate(rt, rs, imm, |b, x, y| b.ins().band(x, y)),
+            Instruction::Ori { rt, rs, imm } => self.immediate(rt, rs, imm, |b, x, y| b.ins().bor(x, y)),
+            Instruction::Xori { rt, rs, imm } => self.immediate(rt, rs, imm, |b, x, y| b.ins().bxor(x, y)),
+            Instruction::Lui { rt, imm } => {
+                let value = self.constant(imm << 16);
+                self.set(rt, value);
+            }
+            Instruction::Lb { rt, base, offset } => {
+                let value = self.load_memory(base, offset, 8, index, pc);
+                let byte = self.builder.ins().ireduce(types::I8, value);
+                let value = self.builder.ins().sextend(types::I32, byte);
+                self.set(rt, value);
+            }
+            Instruction::Lh { rt, base, offset } => {
+                let value = self.load_memory(base, offset, 16, index, pc);
+                let half = self.builder.ins().ireduce(types::I16, value);
+                let value = self.builder.ins().sextend(types::I32, half);
+                self.set(rt, value);
+            }
+            Instruction::Lw { rt, base, offset } => {
+                let value = self.load_memory(base, offset, 32, index, pc);
+                self.set(rt, value);
+            }
+            Instruction::Lbu { rt, base, offset } => {
+                let value = self.load_memory(base, offset, 8, index, pc);
+                self.set(rt, value);
+            }
+            Instruction::Lhu { rt, base, offset } => {
+                let value = self.load_memory(base, offset, 16, index, pc);
+                self.set(rt, value);
+            }
+            Instruction::Sb { rt, base, offset } => self.store_memory(base, offset, 8, rt, index, pc),
+            Instruction::Sh { rt, base, offset } => self.store_memory(base, offset, 16, rt, index, pc),
+            Instruction::Sw { rt, base, offset } => self.store_memory(base, offset, 32, rt, index, pc),
+            _ => unreachable!("{:?} is not supported by the translator", instruction),
+        }
+    }
+}
+
+pub struct Jit {
+    module: JITModule,
+    load: FuncId,
+    store: FuncId,
+    blocks: HashMap<u32, Entry>,
+    // compiled blocks whose code has been overwritten since the module was built
+    stale: usize,
+}
+
+impl Jit {
+    pub fn new() -> io::Result<Self> {
+        let (module, load, store) = Self::module()?;
+        Ok(Self {
+            module,
+            load,
+            store,
+            blocks: HashMap::new(),
+            stale: 0,
+        })
+    }
+
+    fn module() -> io::Result<(JITModule, FuncId, FuncId)> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(error)?;
+        let isa = cranelift_native::builder()
+            .map_err(error)?
+            .finish(settings::Flags::new(flags))
+            .map_err(error)?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("jit_load", jit_load as *const u8);
+        builder.symbol("jit_store", jit_store as *const u8);
+        let mut module = JITModule::new(builder);
+        let pointer = module.target_config().pointer_type();
+
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I64));
+        let load = module.declare_function("jit_load", Linkage::Import, &signature).map_err(error)?;
+
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I32));
+        let store = module.declare_function("jit_store", Linkage::Import, &signature).map_err(error)?;
+
+        Ok((module, load, store))
+    }
+
+    // Drop every compiled block and free their code
+    fn rebuild(&mut self) -> io::Result<()> {
+        let (module, load, store) = Self::module()?;
+        let old = std::mem::replace(&mut self.module, module);
+        // compiled code only runs inside execute, none of it is running now
+        unsafe { old.free_memory() };
+        self.load = load;
+        self.store = store;
+        self.blocks.clear();
+        self.stale = 0;
+        Ok(())
+    }
+
+    // Compile the supported instructions at the start of a block at pc, None
+    // when the first one is not supported
+    fn compile(&mut self, block: &[Decoded], pc: u32) -> io::Result<Option<CompiledBlock>> {
+        let len = compiled_len(block);
+        if len == 0 {
+            return Ok(None);
+        }
+        let pointer = self.module.target_config().pointer_type();
+        let mut context = self.module.make_context();
+        for _ in 0..5 {
+            context.func.signature.params.push(AbiParam::new(pointer));
+        }
+        context.func.signature.returns.push(AbiParam::new(types::I32));
+
+        let mut function_context = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+        let load = self.module.declare_func_in_func(self.load, builder.func);
+        let store = self.module.declare_func_in_func(self.store, builder.func);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let params = builder.block_params(entry).to_vec();
+        for reg in 0..=LO {
+            builder.declare_var(Variable::new(reg), types::I32);
+        }
+        let mut translator = Translator {
+            builder,
+            cpu: params[0],
+            regs: params[1],
+            hi: params[2],
+            lo: params[3],
+            exit: params[4],
+            load,
+            store,
+            loaded: [false; 34],
+            dirty: [false; 34],
+            branch: None,
+        };
+        for (index, decoded) in block[..len].iter().enumerate() {
+            if let Ok(instruction) = decoded {
+                translator.translate(*instruction, index, pc.wrapping_add(4 * index as u32));
+            }
+        }
+        let end = pc.wrapping_add(4 * (len as u32 - 1));
+        let next = translator.next_pc(end);
+        let none = translator.constant(DELAY_NONE);
+        translator.exit(next, len, none, none);
+        translator.builder.finalize();
+
+        let id = self.module.declare_anonymous_function(&context.func.signature).map_err(error)?;
+        self.module.define_function(id, &mut context).map_err(error)?;
+        self.module.clear_context(&mut context);
+        self.module.finalize_definitions().map_err(error)?;
+        let code = self.module.get_finalized_function(id);
+        Ok(Some(unsafe { std::mem::transmute::<*const u8, CompiledBlock>(code) }))
+    }
+
+    // Run the compiled block at pc, compiling it once it is hot. Returns
+    // false when the interpreter has to execute the next instructions.
+    pub fn execute(&mut self, cpu: &mut Cpu) -> io::Result<bool> {
+        // compiled blocks neither trace nor start in a delay slot
+        if cpu.trace || cpu.in_delay_slot() || cpu.pc & 3 != 0 {
+            return Ok(false);
+        }
+        // the interpreter takes exceptions on the fetch
+        let physical_addr = match cpu.mmu(cpu.pc, false) {
+            Ok(physical_addr) => physical_addr,
+            Err(_) => return Ok(false),
+        };
+        let version = cpu.bus.code_version(physical_addr);
+        let entry = self.blocks.entry(cpu.pc).or_insert(Entry {
+            physical_addr,
+            version,
+            state: State::Counting(0),
+        });
+        if entry.physical_addr != physical_addr || entry.version != version {
+            if let State::Compiled(_) = entry.state {
+                self.stale += 1;
+            }
+            *entry = Entry {
+                physical_addr,
+                version,
+                state: State::Counting(0),
+            };
+        }
+        let compiled = match entry.state {
+            State::Compiled(compiled) => compiled,
+            State::Interpreted => return Ok(false),
+            State::Counting(count) if count + 1 < HOT_THRESHOLD => {
+                entry.state = State::Counting(count + 1);
+                return Ok(false);
+            }
+            State::Counting(_) => {
+                if self.stale >= MAX_STALE_BLOCKS {
+                    self.rebuild()?;
+                }
+                let block = match cpu.bus.fetch_block(physical_addr) {
+                    Ok(block) => block,
+                    Err(_) => return Ok(false),
+                };
+                let state = match self.compile(&block.decoded, cpu.pc)? {
+                    Some(compiled) => State::Compiled(compiled),
+                    None => State::Interpreted,
+                };
+                self.blocks.insert(
+                    cpu.pc,
+                    Entry {
+                        physical_addr,
+                        version,
+                        state,
+                    },
+                );
+                match state {
+                    State::Compiled(compiled) => compiled,
+                    _ => return Ok(false),
+                }
+            }
+        };
+
+        let mut exit = Exit::default();
+        let cpu_ptr: *mut Cpu = cpu;
+        let executed = unsafe {
+            compiled(
+                cpu_ptr,
+                ptr::addr_of_mut!((*cpu_ptr).regs) as *mut u32,
+                ptr::addr_of_mut!((*cpu_ptr).hi),
+                ptr::addr_of_mut!((*cpu_ptr).lo),
+                &mut exit,
+            )
+        };
+        cpu.pc = exit.pc;
+        cpu.cp0.count = cpu.cp0.count.wrapping_add(executed);
+        match exit.delay {
+            DELAY_TAKEN => cpu.enter_delay_slot(Some(exit.target)),
+            DELAY_NOT_TAKEN => cpu.enter_delay_slot(None),
+            _ => {}
+        }
+        // a fault in the first instruction is left to the interpreter
+        Ok(executed != 0)
+    }
+}
+
+// The fetch/execute loop of main with --jit: hot blocks run compiled, the
+// others and the instructions the translator does not support run in the
+// interpreter
+pub fn run(cpu: &mut Cpu) -> io::Result<Result<(), CpuError>> {
+    let mut jit = Jit::new()?;
+    while cpu.pc != 0 {
+        if !jit.execute(cpu)? {
+            let stepped = cpu.fetch_block().and_then(|block| cpu.execute_block(&block));
+            if stepped.is_err() {
+                return Ok(stepped);
+            }
+        }
+    }
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: u32 = 0x8000_2000;
+    // registers the generated programs do not clobber: the data pointer, the
+    // loop counter and k0/k1 of the exception handler
+    const BASE: u32 = 22;
+    const COUNTER: u32 = 23;
+    const POOL: [u32; 26] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 24, 25, 28, 31,
+    ];
+
+    fn r_type(rs: u32, rt: u32, rd: u32, sa: u32, funct: u32) -> u32 {
+        rs << 21 | rt << 16 | rd << 11 | sa << 6 | funct
+    }
+
+    fn i_type(opcode: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+        opcode << 26 | rs << 21 | rt << 16 | (imm & 0xffff)
+    }
+
+    // xorshift, the programs only need to differ from each other
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 16) as u32
+        }
+
+        fn below(&mut self, n: u32) -> u32 {
+            self.next() % n
+        }
+
+        fn reg(&mut self) -> u32 {
+            POOL[self.below(POOL.len() as u32) as usize]
+        }
+    }
+
+    // A non-branch instruction, mostly ones the translator supports
+    fn instruction(random: &mut Random) -> u32 {
+        let (rs, rt, rd) = (random.reg(), random.reg(), random.reg());
+        let offset = random.below(64).wrapping_sub(32);
+        match random.below(8) {
+            0 | 1 => {
+                let functs = [0x21, 0x23, 0x24, 0x25, 0x26, 0x27, 0x2a, 0x2b, 0x04, 0x06, 0x07, 0x0a, 0x0b];
+                r_type(rs, rt, rd, 0, functs[random.below(functs.len() as u32) as usize])
+            }
+            2 => r_type(0, rt, rd, random.below(32), [0x00, 0x02, 0x03][random.below(3) as usize]),
+            3 => i_type(0x09 + random.below(7), rs, rt, random.next()),
+            4 => match random.below(7) {
+                0 => r_type(rs, rt, 0, 0, 0x18),
+                1 => r_type(rs, rt, 0, 0, 0x19),
+                2 => r_type(0, 0, rd, 0, 0x10),
+                3 => r_type(0, 0, rd, 0, 0x12),
+                4 => r_type(rs, 0, 0, 0, 0x11),
+                5 => r_type(rs, 0, 0, 0, 0x13),
+                // mul
+                _ => 0x1c << 26 | r_type(rs, rt, rd, 0, 0x02),
+            },
+            // loads and stores around DATA, some of them unaligned
+            5 | 6 => {
+                let opcodes = [0x20, 0x21, 0x23, 0x24, 0x25, 0x28, 0x29, 0x2b];
+                i_type(opcodes[random.below(opcodes.len() as u32) as usize], BASE, rt, offset)
+            }
+            // left to the interpreter: add, div, clz and syscall
+            _ => match random.below(4) {
+                0 => r_type(rs, rt, rd, 0, 0x20),
+                1 => r_type(rs, rt, 0, 0, 0x1a),
+                2 => 0x1c << 26 | r_type(rs, rd, rd, 0, 0x20),
+                _ => 0x0000_000c,
+            },
+        }
+    }
+
+    // A branch or jump forward by skip instructions from its delay slot
+    fn branch(random: &mut Random, addr: u32, skip: u32) -> u32 {
+        let (rs, rt) = (random.reg(), random.reg());
+        match random.below(6) {
+            0 => i_type(0x04 + random.below(4), rs, rt, skip),
+            1 => i_type(0x01, rs, [0x00, 0x01, 0x10, 0x11][random.below(4) as usize], skip),
+            2 => (0x02 + random.below(2)) << 26 | ((addr + 4 + 4 * skip) >> 2 & 0x03ff_ffff),
+            _ => i_type(0x04 + random.below(2), rs, rt, skip),
+        }
+    }
+
+    // Loop COUNTER times over a random body with forward branches, then jump
+    // to 0. Exceptions skip the faulting instruction, or the branch and its
+    // delay slot.
+    fn program(seed: u64) -> Vec<u8> {
+        let mut random = Random(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let mut words = vec![
+            // Status: BEV, ERL and EXL clear
+            i_type(0x0f, 0, 26, 0x0040),
+            0x4080_0000 | 26 << 16 | 12 << 11,
+            i_type(0x0f, 0, BASE, DATA >> 16),
+            i_type(0x0d, BASE, BASE, DATA),
+            i_type(0x09, 0, COUNTER, 40),
+        ];
+        for reg in POOL.iter().skip(1) {
+            words.push(i_type(0x0f, 0, *reg, random.next()));
+            words.push(i_type(0x0d, *reg, *reg, random.next()));
+        }
+        let body = words.len();
+        let len = 48;
+        while words.len() < body + len {
+            let left = (body + len - words.len()) as u32;
+            if left >= 3 && random.below(5) == 0 {
+                let addr = BOOT_EXCEPTION_VECTOR + 4 * words.len() as u32;
+                let skip = random.below(left - 2);
+                words.push(branch(&mut random, addr, skip));
+            }
+            words.push(instruction(&mut random));
+        }
+        let back = (body as i32 - words.len() as i32 - 2) as u32;
+        words.push(i_type(0x09, COUNTER, COUNTER, 0xffff));
+        words.push(i_type(0x05, COUNTER, 0, back));
+        words.push(0);
+        words.push(r_type(0, 0, 0, 0, 0x08));
+        words.push(0);
+        assert!(words.len() < 0x380 / 4);
+        words.resize(0x380 / 4, 0);
+        words.extend_from_slice(&[
+            // mfc0 k0, Cause; mfc0 k1, EPC
+            0x4000_0000 | 26 << 16 | 13 << 11,
+            0x4000_0000 | 27 << 16 | 14 << 11,
+            i_type(0x09, 27, 27, 4),
+            // bgez k0: Cause.BD clear
+            i_type(0x01, 26, 0x01, 2),
+            0,
+            i_type(0x09, 27, 27, 4),
+            // mtc0 k1, EPC; eret
+            0x4080_0000 | 27 << 16 | 14 << 11,
+            0x4200_0018,
+        ]);
+        words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+    }
+
+    fn interpret(cpu: &mut Cpu) -> Result<(), CpuError> {
+        while cpu.pc != 0 {
+            let inst = cpu.fetch()?;
+            cpu.pc += 4;
+            cpu.execute(inst)?;
+        }
+        Ok(())
+    }
+
+    fn assert_same_state(interpreted: &mut Cpu, compiled: &mut Cpu, name: &str) {
+        assert_eq!(interpreted.regs, compiled.regs, "{}: registers", name);
+        assert_eq!(
+            (interpreted.pc, interpreted.hi, interpreted.lo),
+            (compiled.pc, compiled.hi, compiled.lo),
+            "{}: pc, hi and lo",
+            name
+        );
+        assert_eq!(
+            (interpreted.cp0.count, interpreted.cp0.epc, interpreted.cp0.cause),
+            (compiled.cp0.count, compiled.cp0.epc, compiled.cp0.cause),
+            "{}: Count, EPC and Cause",
+            name
+        );
+        for addr in (DATA - 64..DATA + 64).step_by(4) {
+            assert_eq!(interpreted.load(addr, 32), compiled.load(addr, 32), "{}: memory at {:#x}", name, addr);
+        }
+    }
+
+    #[test]
+    fn random_programs_match_the_interpreter() {
+        for seed in 0..200 {
+            let binary = program(seed);
+            let mut interpreted = Cpu::new(binary.clone(), IsaRevision::Release2);
+            interpreted.trace = false;
+            let interpreted_stop = interpret(&mut interpreted);
+            let mut compiled = Cpu::new(binary, IsaRevision::Release2);
+            compiled.trace = false;
+            let compiled_stop = run(&mut compiled).unwrap();
+            assert_eq!(interpreted_stop, compiled_stop, "seed {}: stopped", seed);
+            assert_same_state(&mut interpreted, &mut compiled, &format!("seed {}", seed));
+        }
+    }
+
+    #[test]
+    fn stores_to_compiled_code_are_seen() {
+        // in DRAM at 0x80001000: the loop adds 1 to v0 20 times, then patches
+        // its addiu to add 2 for the other 20 iterations
+        let code = [
+            i_type(0x0f, 0, 16, 0x8000),
+            i_type(0x0d, 16, 16, 0x1000),
+            i_type(0x09, 0, 17, 40),
+            i_type(0x0f, 0, 9, 0x2442),
+            i_type(0x0d, 9, 9, 2),
+            i_type(0x09, 2, 2, 1),
+            i_type(0x09, 17, 17, 0xffff),
+            i_type(0x09, 0, 8, 20),
+            i_type(0x05, 17, 8, 2),
+            0,
+            i_type(0x2b, 16, 9, 20),
+            i_type(0x05, 17, 0, 0xfff9),
+            0,
+            r_type(0, 0, 0, 0, 0x08),
+            0,
+        ];
+        let image: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+        let mut cpus = Vec::new();
+        for _ in 0..2 {
+            let mut cpu = Cpu::new(vec![], IsaRevision::Release2);
+            cpu.trace = false;
+            cpu.bus.load_image(0x1000, &image).unwrap();
+            cpu.pc = 0x8000_1000;
+            cpus.push(cpu);
+        }
+        assert_eq!(interpret(&mut cpus[0]), Ok(()));
+        assert_eq!(run(&mut cpus[1]).unwrap(), Ok(()));
+        let (interpreted, compiled) = cpus.split_at_mut(1);
+        assert_same_state(&mut interpreted[0], &mut compiled[0], "self-modifying loop");
+        assert_eq!(compiled[0].regs[2], 60);
+    }
+}
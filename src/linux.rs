@@ -0,0 +1,1079 @@
+use std::env;
+use std::fs;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::bus::*;
+use crate::cp0::*;
+use crate::cpu::*;
+use crate::decode::*;
+use crate::elf::*;
+use crate::exception::*;
+use crate::memory::*;
+use crate::syscall::*;
+
+// User addresses are identity mapped onto DRAM. The program is loaded at its
+// link address, the heap follows it, mmap allocates upwards from MMAP_BASE and
+// the stack grows down from the end of DRAM.
+const PAGE_SIZE: u32 = 4096;
+const MMAP_BASE: u32 = 0x0400_0000;
+const STACK_TOP: u32 = PHY_MEMORY_BASE + MEMORY_SIZE;
+const STACK_SIZE: u32 = 8 * 1024 * 1024;
+
+// o32 syscall numbers
+const SYS_EXIT: u32 = 4001;
+const SYS_READ: u32 = 4003;
+const SYS_WRITE: u32 = 4004;
+const SYS_OPEN: u32 = 4005;
+const SYS_CLOSE: u32 = 4006;
+const SYS_TIME: u32 = 4013;
+const SYS_LSEEK: u32 = 4019;
+const SYS_GETPID: u32 = 4020;
+const SYS_GETUID: u32 = 4024;
+const SYS_BRK: u32 = 4045;
+const SYS_GETGID: u32 = 4047;
+const SYS_GETEUID: u32 = 4049;
+const SYS_GETEGID: u32 = 4050;
+const SYS_IOCTL: u32 = 4054;
+const SYS_GETTIMEOFDAY: u32 = 4078;
+const SYS_READLINK: u32 = 4085;
+const SYS_MMAP: u32 = 4090;
+const SYS_MUNMAP: u32 = 4091;
+const SYS_FSTAT: u32 = 4108;
+const SYS_UNAME: u32 = 4122;
+const SYS_MPROTECT: u32 = 4125;
+const SYS_LLSEEK: u32 = 4140;
+const SYS_READV: u32 = 4145;
+const SYS_WRITEV: u32 = 4146;
+const SYS_RT_SIGACTION: u32 = 4194;
+const SYS_RT_SIGPROCMASK: u32 = 4195;
+const SYS_GETCWD: u32 = 4203;
+const SYS_SIGALTSTACK: u32 = 4206;
+const SYS_MMAP2: u32 = 4210;
+const SYS_STAT64: u32 = 4213;
+const SYS_LSTAT64: u32 = 4214;
+const SYS_FSTAT64: u32 = 4215;
+const SYS_MADVISE: u32 = 4218;
+const SYS_GETTID: u32 = 4222;
+const SYS_EXIT_GROUP: u32 = 4246;
+const SYS_SET_TID_ADDRESS: u32 = 4252;
+const SYS_CLOCK_GETTIME: u32 = 4263;
+const SYS_SET_THREAD_AREA: u32 = 4283;
+const SYS_OPENAT: u32 = 4288;
+const SYS_FSTATAT64: u32 = 4293;
+const SYS_GETRANDOM: u32 = 4353;
+const SYS_CLOCK_GETTIME64: u32 = 4403;
+
+// errno values of the MIPS ABI
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const ENOMEM: u32 = 12;
+const EFAULT: u32 = 14;
+const EINVAL: u32 = 22;
+const ENOTTY: u32 = 25;
+const ESPIPE: u32 = 29;
+const ERANGE: u32 = 34;
+const ENOSYS: u32 = 89;
+
+// open flags of the MIPS ABI
+const O_ACCMODE: u32 = 0x0003;
+const O_RDONLY: u32 = 0x0000;
+const O_WRONLY: u32 = 0x0001;
+const O_APPEND: u32 = 0x0008;
+const O_CREAT: u32 = 0x0100;
+const O_TRUNC: u32 = 0x0200;
+const O_EXCL: u32 = 0x0400;
+
+const AT_FDCWD: u32 = -100i32 as u32;
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_EMPTY_PATH: u32 = 0x1000;
+
+// mmap flags of the MIPS ABI
+const MAP_FIXED: u32 = 0x010;
+const MAP_ANONYMOUS: u32 = 0x800;
+
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_REALTIME_COARSE: u32 = 5;
+
+const S_IFCHR: u32 = 0o020000;
+
+// auxiliary vector entries
+const AT_NULL: u32 = 0;
+const AT_PHDR: u32 = 3;
+const AT_PHENT: u32 = 4;
+const AT_PHNUM: u32 = 5;
+const AT_PAGESZ: u32 = 6;
+const AT_BASE: u32 = 7;
+const AT_FLAGS: u32 = 8;
+const AT_ENTRY: u32 = 9;
+const AT_UID: u32 = 11;
+const AT_EUID: u32 = 12;
+const AT_GID: u32 = 13;
+const AT_EGID: u32 = 14;
+const AT_HWCAP: u32 = 16;
+const AT_CLKTCK: u32 = 17;
+const AT_SECURE: u32 = 23;
+const AT_RANDOM: u32 = 25;
+const AT_EXECFN: u32 = 31;
+
+// signal numbers of the MIPS ABI
+const SIGILL: i32 = 4;
+const SIGTRAP: i32 = 5;
+const SIGFPE: i32 = 8;
+const SIGBUS: i32 = 10;
+const SIGSEGV: i32 = 11;
+const SIGSYS: i32 = 12;
+
+// Ok(v0) or Err(errno)
+type SyscallResult = Result<u32, u32>;
+
+enum FileDescriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+impl FileDescriptor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FileDescriptor::Stdin => io::stdin().read(buf),
+            FileDescriptor::File(file) => file.read(buf),
+            _ => Err(io::Error::from_raw_os_error(EBADF as i32)),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileDescriptor::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(buf)?;
+                stdout.flush()?;
+                Ok(buf.len())
+            }
+            FileDescriptor::Stderr => {
+                io::stderr().write_all(buf)?;
+                Ok(buf.len())
+            }
+            FileDescriptor::File(file) => file.write(buf),
+            FileDescriptor::Stdin => Err(io::Error::from_raw_os_error(EBADF as i32)),
+        }
+    }
+}
+
+// errno values 1..34 are shared between the host and the MIPS ABI
+fn errno(error: io::Error) -> u32 {
+    match error.raw_os_error() {
+        Some(code) if (1..=34).contains(&code) => code as u32,
+        _ => match error.kind() {
+            io::ErrorKind::NotFound => ENOENT,
+            io::ErrorKind::InvalidInput => EINVAL,
+            _ => EIO,
+        },
+    }
+}
+
+fn page_align(addr: u32) -> u32 {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+fn check_user_range(addr: u32, len: u32) -> Result<(), u32> {
+    match addr.checked_add(len) {
+        Some(end) if end <= STACK_TOP => Ok(()),
+        _ => Err(EFAULT),
+    }
+}
+
+fn read_bytes(bus: &Bus, addr: u32, len: u32) -> Result<Vec<u8>, u32> {
+    check_user_range(addr, len)?;
+    let mut bytes = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        let value = bus.load(addr + i, 8).map_err(|_| EFAULT)?;
+        bytes.push(value as u8);
+    }
+    Ok(bytes)
+}
+
+fn write_bytes(bus: &mut Bus, addr: u32, bytes: &[u8]) -> Result<(), u32> {
+    check_user_range(addr, bytes.len() as u32)?;
+    for (i, byte) in bytes.iter().enumerate() {
+        bus.store(addr + i as u32, 8, *byte as u32).map_err(|_| EFAULT)?;
+    }
+    Ok(())
+}
+
+fn read_word(bus: &Bus, addr: u32) -> Result<u32, u32> {
+    check_user_range(addr, 4)?;
+    bus.load(addr, 32).map_err(|_| EFAULT)
+}
+
+fn write_words(bus: &mut Bus, addr: u32, words: &[u32]) -> Result<(), u32> {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    write_bytes(bus, addr, &bytes)
+}
+
+fn read_string(bus: &Bus, addr: u32) -> Result<String, u32> {
+    let mut bytes = Vec::new();
+    loop {
+        check_user_range(addr, bytes.len() as u32 + 1)?;
+        let value = bus.load(addr + bytes.len() as u32, 8).map_err(|_| EFAULT)?;
+        if value == 0 {
+            break;
+        }
+        bytes.push(value as u8);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn open_options(flags: u32, mode: u32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_RDONLY => options.read(true),
+        O_WRONLY => options.write(true),
+        _ => options.read(true).write(true),
+    };
+    if flags & O_APPEND != 0 {
+        options.append(true);
+    }
+    if flags & O_CREAT != 0 {
+        if flags & O_EXCL != 0 {
+            options.create_new(true);
+        } else {
+            options.create(true);
+        }
+    }
+    if flags & O_TRUNC != 0 {
+        options.truncate(true);
+    }
+    options.mode(mode);
+    options
+}
+
+struct Stat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    size: u64,
+    atime: (i64, i64),
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+    blksize: u64,
+    blocks: u64,
+}
+
+impl Stat {
+    fn from_metadata(metadata: &Metadata) -> Stat {
+        Stat {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mode: metadata.mode(),
+            nlink: metadata.nlink() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            rdev: metadata.rdev(),
+            size: metadata.size(),
+            atime: (metadata.atime(), metadata.atime_nsec()),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+            blksize: metadata.blksize(),
+            blocks: metadata.blocks(),
+        }
+    }
+
+    // standard streams are reported as terminals
+    fn character_device() -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: S_IFCHR | 0o620,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            size: 0,
+            atime: (0, 0),
+            mtime: (0, 0),
+            ctime: (0, 0),
+            blksize: 1024,
+            blocks: 0,
+        }
+    }
+
+    // struct stat of the o32 ABI
+    fn encode(&self) -> Vec<u8> {
+        let words = [
+            self.dev as u32,
+            0,
+            0,
+            0,
+            self.ino as u32,
+            self.mode,
+            self.nlink,
+            self.uid,
+            self.gid,
+            self.rdev as u32,
+            0,
+            0,
+            self.size as u32,
+            0,
+            self.atime.0 as u32,
+            self.atime.1 as u32,
+            self.mtime.0 as u32,
+            self.mtime.1 as u32,
+            self.ctime.0 as u32,
+            self.ctime.1 as u32,
+            self.blksize as u32,
+            self.blocks as u32,
+        ];
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.resize(144, 0);
+        bytes
+    }
+
+    // struct stat64 of the o32 ABI
+    fn encode64(&self) -> Vec<u8> {
+        let words = [
+            self.dev as u32,
+            0,
+            0,
+            0,
+            self.ino as u32,
+            (self.ino >> 32) as u32,
+            self.mode,
+            self.nlink,
+            self.uid,
+            self.gid,
+            self.rdev as u32,
+            0,
+            0,
+            0,
+            self.size as u32,
+            (self.size >> 32) as u32,
+            self.atime.0 as u32,
+            self.atime.1 as u32,
+            self.mtime.0 as u32,
+            self.mtime.1 as u32,
+            self.ctime.0 as u32,
+            self.ctime.1 as u32,
+            self.blksize as u32,
+            0,
+            self.blocks as u32,
+            (self.blocks >> 32) as u32,
+        ];
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+}
+
+// Linux o32 personality: runs a statically linked user program and serves
+// its syscalls on the host
+pub struct Linux {
+    executable: String,
+    files: Vec<Option<FileDescriptor>>,
+    brk_start: u32,
+    brk: u32,
+    mmap_top: u32,
+    uid: u32,
+    gid: u32,
+    start: Instant,
+    random: u32,
+}
+
+impl Linux {
+    // Load a statically linked executable and set up the initial process
+    // stack: argc, argv, envp and the auxiliary vector
+    pub fn load(cpu: &mut Cpu, elf: &Elf, args: &[String], envs: &[String]) -> io::Result<Linux> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let mut brk = 0u32;
+        for header in elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD) {
+            match header.vaddr.checked_add(header.memsz) {
+                Some(end) if end <= MMAP_BASE => brk = brk.max(end),
+                _ => return Err(invalid("segment outside of user memory")),
+            }
+        }
+        let brk = page_align(brk);
+        elf.load(cpu)?;
+
+        // program headers as mapped into memory
+        let phdr = elf
+            .program_headers
+            .iter()
+            .find(|header| header.p_type == PT_PHDR)
+            .map(|header| header.vaddr)
+            .or_else(|| {
+                elf.program_headers
+                    .iter()
+                    .find(|header| {
+                        header.p_type == PT_LOAD
+                            && header.offset <= elf.phoff
+                            && elf.phoff < header.offset + header.filesz
+                    })
+                    .map(|header| header.vaddr + (elf.phoff - header.offset))
+            })
+            .unwrap_or(0);
+
+        let executable = args.first().cloned().unwrap_or_default();
+        let (uid, gid) = match fs::metadata("/proc/self") {
+            Ok(metadata) => (metadata.uid(), metadata.gid()),
+            Err(_) => (0, 0),
+        };
+        let mut linux = Linux {
+            executable,
+            files: vec![
+                Some(FileDescriptor::Stdin),
+                Some(FileDescriptor::Stdout),
+                Some(FileDescriptor::Stderr),
+            ],
+            brk_start: brk,
+            brk,
+            mmap_top: MMAP_BASE,
+            uid,
+            gid,
+            start: Instant::now(),
+            random: 0,
+        };
+        linux.random = linux.seed();
+
+        // strings and the AT_RANDOM bytes at the top of the stack
+        let mut sp = STACK_TOP;
+        let mut push = |bus: &mut Bus, bytes: &[u8]| -> io::Result<u32> {
+            sp -= bytes.len() as u32;
+            write_bytes(bus, sp, bytes).map_err(|_| invalid("stack overflow"))?;
+            Ok(sp)
+        };
+        let mut push_string = |bus: &mut Bus, string: &str| {
+            let mut bytes = string.as_bytes().to_vec();
+            bytes.push(0);
+            push(bus, &bytes)
+        };
+        let execfn = push_string(&mut cpu.bus, &linux.executable)?;
+        let mut argv = Vec::new();
+        for arg in args {
+            argv.push(push_string(&mut cpu.bus, arg)?);
+        }
+        let mut envp = Vec::new();
+        for env in envs {
+            envp.push(push_string(&mut cpu.bus, env)?);
+        }
+        let random_bytes: Vec<u8> = (0..16).map(|_| linux.next_random() as u8).collect();
+        let random = push(&mut cpu.bus, &random_bytes)?;
+
+        let mut table = vec![argv.len() as u32];
+        table.extend(&argv);
+        table.push(0);
+        table.extend(&envp);
+        table.push(0);
+        let auxv = [
+            (AT_PHDR, phdr),
+            (AT_PHENT, 32),
+            (AT_PHNUM, elf.program_headers.len() as u32),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, 0),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, elf.entry),
+            (AT_UID, uid),
+            (AT_EUID, uid),
+            (AT_GID, gid),
+            (AT_EGID, gid),
+            (AT_HWCAP, 0),
+            (AT_CLKTCK, 100),
+            (AT_SECURE, 0),
+            (AT_RANDOM, random),
+            (AT_EXECFN, execfn),
+            (AT_NULL, 0),
+        ];
+        for (key, value) in auxv.iter() {
+            table.push(*key);
+            table.push(*value);
+        }
+        let sp = (random - 4 * table.len() as u32) & !0xf;
+        write_words(&mut cpu.bus, sp, &table).map_err(|_| invalid("stack overflow"))?;
+
+        cpu.regs[29] = sp;
+        // user mode, floating point unit usable
+        cpu.cp0.status = STATUS_CU1 | STATUS_UM;
+        // the process sees its addresses as physical ones, no TLB
+        cpu.cp0.set_mmu_type(CONFIG_MT_NONE);
+
+        Ok(linux)
+    }
+
+    fn seed(&self) -> u32 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+        nanos | 1
+    }
+
+    // xorshift32
+    fn next_random(&mut self) -> u32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random
+    }
+
+    fn file(&mut self, fd: u32) -> Result<&mut FileDescriptor, u32> {
+        match self.files.get_mut(fd as usize) {
+            Some(Some(file)) => Ok(file),
+            _ => Err(EBADF),
+        }
+    }
+
+    // the lowest free descriptor, as on Linux
+    fn allocate_fd(&mut self, file: FileDescriptor) -> u32 {
+        match self.files.iter().position(|file| file.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd as u32
+            }
+            None => {
+                self.files.push(Some(file));
+                (self.files.len() - 1) as u32
+            }
+        }
+    }
+
+    fn read(&mut self, bus: &mut Bus, fd: u32, buf: u32, count: u32) -> SyscallResult {
+        check_user_range(buf, count)?;
+        let mut bytes = vec![0u8; count as usize];
+        let len = self.file(fd)?.read(&mut bytes).map_err(errno)?;
+        write_bytes(bus, buf, &bytes[..len])?;
+        Ok(len as u32)
+    }
+
+    fn write(&mut self, bus: &mut Bus, fd: u32, buf: u32, count: u32) -> SyscallResult {
+        let bytes = read_bytes(bus, buf, count)?;
+        let len = self.file(fd)?.write(&bytes).map_err(errno)?;
+        Ok(len as u32)
+    }
+
+    fn readv(&mut self, bus: &mut Bus, fd: u32, iov: u32, iovcnt: u32) -> SyscallResult {
+        let mut total = 0u32;
+        for i in 0..iovcnt {
+            // struct iovec, a pointer past the end of memory faults in read_word
+            let entry = iov.wrapping_add(i.wrapping_mul(8));
+            let base = read_word(bus, entry)?;
+            let len = read_word(bus, entry.wrapping_add(4))?;
+            let count = self.read(bus, fd, base, len)?;
+            total = total.wrapping_add(count);
+            if count < len {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    fn writev(&mut self, bus: &mut Bus, fd: u32, iov: u32, iovcnt: u32) -> SyscallResult {
+        let mut total = 0u32;
+        for i in 0..iovcnt {
+            let entry = iov.wrapping_add(i.wrapping_mul(8));
+            let base = read_word(bus, entry)?;
+            let len = read_word(bus, entry.wrapping_add(4))?;
+            total = total.wrapping_add(self.write(bus, fd, base, len)?);
+        }
+        Ok(total)
+    }
+
+    fn open(&mut self, bus: &mut Bus, dirfd: u32, path: u32, flags: u32, mode: u32) -> SyscallResult {
+        let path = read_string(bus, path)?;
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            // paths relative to a directory descriptor are not supported
+            return Err(ENOSYS);
+        }
+        let file = open_options(flags, mode).open(path).map_err(errno)?;
+        Ok(self.allocate_fd(FileDescriptor::File(file)))
+    }
+
+    fn close(&mut self, fd: u32) -> SyscallResult {
+        self.file(fd)?;
+        self.files[fd as usize] = None;
+        Ok(0)
+    }
+
+    fn seek(&mut self, fd: u32, offset: i64, whence: u32) -> Result<u64, u32> {
+        let position = match whence {
+            0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        match self.file(fd)? {
+            FileDescriptor::File(file) => file.seek(position).map_err(errno),
+            _ => Err(ESPIPE),
+        }
+    }
+
+    fn fstat(&mut self, fd: u32) -> Result<Stat, u32> {
+        match self.file(fd)? {
+            FileDescriptor::File(file) => Ok(Stat::from_metadata(&file.metadata().map_err(errno)?)),
+            _ => Ok(Stat::character_device()),
+        }
+    }
+
+    fn stat(&mut self, bus: &mut Bus, dirfd: u32, path: u32, flags: u32) -> Result<Stat, u32> {
+        let path = read_string(bus, path)?;
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            return self.fstat(dirfd);
+        }
+        if dirfd != AT_FDCWD && !path.starts_with('/') {
+            // paths relative to a directory descriptor are not supported
+            return Err(ENOSYS);
+        }
+        let metadata = if flags & AT_SYMLINK_NOFOLLOW != 0 {
+            fs::symlink_metadata(path)
+        } else {
+            fs::metadata(path)
+        };
+        Ok(Stat::from_metadata(&metadata.map_err(errno)?))
+    }
+
+    fn brk(&mut self, bus: &mut Bus, addr: u32) -> SyscallResult {
+        if (self.brk_start..=MMAP_BASE).contains(&addr) {
+            if addr > self.brk {
+                // memory released by shrinking the heap reads as zero again
+                write_bytes(bus, self.brk, &vec![0u8; (addr - self.brk) as usize])?;
+            }
+            self.brk = addr;
+        }
+        Ok(self.brk)
+    }
+
+    fn mmap(&mut self, bus: &mut Bus, args: [u32; 5], offset: u64) -> SyscallResult {
+        let [addr, length, _prot, flags, fd] = args;
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        // more than user memory, and page_align would overflow
+        if length > STACK_TOP {
+            return Err(ENOMEM);
+        }
+        let length = page_align(length);
+        let addr = if flags & MAP_FIXED != 0 {
+            if addr & (PAGE_SIZE - 1) != 0 {
+                return Err(EINVAL);
+            }
+            addr
+        } else {
+            let addr = self.mmap_top;
+            if length > STACK_TOP - STACK_SIZE - addr {
+                return Err(ENOMEM);
+            }
+            self.mmap_top += length;
+            addr
+        };
+
+        let mut bytes = vec![0u8; length as usize];
+        if flags & MAP_ANONYMOUS == 0 {
+            match self.file(fd)? {
+                FileDescriptor::File(file) => {
+                    let position = file.stream_position().map_err(errno)?;
+                    file.seek(SeekFrom::Start(offset)).map_err(errno)?;
+                    let mut len = 0;
+                    while len < bytes.len() {
+                        match file.read(&mut bytes[len..]).map_err(errno)? {
+                            0 => break,
+                            count => len += count,
+                        }
+                    }
+                    file.seek(SeekFrom::Start(position)).map_err(errno)?;
+                }
+                _ => return Err(EINVAL),
+            }
+        }
+        write_bytes(bus, addr, &bytes)?;
+        Ok(addr)
+    }
+
+    // (seconds, nanoseconds) of a clock
+    fn clock(&self, clock: u32) -> (u64, u32) {
+        match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                (now.as_secs(), now.subsec_nanos())
+            }
+            _ => {
+                let elapsed = self.start.elapsed();
+                (elapsed.as_secs(), elapsed.subsec_nanos())
+            }
+        }
+    }
+
+    fn uname(&self, bus: &mut Bus, buf: u32) -> SyscallResult {
+        let fields = ["Linux", "simp", "5.10.0", "#1", "mips", "(none)"];
+        let mut bytes = Vec::new();
+        for field in fields.iter() {
+            let mut field = field.as_bytes().to_vec();
+            field.resize(65, 0);
+            bytes.extend(field);
+        }
+        write_bytes(bus, buf, &bytes)?;
+        Ok(0)
+    }
+
+    fn readlink(&self, bus: &mut Bus, path: u32, buf: u32, size: u32) -> SyscallResult {
+        let path = read_string(bus, path)?;
+        let target = if path == "/proc/self/exe" {
+            fs::canonicalize(&self.executable).map_err(errno)?
+        } else {
+            fs::read_link(path).map_err(errno)?
+        };
+        let target = target.to_string_lossy();
+        let len = target.len().min(size as usize);
+        write_bytes(bus, buf, &target.as_bytes()[..len])?;
+        Ok(len as u32)
+    }
+
+    fn getcwd(&self, bus: &mut Bus, buf: u32, size: u32) -> SyscallResult {
+        let cwd = env::current_dir().map_err(errno)?;
+        let mut bytes = cwd.to_string_lossy().as_bytes().to_vec();
+        bytes.push(0);
+        if bytes.len() > size as usize {
+            return Err(ERANGE);
+        }
+        write_bytes(bus, buf, &bytes)?;
+        Ok(bytes.len() as u32)
+    }
+
+    fn getrandom(&mut self, bus: &mut Bus, buf: u32, count: u32) -> SyscallResult {
+        let bytes: Vec<u8> = (0..count).map(|_| self.next_random() as u8).collect();
+        write_bytes(bus, buf, &bytes)?;
+        Ok(count)
+    }
+
+    fn dispatch(&mut self, context: &mut SyscallContext) -> SyscallResult {
+        let number = context.regs[2];
+        let (a0, a1, a2, a3) = (context.regs[4], context.regs[5], context.regs[6], context.regs[7]);
+        // arguments beyond the fourth are passed on the stack
+        let sp = context.regs[29];
+        let bus = &mut *context.bus;
+
+        match number {
+            SYS_READ => self.read(bus, a0, a1, a2),
+            SYS_WRITE => self.write(bus, a0, a1, a2),
+            SYS_READV => self.readv(bus, a0, a1, a2),
+            SYS_WRITEV => self.writev(bus, a0, a1, a2),
+            SYS_OPEN => self.open(bus, AT_FDCWD, a0, a1, a2),
+            SYS_OPENAT => self.open(bus, a0, a1, a2, a3),
+            SYS_CLOSE => self.close(a0),
+            SYS_LSEEK => {
+                let offset = self.seek(a0, a1 as i32 as i64, a2)?;
+                Ok(offset as u32)
+            }
+            SYS_LLSEEK => {
+                // offset_high, offset_low, result, whence
+                let whence = read_word(bus, sp.wrapping_add(16))?;
+                let offset = self.seek(a0, (((a1 as u64) << 32) | a2 as u64) as i64, whence)?;
+                write_words(bus, a3, &[offset as u32, (offset >> 32) as u32])?;
+                Ok(0)
+            }
+            SYS_IOCTL => Err(ENOTTY),
+            SYS_FSTAT => {
+                let stat = self.fstat(a0)?;
+                write_bytes(bus, a1, &stat.encode())?;
+                Ok(0)
+            }
+            SYS_FSTAT64 => {
+                let stat = self.fstat(a0)?;
+                write_bytes(bus, a1, &stat.encode64())?;
+                Ok(0)
+            }
+            SYS_STAT64 | SYS_LSTAT64 => {
+                let flags = if number == SYS_LSTAT64 { AT_SYMLINK_NOFOLLOW } else { 0 };
+                let stat = self.stat(bus, AT_FDCWD, a0, flags)?;
+                write_bytes(bus, a1, &stat.encode64())?;
+                Ok(0)
+            }
+            SYS_FSTATAT64 => {
+                let stat = self.stat(bus, a0, a1, a3)?;
+                write_bytes(bus, a2, &stat.encode64())?;
+                Ok(0)
+            }
+            SYS_READLINK => self.readlink(bus, a0, a1, a2),
+            SYS_GETCWD => self.getcwd(bus, a0, a1),
+            SYS_BRK => self.brk(bus, a0),
+            SYS_MMAP | SYS_MMAP2 => {
+                let fd = read_word(bus, sp.wrapping_add(16))?;
+                let offset = read_word(bus, sp.wrapping_add(20))? as u64;
+                // mmap2 passes the offset in 4096 byte units
+                let offset = if number == SYS_MMAP2 { offset * 4096 } else { offset };
+                self.mmap(bus, [a0, a1, a2, a3, fd], offset)
+            }
+            SYS_MUNMAP | SYS_MPROTECT | SYS_MADVISE => Ok(0),
+            SYS_UNAME => self.uname(bus, a0),
+            SYS_TIME => {
+                let (sec, _) = self.clock(CLOCK_REALTIME);
+                if a0 != 0 {
+                    write_words(bus, a0, &[sec as u32])?;
+                }
+                Ok(sec as u32)
+            }
+            SYS_GETTIMEOFDAY => {
+                let (sec, nsec) = self.clock(CLOCK_REALTIME);
+                if a0 != 0 {
+                    write_words(bus, a0, &[sec as u32, nsec / 1000])?;
+                }
+                Ok(0)
+            }
+            SYS_CLOCK_GETTIME => {
+                let (sec, nsec) = self.clock(a0);
+                write_words(bus, a1, &[sec as u32, nsec])?;
+                Ok(0)
+            }
+            SYS_CLOCK_GETTIME64 => {
+                let (sec, nsec) = self.clock(a0);
+                write_words(bus, a1, &[sec as u32, (sec >> 32) as u32, nsec, 0])?;
+                Ok(0)
+            }
+            SYS_GETRANDOM => self.getrandom(bus, a0, a1),
+            SYS_GETPID | SYS_GETTID | SYS_SET_TID_ADDRESS => Ok(std::process::id()),
+            SYS_GETUID | SYS_GETEUID => Ok(self.uid),
+            SYS_GETGID | SYS_GETEGID => Ok(self.gid),
+            SYS_SET_THREAD_AREA => {
+                *context.user_local = a0;
+                Ok(0)
+            }
+            // signals are never delivered
+            SYS_RT_SIGACTION | SYS_RT_SIGPROCMASK | SYS_SIGALTSTACK => Ok(0),
+            _ => Err(ENOSYS),
+        }
+    }
+}
+
+impl SyscallHandler for Linux {
+    fn syscall(&mut self, context: &mut SyscallContext) -> SyscallAction {
+        match context.regs[2] {
+            SYS_EXIT | SYS_EXIT_GROUP => return SyscallAction::Exit((context.regs[4] & 0xff) as i32),
+            _ => {}
+        }
+
+        // result in v0, a3 set on error
+        match self.dispatch(context) {
+            Ok(value) => {
+                context.regs[2] = value;
+                context.regs[7] = 0;
+            }
+            Err(errno) => {
+                if errno == ENOSYS {
+                    eprintln!("simp: unsupported syscall {} at {:#x}", context.regs[2], context.pc);
+                }
+                context.regs[2] = errno;
+                context.regs[7] = 1;
+            }
+        }
+        SyscallAction::Continue
+    }
+
+    // Like the kernel, read the TLS pointer for rdhwr $29 on cores before
+    // Release 2
+    fn reserved_instruction(&mut self, instruction: Instruction, context: &mut SyscallContext) -> SyscallAction {
+        match instruction {
+            Instruction::Rdhwr { rt, rd: 29 } => {
+                context.regs[rt] = *context.user_local;
+                SyscallAction::Continue
+            }
+            _ => SyscallAction::Exception,
+        }
+    }
+
+    // There is no kernel to handle exceptions, they kill the process with the
+    // signal Linux would send
+    fn exception(&mut self, exception: Exception) -> SyscallAction {
+        let signal = match exception {
+            Exception::AddressErrorLoad(_) | Exception::AddressErrorStore(_) => SIGBUS,
+            Exception::DataBusError { .. }
+            | Exception::TlbModified(_)
+            | Exception::TlbRefillLoad(_)
+            | Exception::TlbRefillStore(_)
+            | Exception::TlbInvalidLoad(_)
+            | Exception::TlbInvalidStore(_) => SIGSEGV,
+            Exception::Syscall => SIGSYS,
+            Exception::Breakpoint | Exception::Trap => SIGTRAP,
+            Exception::ReservedInstruction | Exception::CoprocessorUnusable(_) => SIGILL,
+            Exception::IntegerOverflow | Exception::FloatingPoint => SIGFPE,
+        };
+        eprintln!("simp: uncaught target signal {} ({:?})", signal, exception);
+        SyscallAction::Exit(128 + signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::*;
+
+    const ENTRY: u32 = 0x0040_0000;
+
+    // a static executable with one segment holding code at ENTRY
+    fn executable(code: &[u32]) -> Elf {
+        let offset = 0x1000u32;
+        let size = offset + 4 * code.len() as u32;
+        let mut data = vec![0x7f, b'E', b'L', b'F', 1, 1, 1];
+        data.resize(16, 0);
+        for half in [2u16, 8] {
+            data.extend(half.to_le_bytes());
+        }
+        for word in [1, ENTRY, 52, 0, 0] {
+            data.extend(u32::to_le_bytes(word));
+        }
+        for half in [52u16, 32, 1, 40, 0, 0] {
+            data.extend(half.to_le_bytes());
+        }
+        for word in [PT_LOAD, 0, ENTRY - offset, ENTRY - offset, size, size, 5, PAGE_SIZE] {
+            data.extend(u32::to_le_bytes(word));
+        }
+        data.resize(offset as usize, 0);
+        data.extend(code.iter().flat_map(|word| word.to_le_bytes()));
+        Elf::parse(data).unwrap()
+    }
+
+    fn process(args: &[&str], envs: &[&str]) -> (Cpu, Linux) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let envs: Vec<String> = envs.iter().map(|env| env.to_string()).collect();
+        let mut cpu = Cpu::new(vec![], IsaRevision::Release2);
+        cpu.trace = false;
+        let linux = Linux::load(&mut cpu, &executable(&[0]), &args, &envs).unwrap();
+        (cpu, linux)
+    }
+
+    // v0 and a3 after the syscall
+    fn syscall(cpu: &mut Cpu, linux: &mut Linux, number: u32, args: &[u32]) -> (u32, u32) {
+        cpu.regs[2] = number;
+        cpu.regs[4..4 + args.len()].copy_from_slice(args);
+        let mut context = SyscallContext {
+            regs: &mut cpu.regs,
+            hi: &mut cpu.hi,
+            lo: &mut cpu.lo,
+            user_local: &mut cpu.cp0.user_local,
+            bus: &mut cpu.bus,
+            pc: ENTRY,
+            code: 0,
+        };
+        assert_eq!(linux.syscall(&mut context), SyscallAction::Continue);
+        (cpu.regs[2], cpu.regs[7])
+    }
+
+    #[test]
+    fn stack_holds_argc_argv_envp_and_auxv() {
+        let (cpu, _) = process(&["prog", "foo"], &["A=1"]);
+        let bus = &cpu.bus;
+        let sp = cpu.regs[29];
+        assert_eq!(sp & 0xf, 0);
+        let word = |index: u32| read_word(bus, sp + 4 * index).unwrap();
+        let string = |index: u32| read_string(bus, word(index)).unwrap();
+
+        assert_eq!(word(0), 2);
+        assert_eq!((string(1), string(2), word(3)), (String::from("prog"), String::from("foo"), 0));
+        assert_eq!((string(4), word(5)), (String::from("A=1"), 0));
+
+        let mut auxv = Vec::new();
+        let mut index = 6;
+        loop {
+            let (key, value) = (word(index), word(index + 1));
+            auxv.push((key, value));
+            index += 2;
+            if key == AT_NULL {
+                break;
+            }
+        }
+        let entry = |key: u32| auxv.iter().find(|(k, _)| *k == key).map(|(_, value)| *value);
+        assert_eq!(entry(AT_PAGESZ), Some(PAGE_SIZE));
+        assert_eq!(entry(AT_ENTRY), Some(ENTRY));
+        assert_eq!(entry(AT_PHNUM), Some(1));
+        assert_eq!(entry(AT_EXECFN).map(|addr| read_string(bus, addr).unwrap()), Some(String::from("prog")));
+        // the random bytes sit between the table and the strings
+        let random = entry(AT_RANDOM).unwrap();
+        assert!(sp + 4 * index <= random && random + 16 <= word(1));
+        assert_eq!(auxv.last(), Some(&(AT_NULL, 0)));
+    }
+
+    #[test]
+    fn errors_return_errno_with_a3_set() {
+        let (mut cpu, mut linux) = process(&["prog"], &[]);
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_CLOSE, &[99]), (EBADF, 1));
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_WRITE, &[1, STACK_TOP - 2, 4]), (EFAULT, 1));
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_MMAP, &[0, 0, 0, MAP_ANONYMOUS]), (EINVAL, 1));
+        assert_eq!(syscall(&mut cpu, &mut linux, 4999, &[]), (ENOSYS, 1));
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_GETUID, &[]), (linux.uid, 0));
+    }
+
+    #[test]
+    fn brk_and_mmap_hand_out_zeroed_memory() {
+        let (mut cpu, mut linux) = process(&["prog"], &[]);
+        let (start, _) = syscall(&mut cpu, &mut linux, SYS_BRK, &[0]);
+        assert_eq!(start, page_align(ENTRY + 4));
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_BRK, &[start + 0x1000]), (start + 0x1000, 0));
+        // outside the heap the break does not move
+        assert_eq!(syscall(&mut cpu, &mut linux, SYS_BRK, &[MMAP_BASE + 1]), (start + 0x1000, 0));
+
+        cpu.bus.store(MMAP_BASE, 32, 0xdead_beef).unwrap();
+        let (first, a3) = syscall(&mut cpu, &mut linux, SYS_MMAP, &[0, 100, 3, MAP_ANONYMOUS]);
+        assert_eq!((first, a3), (MMAP_BASE, 0));
+        assert_eq!(read_word(&cpu.bus, first), Ok(0));
+        let (second, _) = syscall(&mut cpu, &mut linux, SYS_MMAP, &[0, 100, 3, MAP_ANONYMOUS]);
+        assert_eq!(second, first + PAGE_SIZE);
+    }
+
+    #[test]
+    fn uncaught_bus_errors_report_the_access() {
+        let (mut cpu, linux) = process(&["prog"], &[]);
+        cpu.set_syscall_handler(Box::new(linux));
+        // kernel mode, where kseg1 reaches past the end of memory
+        cpu.cp0.status &= !STATUS_UM;
+        cpu.regs[8] = 0xa800_0000;
+        cpu.pc = ENTRY + 4;
+        // lw t1, 0(t0)
+        assert_eq!(
+            cpu.execute(0x8d09_0000),
+            Err(CpuError::DataBus {
+                pc: ENTRY,
+                addr: 0xa800_0000,
+                error: BusError::Unmapped {
+                    addr: 0x0800_0000,
+                    size: 32
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn stat64_has_the_o32_layout() {
+        let stat = Stat {
+            dev: 1,
+            ino: 0x2_0000_0003,
+            mode: 0o100644,
+            nlink: 4,
+            uid: 5,
+            gid: 6,
+            rdev: 7,
+            size: 0x8_0000_0009,
+            atime: (10, 11),
+            mtime: (12, 13),
+            ctime: (14, 15),
+            blksize: 16,
+            blocks: 17,
+        };
+        let bytes = stat.encode64();
+        assert_eq!(bytes.len(), 104);
+        let word = |offset: usize| {
+            let field = &bytes[offset..offset + 4];
+            u32::from_le_bytes([field[0], field[1], field[2], field[3]])
+        };
+        assert_eq!(word(0), 1);
+        assert_eq!((word(16), word(20)), (3, 2));
+        assert_eq!((word(24), word(28), word(32), word(36), word(40)), (0o100644, 4, 5, 6, 7));
+        assert_eq!((word(56), word(60)), (9, 8));
+        assert_eq!((word(64), word(68), word(72), word(76), word(80), word(84)), (10, 11, 12, 13, 14, 15));
+        assert_eq!((word(88), word(96), word(100)), (16, 17, 0));
+    }
+}
@@ -1,44 +1,208 @@
+mod bench;
+mod block;
 mod bus;
+mod cp0;
+mod cp1;
 mod cpu;
+mod debug_info;
+mod decode;
+mod decode_cache;
+mod disasm;
+mod elf;
+mod error;
+mod exception;
+mod gdb;
+mod image;
+#[cfg(feature = "jit")]
+mod jit;
+mod linux;
 mod memory;
+mod monitor;
+mod syscall;
+mod tlb;
 
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::path::Path;
+use std::process;
 
+use crate::cp0::*;
 use crate::cpu::*;
+use crate::elf::*;
+use crate::error::*;
+use crate::image::*;
+use crate::linux::*;
+use crate::tlb::*;
+
+const USAGE: &str = "Usage: simp [--isa r1|r2] [--mmu none|tlb|fmt] [--tlb <entries>] [--linux] [--blocks | --jit] [--gdb <port> | --debug] <filename> [args...]\n       simp disas <filename>\n       simp bench <filename>";
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        panic!("Usage: simp <filename>");
+    if args.get(1).map(|arg| arg.as_str()) == Some("disas") {
+        let filename = args.get(2).expect(USAGE);
+        let mut binary = Vec::new();
+        File::open(filename)?.read_to_end(&mut binary)?;
+        return disasm::dump(filename, binary);
+    }
+
+    if args.get(1).map(|arg| arg.as_str()) == Some("bench") {
+        let filename = args.get(2).expect(USAGE);
+        let mut binary = Vec::new();
+        File::open(filename)?.read_to_end(&mut binary)?;
+        return bench::run(binary);
+    }
+
+    let mut filename = None;
+    let mut isa = IsaRevision::Release2;
+    let mut linux = false;
+    let mut gdb_port = None;
+    let mut debug = false;
+    let mut blocks = false;
+    let mut jit = false;
+    let mut tlb_entries = None;
+    let mut mmu_type = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--linux" => linux = true,
+            "--debug" => debug = true,
+            "--blocks" => blocks = true,
+            "--jit" => jit = true,
+            "--gdb" => {
+                gdb_port = match iter.next().map(|s| s.parse::<u16>()) {
+                    Some(Ok(port)) => Some(port),
+                    _ => panic!("{}", USAGE),
+                }
+            }
+            "--mmu" => {
+                mmu_type = match iter.next().map(|s| s.as_str()) {
+                    Some("none") => Some(CONFIG_MT_NONE),
+                    Some("tlb") => Some(CONFIG_MT_TLB),
+                    Some("fmt") => Some(CONFIG_MT_FMT),
+                    _ => panic!("{}", USAGE),
+                }
+            }
+            "--tlb" => {
+                tlb_entries = match iter.next().map(|s| s.parse::<usize>()) {
+                    Some(Ok(entries)) if (1..=MAX_TLB_ENTRIES).contains(&entries) => Some(entries),
+                    _ => panic!("{}", USAGE),
+                }
+            }
+            "--isa" => {
+                isa = match iter.next().map(|s| s.as_str()) {
+                    Some("r1") => IsaRevision::Release1,
+                    Some("r2") => IsaRevision::Release2,
+                    _ => panic!("{}", USAGE),
+                }
+            }
+            _ => {
+                filename = Some(arg);
+                break;
+            }
+        }
     }
-    let mut file = File::open(&args[1])?;
+    let filename = filename.expect(USAGE);
+    // the remaining arguments are passed to the guest program
+    let guest_args: Vec<String> = std::iter::once(filename.clone()).chain(iter.cloned()).collect();
+
+    let mut file = File::open(filename)?;
     let mut binary = Vec::new();
     file.read_to_end(&mut binary)?;
 
-    let mut cpu = Cpu::new(binary);
-
-    loop {
-        let inst = match cpu.fetch() {
-            Ok(inst) => inst,
-            Err(_) => break,
-        };
+    let mut cpu = if linux {
+        let elf = Elf::parse(binary)?;
+        let envs: Vec<String> = env::vars().map(|(key, value)| format!("{}={}", key, value)).collect();
+        let mut cpu = Cpu::new(vec![], isa);
+        let process = Linux::load(&mut cpu, &elf, &guest_args, &envs)?;
+        cpu.set_syscall_handler(Box::new(process));
+        // stdout belongs to the guest program
+        cpu.trace = false;
+        cpu
+    } else if Elf::is_elf(&binary) {
+        let elf = Elf::parse(binary)?;
+        let mut cpu = Cpu::new(vec![], isa);
+        elf.load(&mut cpu)?;
+        cpu
+    } else {
+        let extension = Path::new(filename).extension().and_then(|extension| extension.to_str());
+        match extension {
+            Some("hex") | Some("ihex") | Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => {
+                let text = String::from_utf8(binary).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let mut cpu = Cpu::new(vec![], isa);
+                if let Some("hex") | Some("ihex") = extension {
+                    load_ihex(&mut cpu.bus, &text)?;
+                } else {
+                    load_srec(&mut cpu.bus, &text)?;
+                }
+                cpu
+            }
+            _ => Cpu::new(binary, isa),
+        }
+    };
 
-        cpu.pc += 4;
+    if let Some(mt) = mmu_type {
+        cpu.cp0.set_mmu_type(mt);
+    }
+    if let Some(entries) = tlb_entries {
+        cpu.cp0.set_tlb_entries(entries);
+    }
 
-        match cpu.execute(inst) {
-            Ok(_) => {},
-            Err(_) => break,
+    // a gdb session ends with the program killed or exited, or detached and running on,
+    // the built-in monitor leaves the program where it stopped
+    let stopped = match gdb_port {
+        Some(port) => match gdb::serve(&mut cpu, port)? {
+            Some(stopped) => stopped,
+            None => run(&mut cpu, jit, blocks)?,
+        },
+        None if debug => monitor::run(&mut cpu)?,
+        None => run(&mut cpu, jit, blocks)?,
+    };
+    match stopped {
+        Ok(()) => cpu.dump_registers(),
+        Err(CpuError::Halt { status }) => process::exit(status),
+        Err(error) => {
+            cpu.dump_registers();
+            eprintln!("simp: {}", cpu.describe_error(&error));
+            process::exit(error.exit_code());
         }
+    }
+
+    Ok(())
+}
 
-        if cpu.pc == 0 {
-            break;
+// Run the program with the interpreter selected by --blocks and --jit
+fn run(cpu: &mut Cpu, jit: bool, blocks: bool) -> io::Result<Result<(), CpuError>> {
+    if jit {
+        #[cfg(feature = "jit")]
+        {
+            // compiled blocks are not traced
+            cpu.trace = false;
+            return jit::run(cpu);
         }
+        #[cfg(not(feature = "jit"))]
+        panic!("--jit needs simp built with --features jit");
     }
-    cpu.dump_registers();
+    Ok(if blocks { run_blocks(cpu) } else { run_decoded(cpu) })
+}
 
+// The fetch/execute loop, until pc 0 or an error
+fn run_decoded(cpu: &mut Cpu) -> Result<(), CpuError> {
+    while cpu.pc != 0 {
+        let decoded = cpu.fetch_decoded()?;
+        cpu.pc += 4;
+        cpu.execute_decoded(decoded)?;
+    }
+    Ok(())
+}
+
+// The fetch/execute loop block by block, with --blocks
+fn run_blocks(cpu: &mut Cpu) -> Result<(), CpuError> {
+    while cpu.pc != 0 {
+        let block = cpu.fetch_block()?;
+        cpu.execute_block(&block)?;
+    }
     Ok(())
 }
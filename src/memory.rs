@@ -1,7 +1,9 @@
 use crate::bus::*;
+use crate::error::*;
 
 pub const MEMORY_SIZE: u32 = 1024 * 1024 * 128;
 pub const BOOT_ROM_SIZE: u32 = 1024 * 1024 * 4;
+pub const USER_MEMORY_SIZE: u32 = 1024 * 1024 * 32;
 
 pub trait Memory {
     fn load8(&self, addr: u32) -> u32;
@@ -13,22 +15,23 @@ pub trait Memory {
 }
 
 impl<T: Memory> Device for T {
-    fn load(&self, addr: u32, size: u32) -> Result<u32, ()> {
+    fn load(&self, addr: u32, size: u32) -> Result<u32, BusError> {
         match size {
             8 => Ok(self.load8(addr)),
             16 => Ok(self.load16(addr)),
             32 => Ok(self.load32(addr)),
-            _ => Err(()),
+            _ => Err(BusError::InvalidWidth { addr, size }),
         }
     }
 
-    fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), ()> {
+    fn store(&mut self, addr: u32, size: u32, value: u32) -> Result<(), BusError> {
         match size {
-            8 => Ok(self.store8(addr, value)),
-            16 => Ok(self.store16(addr, value)),
-            32 => Ok(self.store32(addr, value)),
-            _ => Err(()),
+            8 => self.store8(addr, value),
+            16 => self.store16(addr, value),
+            32 => self.store32(addr, value),
+            _ => return Err(BusError::InvalidWidth { addr, size }),
         }
+        Ok(())
     }
 }
 
@@ -45,15 +48,15 @@ impl Memory for Dram {
 
     fn load16(&self, addr: u32) -> u32 {
         let index = addr as usize;
-        return (self.memory[index] as u32) | ((self.memory[index + 1] as u32) << 8);
+        (self.memory[index] as u32) | ((self.memory[index + 1] as u32) << 8)
     }
 
     fn load32(&self, addr: u32) -> u32 {
         let index = addr as usize;
-        return (self.memory[index] as u32)
+        (self.memory[index] as u32)
             | ((self.memory[index + 1] as u32) << 8)
             | ((self.memory[index + 2] as u32) << 16)
-            | ((self.memory[index + 3] as u32) << 24);
+            | ((self.memory[index + 3] as u32) << 24)
     }
 
     fn store8(&mut self, addr: u32, value: u32) {
@@ -98,15 +101,15 @@ impl Memory for Rom {
 
     fn load16(&self, addr: u32) -> u32 {
         let index = addr as usize;
-        return (self.memory[index] as u32) | ((self.memory[index + 1] as u32) << 8);
+        (self.memory[index] as u32) | ((self.memory[index + 1] as u32) << 8)
     }
 
     fn load32(&self, addr: u32) -> u32 {
         let index = addr as usize;
-        return (self.memory[index] as u32)
+        (self.memory[index] as u32)
             | ((self.memory[index + 1] as u32) << 8)
             | ((self.memory[index + 2] as u32) << 16)
-            | ((self.memory[index + 3] as u32) << 24);
+            | ((self.memory[index + 3] as u32) << 24)
     }
 
     fn store8(&mut self, _addr: u32, _value: u32) {
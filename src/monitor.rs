@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::prelude::*;
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::error::*;
+
+const HELP: &str = "\
+step [n]              execute n instructions (default 1)
+continue              run until a breakpoint, a watchpoint or the end of the program
+break [addr|symbol]   set a breakpoint, or list breakpoints and watchpoints
+delete <addr|symbol>  remove a breakpoint or watchpoint
+watch <addr> [len]    stop after a store to len bytes (default 4) at addr
+regs                  show the registers
+mem <addr> <len>      dump len bytes of memory at addr
+disas [addr] [n]      disassemble n instructions (default 8) at addr (default pc)
+set <reg> <value>     write a register: r0-r31, an ABI name, pc, hi or lo
+quit                  stop debugging";
+
+enum Stop {
+    Breakpoint,
+    Watch(Watchpoint),
+    // the program stopped the way the fetch/execute loop of main stops it,
+    // with the error unless it reached pc 0
+    Halted(Option<CpuError>),
+}
+
+struct Monitor {
+    breakpoints: BTreeSet<u32>,
+    halted: bool,
+    // the error the program halted with, unless it reached pc 0
+    stopped: Option<CpuError>,
+}
+
+// "0x" prefixed hex or decimal number, or a symbol of the loaded program
+fn parse_addr(cpu: &Cpu, arg: &str) -> Result<u32, String> {
+    parse_number(arg)
+        .or_else(|| cpu.debug_info.lookup(arg))
+        .ok_or_else(|| format!("bad address or unknown symbol {:?}", arg))
+}
+
+fn parse_number(arg: &str) -> Option<u32> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => arg.parse::<i64>().ok().map(|value| value as u32),
+    }
+}
+
+impl Monitor {
+    // Execute one instruction, like the fetch/execute loop of main
+    fn step(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        cpu.watch_hit = None;
+        let decoded = match cpu.fetch_decoded() {
+            Ok(decoded) => decoded,
+            Err(error) => return Some(Stop::Halted(Some(error))),
+        };
+        cpu.pc += 4;
+        if let Err(error) = cpu.execute_decoded(decoded) {
+            return Some(Stop::Halted(Some(error)));
+        }
+        if cpu.pc == 0 {
+            return Some(Stop::Halted(None));
+        }
+        cpu.watch_hit.map(Stop::Watch)
+    }
+
+    // Execute up to count instructions, or until a breakpoint when count is None
+    fn resume(&mut self, cpu: &mut Cpu, count: Option<u64>) -> Result<(), String> {
+        if self.halted {
+            return Err(String::from("the program is not running"));
+        }
+        let mut executed = 0u64;
+        let stop = loop {
+            // a breakpoint at the resume address has already been reported
+            if let Some(stop) = self.step(cpu) {
+                break Some(stop);
+            }
+            executed += 1;
+            if self.breakpoints.contains(&cpu.pc) {
+                break Some(Stop::Breakpoint);
+            }
+            if count == Some(executed) {
+                break None;
+            }
+        };
+        match stop {
+            Some(Stop::Breakpoint) => println!("breakpoint at {}", cpu.describe(cpu.pc)),
+            Some(Stop::Watch(watchpoint)) => {
+                println!("watchpoint {:#x} hit, pc={}", watchpoint.addr, cpu.describe(cpu.pc))
+            }
+            Some(Stop::Halted(error)) => {
+                self.halted = true;
+                self.stopped = error;
+                match error {
+                    Some(CpuError::Halt { status }) => println!("program exited with status {}", status),
+                    Some(error) => println!("program stopped: {}", cpu.describe_error(&error)),
+                    None => println!("program stopped at {}", cpu.describe(cpu.pc)),
+                }
+            }
+            None => println!("pc={}", cpu.describe(cpu.pc)),
+        }
+        Ok(())
+    }
+
+    fn register_index(cpu: &Cpu, name: &str) -> Option<usize> {
+        if let Some(index) = ABI_NAMES.iter().position(|abi| *abi == name) {
+            return Some(index);
+        }
+        let number = name.strip_prefix('r').or_else(|| name.strip_prefix('$')).unwrap_or(name);
+        match number.parse::<usize>() {
+            Ok(index) if index < cpu.regs.len() => Some(index),
+            _ => None,
+        }
+    }
+
+    fn set_register(cpu: &mut Cpu, name: &str, value: u32) -> Result<(), String> {
+        match name {
+            "pc" => cpu.pc = value,
+            "hi" => cpu.hi = value,
+            "lo" => cpu.lo = value,
+            _ => match Self::register_index(cpu, name) {
+                // r0 is hardwired to zero
+                Some(0) => return Err(String::from("r0 is read only")),
+                Some(index) => cpu.regs[index] = value,
+                None => return Err(format!("unknown register {:?}", name)),
+            },
+        }
+        Ok(())
+    }
+
+    fn dump_memory(cpu: &Cpu, addr: u32, len: u32) -> Result<(), String> {
+        for line in (0..len).step_by(16) {
+            let line_addr = addr.wrapping_add(line);
+            let mut bytes = Vec::new();
+            for i in line..len.min(line.saturating_add(16)) {
+                let byte_addr = addr.wrapping_add(i);
+                let byte = cpu.peek(byte_addr).ok_or_else(|| format!("cannot access memory at {:#x}", byte_addr))?;
+                bytes.push(byte);
+            }
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            println!("{:08x}  {:<47}  {}", line_addr, hex.join(" "), ascii);
+        }
+        Ok(())
+    }
+
+    fn disassemble(cpu: &Cpu, addr: u32, count: u32) -> Result<(), String> {
+        for i in 0..count {
+            let inst_addr = addr.wrapping_add(i.wrapping_mul(4));
+            let mut word = [0u8; 4];
+            for (j, byte) in word.iter_mut().enumerate() {
+                let byte_addr = inst_addr.wrapping_add(j as u32);
+                *byte = cpu.peek(byte_addr).ok_or_else(|| format!("cannot access memory at {:#x}", byte_addr))?;
+            }
+            let inst = u32::from_le_bytes(word);
+            let marker = if inst_addr == cpu.pc { "=>" } else { "  " };
+            let text = disassemble(inst, inst_addr, &cpu.debug_info).replace('\t', " ");
+            println!("{} {}: {:08x}  {}", marker, cpu.describe(inst_addr), inst, text);
+        }
+        Ok(())
+    }
+
+    fn list(&self, cpu: &Cpu) {
+        for addr in self.breakpoints.iter() {
+            println!("breakpoint {}", cpu.describe(*addr));
+        }
+        for watchpoint in cpu.watchpoints.iter() {
+            println!("watchpoint {} len {}", cpu.describe(watchpoint.addr), watchpoint.len);
+        }
+    }
+
+    // Run one command line, returns false on quit
+    fn command(&mut self, cpu: &mut Cpu, line: &str) -> Result<bool, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match args.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+        let arg = |index: usize| args.get(index).copied();
+        match command {
+            "step" | "s" => {
+                let count = match arg(0) {
+                    Some(count) => parse_number(count).ok_or_else(|| format!("bad count {:?}", count))?,
+                    None => 1,
+                };
+                self.resume(cpu, Some(count.max(1) as u64))?;
+            }
+            "continue" | "c" => self.resume(cpu, None)?,
+            "break" | "b" => match arg(0) {
+                Some(target) => {
+                    let addr = parse_addr(cpu, target)?;
+                    self.breakpoints.insert(addr);
+                    println!("breakpoint {}", cpu.describe(addr));
+                }
+                None => self.list(cpu),
+            },
+            "delete" | "d" => {
+                let addr = parse_addr(cpu, arg(0).ok_or("delete needs an address")?)?;
+                let watchpoints = cpu.watchpoints.len();
+                cpu.watchpoints.retain(|watchpoint| watchpoint.addr != addr);
+                if !self.breakpoints.remove(&addr) && watchpoints == cpu.watchpoints.len() {
+                    return Err(format!("no breakpoint or watchpoint at {:#x}", addr));
+                }
+            }
+            "watch" | "w" => {
+                let addr = parse_addr(cpu, arg(0).ok_or("watch needs an address")?)?;
+                let len = match arg(1) {
+                    Some(len) => parse_number(len).ok_or_else(|| format!("bad length {:?}", len))?,
+                    None => 4,
+                };
+                cpu.watchpoints.push(Watchpoint {
+                    addr,
+                    len,
+                    kind: WatchKind::Write,
+                });
+                println!("watchpoint {} len {}", cpu.describe(addr), len);
+            }
+            "regs" | "r" => {
+                println!("pc={} hi={:#x} lo={:#x}", cpu.describe(cpu.pc), cpu.hi, cpu.lo);
+                cpu.dump_registers();
+            }
+            "mem" | "x" => {
+                let addr = parse_addr(cpu, arg(0).ok_or("mem needs an address")?)?;
+                let len = match arg(1) {
+                    Some(len) => parse_number(len).ok_or_else(|| format!("bad length {:?}", len))?,
+                    None => 64,
+                };
+                Self::dump_memory(cpu, addr, len)?;
+            }
+            "disas" => {
+                let addr = match arg(0) {
+                    Some(target) => parse_addr(cpu, target)?,
+                    None => cpu.pc,
+                };
+                let count = match arg(1) {
+                    Some(count) => parse_number(count).ok_or_else(|| format!("bad count {:?}", count))?,
+                    None => 8,
+                };
+                Self::disassemble(cpu, addr, count)?;
+            }
+            "set" => {
+                let (name, value) = match (arg(0), arg(1)) {
+                    (Some(name), Some(value)) => (name, value),
+                    _ => return Err(String::from("set needs a register and a value")),
+                };
+                let value = parse_addr(cpu, value)?;
+                Self::set_register(cpu, name, value)?;
+            }
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(false),
+            _ => return Err(format!("unknown command {:?}, try help", command)),
+        }
+        Ok(true)
+    }
+}
+
+// Read commands from stdin until quit or end of input. An empty line repeats
+// the previous command. Returns the error the program halted with.
+pub fn run(cpu: &mut Cpu) -> io::Result<Result<(), CpuError>> {
+    let mut monitor = Monitor {
+        breakpoints: BTreeSet::new(),
+        halted: false,
+        stopped: None,
+    };
+    let stopped = |monitor: &Monitor| Ok(monitor.stopped.map_or(Ok(()), Err));
+    println!("pc={}", cpu.describe(cpu.pc));
+    let stdin = io::stdin();
+    let mut previous = String::new();
+    loop {
+        print!("(simp) ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return stopped(&monitor);
+        }
+        if line.trim().is_empty() {
+            line = previous.clone();
+        } else {
+            previous = line.clone();
+        }
+        match monitor.command(cpu, &line) {
+            Ok(true) => {}
+            Ok(false) => return stopped(&monitor),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::bus::*;
+use crate::decode::*;
+use crate::exception::*;
+
+// State handed to a syscall handler
+#[allow(dead_code)]
+pub struct SyscallContext<'a> {
+    pub regs: &'a mut [u32; 32],
+    pub hi: &'a mut u32,
+    pub lo: &'a mut u32,
+    // CP0 UserLocal, read by rdhwr $29
+    pub user_local: &'a mut u32,
+    pub bus: &'a mut Bus,
+    // address of the syscall or emulated instruction
+    pub pc: u32,
+    // code field of the syscall instruction, 0 for an emulated one
+    pub code: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallAction {
+    // raise the System Call exception
+    Exception,
+    // handled on the host, continue with the next instruction
+    Continue,
+    // stop the emulation with an exit status
+    Exit(i32),
+}
+
+pub trait SyscallHandler {
+    fn syscall(&mut self, context: &mut SyscallContext) -> SyscallAction;
+
+    // Called for an instruction the CPU does not implement, before it raises
+    // Reserved Instruction. Continue completes it as emulated.
+    fn reserved_instruction(&mut self, _instruction: Instruction, _context: &mut SyscallContext) -> SyscallAction {
+        SyscallAction::Exception
+    }
+
+    // Called before any other exception enters the CP0 exception handler.
+    // Continue is treated like Exception.
+    fn exception(&mut self, _exception: Exception) -> SyscallAction {
+        SyscallAction::Exception
+    }
+}
+
+// Bare-metal mode: syscall vectors to the CP0 exception handler
+pub struct ExceptionSyscall;
+
+impl SyscallHandler for ExceptionSyscall {
+    fn syscall(&mut self, _context: &mut SyscallContext) -> SyscallAction {
+        SyscallAction::Exception
+    }
+}
@@ -0,0 +1,124 @@
+use crate::cpu::*;
+
+// default number of JTLB entries, like the 4Kc
+pub const TLB_ENTRIES: usize = 16;
+// Config1.MMUSize holds entries - 1 in 6 bits
+pub const MAX_TLB_ENTRIES: usize = 64;
+
+pub const ENTRY_HI_VPN2: u32 = 0xffff_e000;
+pub const ENTRY_HI_ASID: u32 = 0x0000_00ff;
+// PFN, C, D, V, G
+pub const ENTRY_LO_WRITABLE: u32 = 0x03ff_ffff;
+pub const ENTRY_LO_PFN_SHIFT: u32 = 6;
+pub const ENTRY_LO_D: u32 = 1 << 2;
+pub const ENTRY_LO_V: u32 = 1 << 1;
+pub const ENTRY_LO_G: u32 = 1 << 0;
+// 4 KiB up to 256 MiB pages
+pub const PAGE_MASK_WRITABLE: u32 = 0x1fff_e000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TlbEntry {
+    pub page_mask: u32,
+    // VPN2 and ASID
+    pub entry_hi: u32,
+    // even and odd page, both with the G bit of the entry
+    pub entry_lo0: u32,
+    pub entry_lo1: u32,
+}
+
+impl TlbEntry {
+    fn global(&self) -> bool {
+        self.entry_lo0 & self.entry_lo1 & ENTRY_LO_G != 0
+    }
+
+    fn matches(&self, entry_hi: u32) -> bool {
+        let vpn2_mask = ENTRY_HI_VPN2 & !self.page_mask;
+        (self.entry_hi ^ entry_hi) & vpn2_mask == 0
+            && (self.global() || (self.entry_hi ^ entry_hi) & ENTRY_HI_ASID == 0)
+    }
+
+    // EntryLo of the page holding addr and the mask of the offset in it
+    fn page(&self, addr: u32) -> (u32, u32) {
+        let odd = ((self.page_mask | 0x1fff) + 1) >> 1;
+        let entry_lo = if addr & odd == 0 { self.entry_lo0 } else { self.entry_lo1 };
+        (entry_lo, odd - 1)
+    }
+}
+
+// Result of a TLB lookup that did not translate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbMiss {
+    // no entry matches, handled by the refill vector
+    Refill,
+    // the page of the matching entry is not valid
+    Invalid,
+    // store to a valid page that is not dirty
+    Modified,
+}
+
+// Software managed joint TLB, each entry maps an even and odd page pair
+pub struct Tlb {
+    pub entries: Vec<TlbEntry>,
+}
+
+impl Tlb {
+    // Like Linux local_flush_tlb_all, every entry maps its own kseg0 page
+    // pair, which is never translated, so that nothing matches after reset
+    pub fn new(entries: usize) -> Self {
+        Self {
+            entries: (0..entries as u32)
+                .map(|index| TlbEntry {
+                    entry_hi: KSEG0_BASE + index * 0x2000,
+                    ..TlbEntry::default()
+                })
+                .collect(),
+        }
+    }
+
+    // Index of the entry mapping the VPN2 and ASID of entry_hi
+    pub fn probe(&self, entry_hi: u32) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.matches(entry_hi))
+    }
+
+    // Physical address of addr in the address space asid
+    pub fn translate(&self, addr: u32, asid: u32, store: bool) -> Result<u32, TlbMiss> {
+        let index = self.probe((addr & ENTRY_HI_VPN2) | asid).ok_or(TlbMiss::Refill)?;
+        let (entry_lo, offset_mask) = self.entries[index].page(addr);
+        if entry_lo & ENTRY_LO_V == 0 {
+            return Err(TlbMiss::Invalid);
+        }
+        if store && entry_lo & ENTRY_LO_D == 0 {
+            return Err(TlbMiss::Modified);
+        }
+        let frame = (entry_lo >> ENTRY_LO_PFN_SHIFT) << 12;
+        Ok((frame & !offset_mask) | (addr & offset_mask))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_entries_match_no_mapped_address() {
+        let tlb = Tlb::new(MAX_TLB_ENTRIES);
+        for addr in [0, 0x0040_0000, 0x7fff_f000, 0xc000_0000, 0xffff_f000] {
+            assert_eq!(tlb.translate(addr, 0, false), Err(TlbMiss::Refill), "{:#x}", addr);
+        }
+    }
+
+    #[test]
+    fn written_entries_translate() {
+        let mut tlb = Tlb::new(TLB_ENTRIES);
+        tlb.entries[3] = TlbEntry {
+            page_mask: 0,
+            entry_hi: 0x0040_0000 | 5,
+            entry_lo0: 0x100 << ENTRY_LO_PFN_SHIFT | ENTRY_LO_V,
+            entry_lo1: 0x101 << ENTRY_LO_PFN_SHIFT | ENTRY_LO_V | ENTRY_LO_D,
+        };
+        assert_eq!(tlb.translate(0x0040_0123, 5, false), Ok(0x0010_0123));
+        assert_eq!(tlb.translate(0x0040_1123, 5, true), Ok(0x0010_1123));
+        assert_eq!(tlb.translate(0x0040_0123, 5, true), Err(TlbMiss::Modified));
+        assert_eq!(tlb.translate(0x0040_0123, 6, false), Err(TlbMiss::Refill));
+    }
+}